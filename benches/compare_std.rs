@@ -0,0 +1,94 @@
+//! Measures insertion, lookup, range scan and bulk load against `std::collections::BTreeSet`,
+//! for integer keys and for string keys, which are more expensive to compare and move.
+//! Run with `cargo bench --bench compare_std`.
+
+use rusty_btree::btree::BTree;
+use std::collections::BTreeSet;
+use std::hint::black_box;
+use std::time::Instant;
+
+const KEY_COUNT: u64 = 200_000;
+const LOOKUP_COUNT: u64 = 400_000;
+const RANGE_COUNT: u64 = 20_000;
+// The number of keys each range scan visits.
+const RANGE_LENGTH: usize = 100;
+
+// Scatters consecutive integers over the whole key range, so operations do not hit the same nodes.
+fn scatter(i: u64) -> u64 {
+    i.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+}
+
+fn int_key(i: u64) -> u64 {
+    scatter(i)
+}
+
+fn string_key(i: u64) -> String {
+    format!("{:032x}", scatter(i))
+}
+
+// Runs `f` `count` times, printing the time each run took on average.
+fn measure(name: &str, count: u64, mut f: impl FnMut(u64)) {
+    let start = Instant::now();
+    for i in 0..count {
+        f(i);
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "  {:<24} {:>8.1} ns/op",
+        name,
+        elapsed.as_nanos() as f64 / count as f64
+    );
+}
+
+fn compare<K>(key_name: &str, key: fn(u64) -> K)
+where
+    K: Ord + Clone,
+{
+    let mut sorted: Vec<K> = (0..KEY_COUNT).map(key).collect();
+    sorted.sort_unstable();
+
+    println!("{} keys, std::collections::BTreeSet", key_name);
+    let mut set = BTreeSet::new();
+    measure("insert", KEY_COUNT, |i| {
+        set.insert(key(i));
+    });
+    // Every other lookup misses.
+    measure("lookup", LOOKUP_COUNT, |i| {
+        black_box(set.contains(&key(i / 2 + i % 2 * KEY_COUNT)));
+    });
+    measure("range scan", RANGE_COUNT, |i| {
+        black_box(set.range(key(i)..).take(RANGE_LENGTH).count());
+    });
+    measure("bulk load", 1, |_| {
+        black_box(sorted.iter().cloned().collect::<BTreeSet<_>>());
+    });
+
+    for &order in &[4, 8, 16, 64] {
+        println!("{} keys, BTree of order {}", key_name, order);
+        let mut tree = BTree::new(order);
+        measure("insert", KEY_COUNT, |i| {
+            tree.insert(key(i));
+        });
+        measure("lookup", LOOKUP_COUNT, |i| {
+            black_box(tree.get(&key(i / 2 + i % 2 * KEY_COUNT)));
+        });
+        measure("range scan", RANGE_COUNT, |i| {
+            let mut cursor = tree.cursor();
+            cursor.seek(&key(i));
+            let mut visited = 0;
+            while cursor.key().is_some() && visited < RANGE_LENGTH {
+                cursor.next();
+                visited += 1;
+            }
+            black_box(visited);
+        });
+        measure("bulk load", 1, |_| {
+            black_box(BTree::from_sorted_iter(sorted.iter().cloned(), order));
+        });
+    }
+}
+
+fn main() {
+    compare("u64", int_key);
+    compare("string", string_key);
+}
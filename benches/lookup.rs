@@ -0,0 +1,55 @@
+//! Measures point lookups on trees which fit in the cache and on ones which do not.
+//! Small trees show the cost of searching within nodes, which decides `LINEAR_SEARCH_ORDER`
+//! of comparators.
+//! Run with `cargo bench --bench lookup`, adding `--features simd` to measure `SimdComparator`.
+
+use rusty_btree::btree::BTree;
+#[cfg(feature = "simd")]
+use rusty_btree::compare::SimdComparator;
+use rusty_btree::compare::{Comparator, OrdComparator};
+use std::hint::black_box;
+use std::time::Instant;
+
+const LOOKUP_COUNT: u64 = 2_000_000;
+
+// Scatters consecutive integers over the whole key range, so lookups do not hit the same nodes.
+fn scatter(i: u64) -> u64 {
+    i.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+}
+
+fn main() {
+    for &key_count in &[10_000, 1_000_000] {
+        for &order in &[4, 8, 12, 16, 32, 64, 128] {
+            lookup("ord", key_count, order, OrdComparator);
+            #[cfg(feature = "simd")]
+            lookup("simd", key_count, order, SimdComparator);
+        }
+    }
+}
+
+fn lookup<C: Comparator<u64>>(name: &str, key_count: u64, order: usize, cmp: C) {
+    let mut tree = BTree::with_comparator(order, cmp);
+    tree.insert_many((0..key_count).map(scatter));
+    let start = Instant::now();
+    let mut found = 0;
+    for i in 0..LOOKUP_COUNT {
+        // Every other lookup misses.
+        let key = if i % 2 == 0 {
+            scatter(i / 2 % key_count)
+        } else {
+            scatter(i) + 1
+        };
+        if tree.get(black_box(&key)).is_some() {
+            found += 1;
+        }
+    }
+    let elapsed = start.elapsed();
+    black_box(found);
+    println!(
+        "{:>4}, {:>7} keys, order {:>3}: {:>6.1} ns/lookup",
+        name,
+        key_count,
+        order,
+        elapsed.as_nanos() as f64 / LOOKUP_COUNT as f64
+    );
+}
@@ -0,0 +1,282 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::mem;
+
+/// This structure represents a B-Tree whose order is fixed at compile time.
+/// Keys and children of a node are stored in inline arrays sized by `ORDER`, so a node
+/// needs a single allocation regardless of how many keys it holds.
+///
+/// A full node is split before an insertion descends into it, which requires `ORDER`
+/// to be even so that both halves get the same number of keys.
+#[derive(Clone, Debug)]
+pub struct ArrayBTree<T, const ORDER: usize> {
+    root: Box<ArrayNode<T, ORDER>>,
+    len: usize,
+}
+
+#[derive(Clone, Debug)]
+struct ArrayNode<T, const ORDER: usize> {
+    // The number of keys in the node. Only `keys[..len]` and `children[..=len]` are occupied.
+    len: usize,
+    // Keys of a node. At most `ORDER - 1` of them are used.
+    keys: [Option<T>; ORDER],
+    // Child nodes, all `None` for a leaf.
+    children: [Option<Box<ArrayNode<T, ORDER>>>; ORDER],
+}
+
+impl<T, const ORDER: usize> ArrayBTree<T, ORDER>
+where
+    T: Ord,
+{
+    pub fn new() -> Self {
+        const {
+            assert!(
+                ORDER >= 4 && ORDER.is_multiple_of(2),
+                "ORDER must be an even number of at least 4"
+            )
+        };
+        Self {
+            root: Box::new(ArrayNode::new()),
+            len: 0,
+        }
+    }
+
+    /// Returns the number of keys in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Search the tree for a given key, returning the element stored in the tree.
+    pub fn get(&self, key: &T) -> Option<&T> {
+        let mut node = &*self.root;
+        loop {
+            match node.search(key) {
+                Ok(index) => return node.keys[index].as_ref(),
+                Err(_) if node.is_leaf() => return None,
+                Err(index) => node = node.children[index].as_deref().unwrap(),
+            }
+        }
+    }
+
+    pub fn contains(&self, key: &T) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Insert a key, returning whether it was not present yet.
+    pub fn insert(&mut self, key: T) -> bool {
+        if self.root.is_full() {
+            let old_root = mem::replace(&mut *self.root, ArrayNode::new());
+            self.root.children[0] = Some(Box::new(old_root));
+            self.root.split_children(0);
+        }
+        let is_inserted = self.root.insert(key);
+        if is_inserted {
+            self.len += 1;
+        }
+        is_inserted
+    }
+
+    /// Returns an iterator over keys in ascending order.
+    pub fn iter(&self) -> ArrayIter<'_, T, ORDER> {
+        let mut iter = ArrayIter { stack: vec![] };
+        iter.push_leftmost_path(&self.root);
+        iter
+    }
+}
+
+impl<T, const ORDER: usize> Default for ArrayBTree<T, ORDER>
+where
+    T: Ord,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const ORDER: usize> ArrayNode<T, ORDER>
+where
+    T: Ord,
+{
+    fn new() -> Self {
+        Self {
+            len: 0,
+            keys: core::array::from_fn(|_| None),
+            children: core::array::from_fn(|_| None),
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children[0].is_none()
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == ORDER - 1
+    }
+
+    fn key(&self, index: usize) -> &T {
+        self.keys[index].as_ref().unwrap()
+    }
+
+    fn search(&self, key: &T) -> Result<usize, usize> {
+        self.keys[..self.len].binary_search_by(|probe| probe.as_ref().unwrap().cmp(key))
+    }
+
+    /// Insert a key into the subtree of a node which is not full.
+    /// Full children are split before descending into them.
+    fn insert(&mut self, key: T) -> bool {
+        let mut index = match self.search(&key) {
+            Ok(_) => return false,
+            Err(index) => index,
+        };
+        if self.is_leaf() {
+            self.keys[self.len] = Some(key);
+            self.keys[index..=self.len].rotate_right(1);
+            self.len += 1;
+            return true;
+        }
+        if self.children[index].as_ref().unwrap().is_full() {
+            self.split_children(index);
+            match key.cmp(self.key(index)) {
+                Ordering::Less => {}
+                Ordering::Equal => return false,
+                Ordering::Greater => index += 1,
+            }
+        }
+        self.children[index].as_mut().unwrap().insert(key)
+    }
+
+    /// Split a full child into two halves and move its median key up to this node.
+    fn split_children(&mut self, index: usize) {
+        let half = ORDER / 2;
+        let child = self.children[index].as_mut().unwrap();
+        let mut right_child = Box::new(ArrayNode::new());
+        for i in 0..half - 1 {
+            right_child.keys[i] = child.keys[half + i].take();
+        }
+        if !child.is_leaf() {
+            for i in 0..half {
+                right_child.children[i] = child.children[half + i].take();
+            }
+        }
+        right_child.len = half - 1;
+        let median = child.keys[half - 1].take();
+        child.len = half - 1;
+
+        self.keys[self.len] = median;
+        self.keys[index..=self.len].rotate_right(1);
+        self.children[self.len + 1] = Some(right_child);
+        self.children[index + 1..=self.len + 1].rotate_right(1);
+        self.len += 1;
+    }
+}
+
+/// An iterator over keys of an `ArrayBTree` in ascending order.
+pub struct ArrayIter<'a, T, const ORDER: usize> {
+    // Nodes on the path to the next key, paired with the index of the next key to yield in each.
+    stack: Vec<(&'a ArrayNode<T, ORDER>, usize)>,
+}
+
+impl<'a, T, const ORDER: usize> ArrayIter<'a, T, ORDER>
+where
+    T: Ord,
+{
+    fn push_leftmost_path(&mut self, mut node: &'a ArrayNode<T, ORDER>) {
+        loop {
+            self.stack.push((node, 0));
+            match node.children[0].as_deref() {
+                Some(child) => node = child,
+                None => return,
+            }
+        }
+    }
+}
+
+impl<'a, T, const ORDER: usize> Iterator for ArrayIter<'a, T, ORDER>
+where
+    T: Ord,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, index)) = self.stack.pop() {
+            if index < node.len {
+                self.stack.push((node, index + 1));
+                if let Some(child) = node.children[index + 1].as_deref() {
+                    self.push_leftmost_path(child);
+                }
+                return Some(node.key(index));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::array_btree::{ArrayBTree, ArrayNode};
+    use rand::Rng;
+    use std::collections::BTreeSet;
+
+    // Asserts given subtree is valid and returns its height.
+    fn is_valid_subtree<T: Ord, const ORDER: usize>(
+        node: &ArrayNode<T, ORDER>,
+        is_root: bool,
+    ) -> usize {
+        assert!(node.len < ORDER);
+        if !is_root {
+            assert!(node.len + 1 >= ORDER / 2);
+        }
+        assert!(node.keys[..node.len].iter().all(Option::is_some));
+        assert!(node.keys[node.len..].iter().all(Option::is_none));
+        assert!(node.keys[..node.len]
+            .windows(2)
+            .all(|pair| pair[0] < pair[1]));
+        if node.is_leaf() {
+            assert!(node.children.iter().all(Option::is_none));
+            return 1;
+        }
+        assert!(node.children[node.len + 1..].iter().all(Option::is_none));
+        let heights: Vec<usize> = node.children[..=node.len]
+            .iter()
+            .map(|child| is_valid_subtree(child.as_deref().unwrap(), false))
+            .collect();
+        // All leaves must be at the same depth.
+        assert!(heights.iter().all(|&height| height == heights[0]));
+        heights[0] + 1
+    }
+
+    #[test]
+    fn insert_random_keys() {
+        let mut rng = rand::thread_rng();
+        let mut tree: ArrayBTree<u32, 4> = ArrayBTree::new();
+        let mut expected = BTreeSet::new();
+        for _ in 0..1000 {
+            let key = rng.gen_range(0, 500);
+            assert_eq!(tree.insert(key), expected.insert(key));
+        }
+        is_valid_subtree(&tree.root, true);
+        assert_eq!(tree.len(), expected.len());
+        assert!(tree.iter().eq(expected.iter()));
+        for key in 0..500 {
+            assert_eq!(tree.contains(&key), expected.contains(&key));
+        }
+    }
+
+    #[test]
+    fn wide_nodes() {
+        let mut tree: ArrayBTree<u32, 16> = ArrayBTree::new();
+        for key in (0..1000).rev() {
+            tree.insert(key);
+        }
+        is_valid_subtree(&tree.root, true);
+        assert!(tree.iter().copied().eq(0..1000));
+        assert_eq!(tree.get(&999), Some(&999));
+        assert_eq!(tree.get(&1000), None);
+    }
+}
@@ -0,0 +1,299 @@
+//! A key-value store kept in a file, for trying out the trees from a shell.
+//! `inspect` opens an interactive shell which walks the nodes of the tree.
+//! The file holds one entry per line in ascending order of keys, with a tab between the key
+//! and the value. A missing file is treated as an empty store.
+
+use rusty_btree::bplus_tree::{BPlusTree, NodeRef};
+use rusty_btree::snapshot::write_atomically;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::ops::Bound;
+use std::path::Path;
+use std::process;
+
+const ORDER: usize = 32;
+
+type Tree = BPlusTree<String, String>;
+
+const USAGE: &str = "usage: rusty_bree <file> <command>
+
+commands:
+    put <key> <value>     associate a value with a key
+    get <key>             print the value associated with a key
+    delete <key>          remove a key
+    scan [<start> [<end>]]
+                          print entries whose keys are at least <start> and less than <end>
+    inspect               walk the nodes of the tree interactively";
+
+const INSPECT_HELP: &str = "commands:
+    show            print the current node
+    child <index>   move to a child of the current node
+    up              move back to the parent
+    root            move back to the root
+    stats           print how many nodes and keys each level has and how full they are
+    quit            leave the shell";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(message) = run(&args) {
+        eprintln!("rusty_bree: {}", message);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (path, command, operands) = match args {
+        [path, command, operands @ ..] => (Path::new(path), command.as_str(), operands),
+        _ => return Err(USAGE.to_string()),
+    };
+    let mut tree =
+        load(path).map_err(|error| format!("cannot read {}: {}", path.display(), error))?;
+    match (command, operands) {
+        ("put", [key, value]) => {
+            check_field(key, "key")?;
+            check_field(value, "value")?;
+            tree.insert(key.clone(), value.clone());
+        }
+        ("get", [key]) => {
+            let value = tree
+                .get(key)
+                .ok_or_else(|| format!("key {:?} not found", key))?;
+            println!("{}", value);
+            return Ok(());
+        }
+        ("delete", [key]) => {
+            tree.remove(key)
+                .ok_or_else(|| format!("key {:?} not found", key))?;
+        }
+        ("scan", bounds) if bounds.len() <= 2 => {
+            let start = bounds.first().map_or(Bound::Unbounded, Bound::Included);
+            let end = bounds.get(1).map_or(Bound::Unbounded, Bound::Excluded);
+            if let (Bound::Included(start), Bound::Excluded(end)) = (start, end) {
+                if start > end {
+                    return Err("scan start is greater than scan end".to_string());
+                }
+            }
+            let stdout = io::stdout();
+            let mut out = BufWriter::new(stdout.lock());
+            for (key, value) in tree.range((start, end)) {
+                writeln!(out, "{}\t{}", key, value).map_err(|error| error.to_string())?;
+            }
+            return out.flush().map_err(|error| error.to_string());
+        }
+        ("inspect", []) => {
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+            return inspect(&tree, stdin.lock(), stdout.lock()).map_err(|error| error.to_string());
+        }
+        _ => return Err(USAGE.to_string()),
+    }
+    store(path, &tree).map_err(|error| format!("cannot write {}: {}", path.display(), error))
+}
+
+/// Reject keys and values which would break the line format of the file.
+fn check_field(field: &str, name: &str) -> Result<(), String> {
+    if field.contains(['\t', '\n', '\r']) {
+        return Err(format!("{} must not contain tabs or line breaks", name));
+    }
+    Ok(())
+}
+
+/// Walk the nodes of a tree by commands read from `input`, starting at the root.
+fn inspect(tree: &Tree, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    // Nodes from the root down to the current one.
+    let mut path = vec![tree.root_node()];
+    show_node(tree, &path, &mut output)?;
+    write!(output, "> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let current = *path.last().unwrap();
+        match words.as_slice() {
+            [] => {}
+            ["show"] => show_node(tree, &path, &mut output)?,
+            ["child", index] => match index.parse().ok().and_then(|index| current.child(index)) {
+                Some(child) => {
+                    path.push(child);
+                    show_node(tree, &path, &mut output)?;
+                }
+                None => writeln!(output, "node {} has no child {}", current.id(), index)?,
+            },
+            ["up"] if path.len() > 1 => {
+                path.pop();
+                show_node(tree, &path, &mut output)?;
+            }
+            ["up"] => writeln!(output, "already at the root")?,
+            ["root"] => {
+                path.truncate(1);
+                show_node(tree, &path, &mut output)?;
+            }
+            ["stats"] => show_stats(tree, &mut output)?,
+            ["help"] => writeln!(output, "{}", INSPECT_HELP)?,
+            ["quit"] | ["exit"] => return Ok(()),
+            _ => writeln!(output, "unknown command {:?}, try help", line.trim())?,
+        }
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+    writeln!(output)
+}
+
+/// Print the last node of `path` with its place in the tree, and its entries or children.
+fn show_node(
+    tree: &Tree,
+    path: &[NodeRef<'_, String, String>],
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let node = path.last().unwrap();
+    writeln!(
+        output,
+        "node {} ({}, depth {}): {} of {} keys",
+        node.id(),
+        if node.is_leaf() { "leaf" } else { "internal" },
+        path.len() - 1,
+        node.keys().len(),
+        tree.order() - 1
+    )?;
+    if node.is_leaf() {
+        let link = |leaf: Option<NodeRef<'_, _, _>>| {
+            leaf.map_or("none".to_string(), |leaf| leaf.id().to_string())
+        };
+        writeln!(
+            output,
+            "  prev leaf {}, next leaf {}",
+            link(node.prev_leaf()),
+            link(node.next_leaf())
+        )?;
+        for (key, value) in node.keys().iter().zip(node.values()) {
+            writeln!(output, "  {}\t{}", key, value)?;
+        }
+        return Ok(());
+    }
+    for index in 0..node.child_count() {
+        let child = node.child(index).unwrap();
+        writeln!(
+            output,
+            "  [{}] node {}, {} keys",
+            index,
+            child.id(),
+            child.keys().len()
+        )?;
+        if let Some(separator) = node.keys().get(index) {
+            writeln!(output, "  separator {}", separator)?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the number of nodes and keys of each level, and how full its nodes are on average.
+fn show_stats(tree: &Tree, output: &mut impl Write) -> io::Result<()> {
+    let mut level = vec![tree.root_node()];
+    let mut depth = 0;
+    while !level.is_empty() {
+        let keys: usize = level.iter().map(|node| node.keys().len()).sum();
+        let capacity = level.len() * (tree.order() - 1);
+        writeln!(
+            output,
+            "depth {}: {} nodes, {} keys, {:.0}% full",
+            depth,
+            level.len(),
+            keys,
+            100.0 * keys as f64 / capacity as f64
+        )?;
+        level = level
+            .iter()
+            .flat_map(|node| (0..node.child_count()).map(move |index| node.child(index).unwrap()))
+            .collect();
+        depth += 1;
+    }
+    writeln!(output, "{} entries", tree.len())
+}
+
+fn load(path: &Path) -> io::Result<Tree> {
+    let mut tree = BPlusTree::new(ORDER);
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(tree),
+        Err(error) => return Err(error),
+    };
+    for (number, line) in contents.lines().enumerate() {
+        let (key, value) = line.split_once('\t').ok_or_else(|| {
+            let message = format!("line {} has no tab between key and value", number + 1);
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })?;
+        tree.insert(key.to_string(), value.to_string());
+    }
+    Ok(tree)
+}
+
+/// Write the entries to `path` atomically, so an interrupted write never leaves a partial file.
+fn store(path: &Path, tree: &Tree) -> io::Result<()> {
+    write_atomically(path, |writer| {
+        for (key, value) in tree.iter() {
+            writeln!(writer, "{}\t{}", key, value)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{inspect, load, run, store, Tree};
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn store_and_load() {
+        let path = env::temp_dir().join(format!("rusty_bree_{}.tsv", std::process::id()));
+        let args = |args: &[&str]| -> Vec<String> {
+            let mut all = vec![path.to_str().unwrap().to_string()];
+            all.extend(args.iter().map(|arg| arg.to_string()));
+            all
+        };
+        for key in 0..100 {
+            let key = format!("{:02}", key);
+            run(&args(&["put", &key, "value"])).unwrap();
+        }
+        run(&args(&["delete", "42"])).unwrap();
+        assert!(run(&args(&["delete", "42"])).is_err());
+        assert!(run(&args(&["put", "a\tb", "value"])).is_err());
+        assert!(run(&args(&["scan", "b", "a"])).is_err());
+        assert!(run(&args(&["get"])).is_err());
+
+        let tree = load(&path).unwrap();
+        assert_eq!(tree.len(), 99);
+        assert_eq!(tree.get(&"07".to_string()), Some(&"value".to_string()));
+        assert!(!tree.contains_key(&"42".to_string()));
+        store(&path, &tree).unwrap();
+        assert!(load(&path).unwrap().iter().eq(tree.iter()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn walk_nodes() {
+        let mut tree = Tree::new(32);
+        for key in 0..100 {
+            tree.insert(format!("{:02}", key), "value".to_string());
+        }
+        let input = "child 0\nchild 0\nup\nup\nchild 9\nstats\nfrobnicate\nquit\nshow\n";
+        let mut output = vec![];
+        inspect(&tree, input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(&format!(
+            "node {} (internal, depth 0)",
+            tree.root_node().id()
+        )));
+        assert!(output.contains("(leaf, depth 1)"));
+        assert!(output.contains("has no child 0"));
+        assert!(output.contains("  00\tvalue\n"));
+        assert!(output.contains("already at the root"));
+        assert!(output.contains("has no child 9"));
+        assert!(output.contains("depth 1: "));
+        assert!(output.contains("100 entries"));
+        assert!(output.contains("unknown command \"frobnicate\""));
+        // Commands after `quit` are not read.
+        assert_eq!(output.matches("(internal, depth 0)").count(), 2);
+    }
+}
@@ -0,0 +1,49 @@
+/// Index of a node in the arena of a `BPlusTree`.
+pub(crate) type NodeId = usize;
+
+/// A node of a B+ tree.
+/// Values live only in leaves; internal nodes hold separators which route a search to a child.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct BPlusNode<K, V> {
+    // Keys of a leaf, or separators of an internal node.
+    // Every key under `children[i]` is less than `keys[i]`, and every key under
+    // `children[i + 1]` is greater than or equal to it.
+    pub(crate) keys: Vec<K>,
+    // Values of a leaf, in the same order as `keys`. Empty for internal nodes.
+    pub(crate) values: Vec<V>,
+    // Child nodes. Empty for leaves.
+    pub(crate) children: Vec<NodeId>,
+    // Neighbouring leaves in key order. Always `None` for internal nodes.
+    pub(crate) prev: Option<NodeId>,
+    pub(crate) next: Option<NodeId>,
+}
+
+impl<K, V> BPlusNode<K, V>
+where
+    K: Ord,
+{
+    pub fn new() -> Self {
+        Self {
+            keys: vec![],
+            values: vec![],
+            children: vec![],
+            prev: None,
+            next: None,
+        }
+    }
+
+    pub(crate) fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Index of the child whose subtree may contain `key`.
+    pub(crate) fn child_index(&self, key: &K) -> usize {
+        match self.keys.binary_search(key) {
+            Ok(index) => index + 1,
+            Err(index) => index,
+        }
+    }
+}
+
+use alloc::vec;
+use alloc::vec::Vec;
@@ -0,0 +1,1419 @@
+use crate::bplus_node::{BPlusNode, NodeId};
+use crate::btree::{check_order, DEFAULT_ORDER};
+use crate::error::{InvalidOrder, OccupiedError};
+use crate::node::even_share;
+use crate::time::Timestamp;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::iter::{Flatten, Skip, Take, Zip};
+use core::mem;
+use core::ops::{Bound, Index, RangeBounds};
+use core::slice;
+
+/// This structure represents a B+ tree which maps keys to values.
+/// All values are stored in leaves, and internal nodes only hold separator keys.
+/// Leaves are linked to their siblings, so a range scan walks along the leaves
+/// once it has found the first one.
+#[derive(Clone, Debug)]
+pub struct BPlusTree<K, V> {
+    // The maximum number of children each node can accomodates.
+    order: usize,
+    // All nodes of the tree. Nodes refer to each other by index into this vector.
+    nodes: Vec<BPlusNode<K, V>>,
+    // Slots of `nodes` released by merges, reused by later splits.
+    free: Vec<NodeId>,
+    root: NodeId,
+    len: usize,
+}
+
+impl<K, V> BPlusTree<K, V>
+where
+    K: Ord + Clone,
+{
+    /// Create an empty tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_ORDER`. Use `try_new` to handle it as an error.
+    pub fn new(order: usize) -> Self {
+        Self::try_new(order).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Create an empty tree, failing if `order` is less than `MIN_ORDER`.
+    pub fn try_new(order: usize) -> Result<Self, InvalidOrder> {
+        check_order(order)?;
+        Ok(Self {
+            order,
+            nodes: vec![BPlusNode::new()],
+            free: vec![],
+            root: 0,
+            len: 0,
+        })
+    }
+
+    /// Build a tree from key-value pairs yielded in ascending order of keys.
+    /// The tree is constructed bottom-up in linear time, which is much faster than inserting
+    /// the pairs one by one. Keys must be unique and sorted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_ORDER`.
+    pub fn from_sorted_iter<I>(iter: I, order: usize) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut tree = Self::new(order);
+        let (mut keys, mut values): (Vec<K>, Vec<V>) = iter.into_iter().unzip();
+        debug_assert!(
+            keys.windows(2).all(|pair| pair[0] < pair[1]),
+            "keys must be unique and sorted in ascending order"
+        );
+        let len = keys.len();
+        if len == 0 {
+            return tree;
+        }
+        // Spread the pairs evenly over as few leaves as can hold them, so that every leaf
+        // holds at least the minimum number of keys. Leaves are cut from the back.
+        let leaf_count = len.div_ceil(order - 1);
+        let mut leaves = Vec::with_capacity(leaf_count);
+        for index in (0..leaf_count).rev() {
+            let at = keys.len() - even_share(len, leaf_count, index);
+            let mut leaf = BPlusNode::new();
+            leaf.keys = keys.split_off(at);
+            leaf.values = values.split_off(at);
+            leaves.push(leaf);
+        }
+        tree.nodes.clear();
+        // Each node of the level being built, with the smallest key under it.
+        let mut level = Vec::with_capacity(leaf_count);
+        for (id, mut leaf) in leaves.into_iter().rev().enumerate() {
+            leaf.prev = id.checked_sub(1);
+            leaf.next = Some(id + 1).filter(|&next| next < leaf_count);
+            level.push((id, leaf.keys[0].clone()));
+            tree.nodes.push(leaf);
+        }
+        // Group the nodes of each level under new parents until a single root remains.
+        // The smallest key under each child but the first becomes a separator.
+        while level.len() > 1 {
+            let child_count = level.len();
+            let parent_count = child_count.div_ceil(order);
+            let mut children = level.into_iter();
+            level = Vec::with_capacity(parent_count);
+            for index in 0..parent_count {
+                let mut parent = BPlusNode::new();
+                let mut smallest = None;
+                let share = even_share(child_count, parent_count, index);
+                for (child, key) in children.by_ref().take(share) {
+                    match smallest {
+                        None => smallest = Some(key),
+                        Some(_) => parent.keys.push(key),
+                    }
+                    parent.children.push(child);
+                }
+                level.push((tree.nodes.len(), smallest.unwrap()));
+                tree.nodes.push(parent);
+            }
+        }
+        tree.root = level[0].0;
+        tree.len = len;
+        tree
+    }
+
+    /// Returns the number of key-value pairs in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Search the tree for a value associated with a given key.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let leaf = &self.nodes[self.find_leaf(key)];
+        match leaf.keys.binary_search(key) {
+            Ok(index) => Some(&leaf.values[index]),
+            Err(_) => None,
+        }
+    }
+
+    /// Search the tree for a given key, returning the key stored in the tree with its value.
+    /// The stored key can differ from the given one in fields which do not affect the order.
+    pub fn get_key_value(&self, key: &K) -> Option<(&K, &V)> {
+        let leaf = &self.nodes[self.find_leaf(key)];
+        match leaf.keys.binary_search(key) {
+            Ok(index) => Some((&leaf.keys[index], &leaf.values[index])),
+            Err(_) => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let leaf = self.find_leaf(key);
+        let leaf = &mut self.nodes[leaf];
+        match leaf.keys.binary_search(key) {
+            Ok(index) => Some(&mut leaf.values[index]),
+            Err(_) => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Insert a key-value pair. If the key is already present, its value is replaced and
+    /// the old one is returned.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let old_value = self.insert_at(self.root, key, value);
+        if old_value.is_none() {
+            self.len += 1;
+        }
+        if self.is_overflow(self.root) {
+            self.split_root();
+        }
+        old_value
+    }
+
+    /// Insert a key-value pair only if the key is not present yet, returning a reference
+    /// to the inserted value. Otherwise the tree is left as it is, and the pair is given back.
+    pub fn try_insert(&mut self, key: K, value: V) -> Result<&mut V, OccupiedError<'_, K, V>> {
+        // Internal nodes on the way to the leaf, with the index of the child taken in each.
+        let mut path = vec![];
+        let mut leaf = self.root;
+        while !self.nodes[leaf].is_leaf() {
+            let index = self.nodes[leaf].child_index(&key);
+            path.push((leaf, index));
+            leaf = self.nodes[leaf].children[index];
+        }
+        let mut index = match self.nodes[leaf].keys.binary_search(&key) {
+            Ok(index) => {
+                return Err(OccupiedError {
+                    key,
+                    value,
+                    existing: &mut self.nodes[leaf].values[index],
+                })
+            }
+            Err(index) => index,
+        };
+        let node = &mut self.nodes[leaf];
+        node.keys.insert(index, key);
+        node.values.insert(index, value);
+        self.len += 1;
+        if self.is_overflow(leaf) {
+            let (parent, child_index) = match path.last() {
+                Some(&(parent, child_index)) => {
+                    self.split_children(parent, child_index);
+                    (parent, child_index)
+                }
+                None => {
+                    self.split_root();
+                    (self.root, 0)
+                }
+            };
+            // Only the split of the leaf moves the new entry, if it lands in the right half.
+            let split_at = self.order / 2;
+            if index >= split_at {
+                leaf = self.nodes[parent].children[child_index + 1];
+                index -= split_at;
+            }
+            for pair in path.windows(2).rev() {
+                let (id, child_index) = pair[0];
+                if !self.is_overflow(pair[1].0) {
+                    break;
+                }
+                self.split_children(id, child_index);
+            }
+            if self.is_overflow(self.root) {
+                self.split_root();
+            }
+        }
+        Ok(&mut self.nodes[leaf].values[index])
+    }
+
+    /// Remove a key from the tree, returning the value associated with it.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let value = self.remove_at(self.root, key);
+        if value.is_some() {
+            self.len -= 1;
+        }
+        // The root has lost its last separator, so its only child becomes the new root.
+        let root = &self.nodes[self.root];
+        if !root.is_leaf() && root.keys.is_empty() {
+            let child = root.children[0];
+            self.release(self.root);
+            self.root = child;
+        }
+        value
+    }
+
+    /// Returns the maximum number of children each node can hold.
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    /// Returns a read-only view of the root node, for inspecting the shape of the tree.
+    pub fn root_node(&self) -> NodeRef<'_, K, V> {
+        NodeRef {
+            nodes: &self.nodes,
+            id: self.root,
+        }
+    }
+
+    /// Returns an iterator over key-value pairs in ascending order of keys.
+    pub fn iter(&self) -> Range<'_, K, V> {
+        self.range(..)
+    }
+
+    /// Returns an iterator over key-value pairs whose keys fall within a given range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the range is greater than its end.
+    pub fn range<R>(&self, range: R) -> Range<'_, K, V>
+    where
+        R: RangeBounds<K>,
+    {
+        let (front, back) = self.positions(range);
+        Range {
+            nodes: &self.nodes,
+            front,
+            back,
+        }
+    }
+
+    /// Returns an iterator over key-value pairs whose keys fall within a given range,
+    /// with mutable references to the values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the start of the range is greater than its end.
+    pub fn range_mut<R>(&mut self, range: R) -> RangeMut<'_, K, V>
+    where
+        R: RangeBounds<K>,
+    {
+        let (front, back) = self.positions(range);
+        let mut ids = vec![front.0];
+        while ids[ids.len() - 1] != back.0 {
+            ids.push(self.nodes[ids[ids.len() - 1]].next.unwrap());
+        }
+        let last = ids.len() - 1;
+        let leaves = leaves_mut(&mut self.nodes, &ids)
+            .into_iter()
+            .enumerate()
+            .map(move |(index, leaf)| {
+                let start = if index == 0 { front.1 } else { 0 };
+                let end = if index == last {
+                    back.1.max(start)
+                } else {
+                    leaf.keys.len()
+                };
+                leaf.keys[start..end]
+                    .iter()
+                    .zip(leaf.values[start..end].iter_mut())
+            });
+        RangeMut {
+            inner: leaves.collect::<Vec<_>>().into_iter().flatten(),
+        }
+    }
+
+    /// Returns the leaf and the index in it of the first entry within a range,
+    /// and the leaf and the index in it just past the last one.
+    fn positions<R>(&self, range: R) -> ((NodeId, usize), (NodeId, usize))
+    where
+        R: RangeBounds<K>,
+    {
+        match (range.start_bound(), range.end_bound()) {
+            (Bound::Excluded(start), Bound::Excluded(end)) if start == end => {
+                panic!("range start and end are equal and excluded")
+            }
+            (Bound::Included(start), Bound::Included(end))
+            | (Bound::Included(start), Bound::Excluded(end))
+            | (Bound::Excluded(start), Bound::Included(end))
+            | (Bound::Excluded(start), Bound::Excluded(end))
+                if start > end =>
+            {
+                panic!("range start is greater than range end")
+            }
+            _ => {}
+        }
+
+        let front = match range.start_bound() {
+            Bound::Included(key) => {
+                let leaf = self.find_leaf(key);
+                match self.nodes[leaf].keys.binary_search(key) {
+                    Ok(index) | Err(index) => (leaf, index),
+                }
+            }
+            Bound::Excluded(key) => {
+                let leaf = self.find_leaf(key);
+                match self.nodes[leaf].keys.binary_search(key) {
+                    Ok(index) => (leaf, index + 1),
+                    Err(index) => (leaf, index),
+                }
+            }
+            Bound::Unbounded => (self.first_leaf(), 0),
+        };
+        let back = match range.end_bound() {
+            Bound::Included(key) => {
+                let leaf = self.find_leaf(key);
+                match self.nodes[leaf].keys.binary_search(key) {
+                    Ok(index) => (leaf, index + 1),
+                    Err(index) => (leaf, index),
+                }
+            }
+            Bound::Excluded(key) => {
+                let leaf = self.find_leaf(key);
+                match self.nodes[leaf].keys.binary_search(key) {
+                    Ok(index) | Err(index) => (leaf, index),
+                }
+            }
+            Bound::Unbounded => {
+                let leaf = self.last_leaf();
+                (leaf, self.nodes[leaf].keys.len())
+            }
+        };
+        (front, back)
+    }
+
+    /// Returns entries whose keys are at most `duration` before `now`, `now` included,
+    /// in ascending order. The first `offset` of them are skipped and at most `limit`
+    /// are yielded after them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a negative `duration` puts the start of the window after `now`.
+    pub fn range_last(
+        &self,
+        duration: &K::Duration,
+        now: &K,
+        offset: usize,
+        limit: usize,
+    ) -> Take<Skip<Range<'_, K, V>>>
+    where
+        K: Timestamp,
+    {
+        // Windows reaching before the earliest representable time start at the first entry.
+        let start = now.checked_sub(duration);
+        let start = start.as_ref().map_or(Bound::Unbounded, Bound::Included);
+        self.range((start, Bound::Included(now)))
+            .skip(offset)
+            .take(limit)
+    }
+
+    /// Returns entries whose keys are between `start` and `end`, both included, in ascending
+    /// order. The first `offset` of them are skipped and at most `limit` are yielded after them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start` is greater than `end`.
+    pub fn range_between(
+        &self,
+        start: &K,
+        end: &K,
+        offset: usize,
+        limit: usize,
+    ) -> Take<Skip<Range<'_, K, V>>>
+    where
+        K: Timestamp,
+    {
+        self.range((Bound::Included(start), Bound::Included(end)))
+            .skip(offset)
+            .take(limit)
+    }
+
+    /// Returns an iterator over keys in ascending order.
+    pub fn keys(&self) -> Keys<'_, K, V> {
+        Keys { inner: self.iter() }
+    }
+
+    /// Returns an iterator over values in ascending order of their keys.
+    pub fn values(&self) -> Values<'_, K, V> {
+        Values { inner: self.iter() }
+    }
+
+    /// Returns an iterator over mutable references to values in ascending order of their keys.
+    pub fn values_mut(&mut self) -> ValuesMut<'_, V> {
+        let mut ids = vec![];
+        let mut leaf = Some(self.first_leaf());
+        while let Some(id) = leaf {
+            ids.push(id);
+            leaf = self.nodes[id].next;
+        }
+        let leaves = leaves_mut(&mut self.nodes, &ids);
+        ValuesMut {
+            inner: leaves
+                .into_iter()
+                .map(|leaf| &mut leaf.values)
+                .collect::<Vec<_>>()
+                .into_iter()
+                .flatten(),
+        }
+    }
+
+    /// Returns an iterator which consumes the tree, yielding keys in ascending order.
+    pub fn into_keys(self) -> IntoKeys<K> {
+        let (keys, _) = self.into_leaf_entries();
+        IntoKeys {
+            inner: keys.into_iter().flatten(),
+        }
+    }
+
+    /// Returns an iterator which consumes the tree, yielding values in ascending order of their keys.
+    pub fn into_values(self) -> IntoValues<V> {
+        let (_, values) = self.into_leaf_entries();
+        IntoValues {
+            inner: values.into_iter().flatten(),
+        }
+    }
+
+    /// Take the keys and values out of each leaf, from left to right.
+    fn into_leaf_entries(self) -> (Vec<Vec<K>>, Vec<Vec<V>>) {
+        let mut leaf = Some(self.first_leaf());
+        let mut slots: Vec<_> = self.nodes.into_iter().map(Some).collect();
+        let mut keys = vec![];
+        let mut values = vec![];
+        while let Some(node) = leaf.and_then(|id| slots[id].take()) {
+            keys.push(node.keys);
+            values.push(node.values);
+            leaf = node.next;
+        }
+        (keys, values)
+    }
+
+    fn allocate(&mut self, node: BPlusNode<K, V>) -> NodeId {
+        match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, id: NodeId) {
+        self.nodes[id] = BPlusNode::new();
+        self.free.push(id);
+    }
+
+    /// Borrow two distinct nodes mutably at once.
+    fn pair_mut(
+        &mut self,
+        left: NodeId,
+        right: NodeId,
+    ) -> (&mut BPlusNode<K, V>, &mut BPlusNode<K, V>) {
+        if left < right {
+            let (head, tail) = self.nodes.split_at_mut(right);
+            (&mut head[left], &mut tail[0])
+        } else {
+            let (head, tail) = self.nodes.split_at_mut(left);
+            (&mut tail[0], &mut head[right])
+        }
+    }
+
+    fn is_overflow(&self, id: NodeId) -> bool {
+        self.nodes[id].keys.len() == self.order
+    }
+
+    fn is_underflow(&self, id: NodeId) -> bool {
+        self.nodes[id].keys.len() < self.min_keys()
+    }
+
+    /// The minimum number of keys a node except the root must hold.
+    fn min_keys(&self) -> usize {
+        (self.order - 1) / 2
+    }
+
+    /// Descend from the root to the leaf whose range covers a given key.
+    fn find_leaf(&self, key: &K) -> NodeId {
+        let mut id = self.root;
+        while !self.nodes[id].is_leaf() {
+            let node = &self.nodes[id];
+            id = node.children[node.child_index(key)];
+        }
+        id
+    }
+
+    fn first_leaf(&self) -> NodeId {
+        let mut id = self.root;
+        while !self.nodes[id].is_leaf() {
+            id = self.nodes[id].children[0];
+        }
+        id
+    }
+
+    fn last_leaf(&self) -> NodeId {
+        let mut id = self.root;
+        while !self.nodes[id].is_leaf() {
+            id = *self.nodes[id].children.last().unwrap();
+        }
+        id
+    }
+
+    /// Insert a key-value pair into a subtree, returning the value previously associated
+    /// with the key. Children which overflow are split, but the node `id` may be left overflowing.
+    fn insert_at(&mut self, id: NodeId, key: K, value: V) -> Option<V> {
+        let node = &mut self.nodes[id];
+        if node.is_leaf() {
+            return match node.keys.binary_search(&key) {
+                Ok(index) => Some(mem::replace(&mut node.values[index], value)),
+                Err(index) => {
+                    node.keys.insert(index, key);
+                    node.values.insert(index, value);
+                    None
+                }
+            };
+        }
+        let index = node.child_index(&key);
+        let child = node.children[index];
+        let old_value = self.insert_at(child, key, value);
+        if self.is_overflow(child) {
+            self.split_children(id, index);
+        }
+        old_value
+    }
+
+    /// Split the overflowing root under a new root, which grows the tree by a level.
+    fn split_root(&mut self) {
+        let mut root = BPlusNode::new();
+        root.children.push(self.root);
+        self.root = self.allocate(root);
+        self.split_children(self.root, 0);
+    }
+
+    /// Split an overflowing child into two and insert a separator between them.
+    /// A leaf keeps the separator as its first key, while an internal node moves it up.
+    fn split_children(&mut self, id: NodeId, index: usize) {
+        let child_id = self.nodes[id].children[index];
+        let split_at = self.order / 2;
+        let child = &mut self.nodes[child_id];
+        let mut right_child = BPlusNode::new();
+        let separator = if child.is_leaf() {
+            right_child.keys = child.keys.split_off(split_at);
+            right_child.values = child.values.split_off(split_at);
+            right_child.prev = Some(child_id);
+            right_child.next = child.next;
+            right_child.keys[0].clone()
+        } else {
+            right_child.keys = child.keys.split_off(split_at + 1);
+            right_child.children = child.children.split_off(split_at + 1);
+            child.keys.pop().unwrap()
+        };
+        let next = right_child.next;
+        let right_id = self.allocate(right_child);
+        if self.nodes[child_id].is_leaf() {
+            self.nodes[child_id].next = Some(right_id);
+            if let Some(next) = next {
+                self.nodes[next].prev = Some(right_id);
+            }
+        }
+        let node = &mut self.nodes[id];
+        node.keys.insert(index, separator);
+        node.children.insert(index + 1, right_id);
+    }
+
+    /// Remove a key from a subtree, returning the value associated with it.
+    /// Children which underflow are refilled, but the node `id` may be left underflowing.
+    fn remove_at(&mut self, id: NodeId, key: &K) -> Option<V> {
+        let node = &mut self.nodes[id];
+        if node.is_leaf() {
+            return match node.keys.binary_search(key) {
+                Ok(index) => {
+                    node.keys.remove(index);
+                    Some(node.values.remove(index))
+                }
+                Err(_) => None,
+            };
+        }
+        let index = node.child_index(key);
+        let child = node.children[index];
+        let value = self.remove_at(child, key);
+        if self.is_underflow(child) {
+            self.rebalance_children(id, index);
+        }
+        value
+    }
+
+    /// Refill an underflowing child by borrowing a key from a sibling, or merge it into
+    /// a sibling if neither of them has a key to spare.
+    fn rebalance_children(&mut self, id: NodeId, index: usize) {
+        let min_keys = self.min_keys();
+        let children = &self.nodes[id].children;
+        let has_spare = |sibling: NodeId| self.nodes[sibling].keys.len() > min_keys;
+        if index > 0 && has_spare(children[index - 1]) {
+            self.borrow_from_left(id, index);
+        } else if index + 1 < children.len() && has_spare(children[index + 1]) {
+            self.borrow_from_right(id, index);
+        } else if index > 0 {
+            self.merge_children(id, index - 1);
+        } else {
+            self.merge_children(id, index);
+        }
+    }
+
+    fn borrow_from_left(&mut self, id: NodeId, index: usize) {
+        let left_id = self.nodes[id].children[index - 1];
+        let child_id = self.nodes[id].children[index];
+        let (left, child) = self.pair_mut(left_id, child_id);
+        if child.is_leaf() {
+            child.keys.insert(0, left.keys.pop().unwrap());
+            child.values.insert(0, left.values.pop().unwrap());
+            let separator = child.keys[0].clone();
+            self.nodes[id].keys[index - 1] = separator;
+        } else {
+            child.children.insert(0, left.children.pop().unwrap());
+            let key = left.keys.pop().unwrap();
+            let separator = mem::replace(&mut self.nodes[id].keys[index - 1], key);
+            self.nodes[child_id].keys.insert(0, separator);
+        }
+    }
+
+    fn borrow_from_right(&mut self, id: NodeId, index: usize) {
+        let child_id = self.nodes[id].children[index];
+        let right_id = self.nodes[id].children[index + 1];
+        let (child, right) = self.pair_mut(child_id, right_id);
+        if child.is_leaf() {
+            child.keys.push(right.keys.remove(0));
+            child.values.push(right.values.remove(0));
+            let separator = right.keys[0].clone();
+            self.nodes[id].keys[index] = separator;
+        } else {
+            child.children.push(right.children.remove(0));
+            let key = right.keys.remove(0);
+            let separator = mem::replace(&mut self.nodes[id].keys[index], key);
+            self.nodes[child_id].keys.push(separator);
+        }
+    }
+
+    /// Merge `children[index + 1]` of a node into `children[index]`.
+    fn merge_children(&mut self, id: NodeId, index: usize) {
+        let node = &mut self.nodes[id];
+        let right_id = node.children.remove(index + 1);
+        let separator = node.keys.remove(index);
+        let left_id = node.children[index];
+        let right = mem::replace(&mut self.nodes[right_id], BPlusNode::new());
+        let left = &mut self.nodes[left_id];
+        if left.is_leaf() {
+            left.next = right.next;
+            if let Some(next) = right.next {
+                self.nodes[next].prev = Some(left_id);
+            }
+        } else {
+            left.keys.push(separator);
+        }
+        let left = &mut self.nodes[left_id];
+        left.keys.extend(right.keys);
+        left.values.extend(right.values);
+        left.children.extend(right.children);
+        self.release(right_id);
+    }
+}
+
+impl<K, V> Index<&K> for BPlusTree<K, V>
+where
+    K: Ord + Clone,
+{
+    type Output = V;
+
+    /// Returns the value associated with a given key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not present in the tree.
+    fn index(&self, key: &K) -> &V {
+        self.get(key).expect("key not found in the tree")
+    }
+}
+
+impl<K, V> From<BTreeMap<K, V>> for BPlusTree<K, V>
+where
+    K: Ord + Clone,
+{
+    /// Build a tree of `DEFAULT_ORDER` in linear time, as keys of a map are sorted already.
+    fn from(map: BTreeMap<K, V>) -> Self {
+        Self::from_sorted_iter(map, DEFAULT_ORDER)
+    }
+}
+
+impl<K, V> From<BPlusTree<K, V>> for BTreeMap<K, V>
+where
+    K: Ord + Clone,
+{
+    fn from(tree: BPlusTree<K, V>) -> Self {
+        let (keys, values) = tree.into_leaf_entries();
+        keys.into_iter()
+            .flatten()
+            .zip(values.into_iter().flatten())
+            .collect()
+    }
+}
+
+/// A read-only view of a node of a `BPlusTree`.
+pub struct NodeRef<'a, K, V> {
+    nodes: &'a [BPlusNode<K, V>],
+    id: NodeId,
+}
+
+impl<'a, K, V> NodeRef<'a, K, V> {
+    /// Returns the slot of the node in the arena of the tree.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.nodes[self.id].children.is_empty()
+    }
+
+    /// Returns the keys of a leaf, or the separators of an internal node.
+    pub fn keys(&self) -> &'a [K] {
+        &self.nodes[self.id].keys
+    }
+
+    /// Returns the values of a leaf. It is empty for internal nodes.
+    pub fn values(&self) -> &'a [V] {
+        &self.nodes[self.id].values
+    }
+
+    pub fn child_count(&self) -> usize {
+        self.nodes[self.id].children.len()
+    }
+
+    pub fn child(&self, index: usize) -> Option<Self> {
+        let id = *self.nodes[self.id].children.get(index)?;
+        Some(self.with_id(id))
+    }
+
+    /// Returns the previous leaf in key order. It is `None` for internal nodes.
+    pub fn prev_leaf(&self) -> Option<Self> {
+        self.nodes[self.id].prev.map(|id| self.with_id(id))
+    }
+
+    /// Returns the next leaf in key order. It is `None` for internal nodes.
+    pub fn next_leaf(&self) -> Option<Self> {
+        self.nodes[self.id].next.map(|id| self.with_id(id))
+    }
+
+    fn with_id(&self, id: NodeId) -> Self {
+        Self {
+            nodes: self.nodes,
+            id,
+        }
+    }
+}
+
+impl<K, V> Clone for NodeRef<'_, K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K, V> Copy for NodeRef<'_, K, V> {}
+
+/// An iterator over a range of entries of a `BPlusTree`.
+pub struct Range<'a, K, V> {
+    nodes: &'a [BPlusNode<K, V>],
+    // The leaf and the index in it of the next entry to yield from the front.
+    front: (NodeId, usize),
+    // The leaf and the index in it just past the next entry to yield from the back.
+    back: (NodeId, usize),
+}
+
+impl<'a, K, V> Iterator for Range<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (mut leaf, mut index) = self.front;
+        while index == self.nodes[leaf].keys.len() && leaf != self.back.0 {
+            leaf = self.nodes[leaf].next?;
+            index = 0;
+        }
+        if leaf == self.back.0 && index >= self.back.1 {
+            self.front = (leaf, index);
+            return None;
+        }
+        self.front = (leaf, index + 1);
+        let node = &self.nodes[leaf];
+        Some((&node.keys[index], &node.values[index]))
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Range<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let (mut leaf, mut index) = self.back;
+        while index == 0 && leaf != self.front.0 {
+            leaf = self.nodes[leaf].prev?;
+            index = self.nodes[leaf].keys.len();
+        }
+        if leaf == self.front.0 && index <= self.front.1 {
+            self.back = (leaf, index);
+            return None;
+        }
+        self.back = (leaf, index - 1);
+        let node = &self.nodes[leaf];
+        Some((&node.keys[index - 1], &node.values[index - 1]))
+    }
+}
+
+/// Borrow leaves apart, given their ids in key order. The ids are visited in ascending order
+/// to split the nodes, so nodes outside the leaves are not visited.
+fn leaves_mut<'a, K, V>(
+    nodes: &'a mut [BPlusNode<K, V>],
+    ids: &[NodeId],
+) -> Vec<&'a mut BPlusNode<K, V>> {
+    let mut order: Vec<(NodeId, usize)> = ids.iter().copied().zip(0..).collect();
+    order.sort_unstable();
+    let mut leaves: Vec<Option<&'a mut BPlusNode<K, V>>> = ids.iter().map(|_| None).collect();
+    let mut rest = nodes;
+    // The id of the first node in `rest`.
+    let mut offset = 0;
+    for (id, index) in order {
+        let (leaf, tail) = mem::take(&mut rest)[id - offset..]
+            .split_first_mut()
+            .unwrap();
+        leaves[index] = Some(leaf);
+        rest = tail;
+        offset = id + 1;
+    }
+    leaves.into_iter().map(Option::unwrap).collect()
+}
+
+/// An iterator over a range of entries of a `BPlusTree`, with mutable references to values.
+pub struct RangeMut<'a, K, V> {
+    // Entries of each leaf within the range from left to right.
+    inner: Flatten<vec::IntoIter<Zip<slice::Iter<'a, K>, slice::IterMut<'a, V>>>>,
+}
+
+impl<'a, K, V> Iterator for RangeMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for RangeMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+/// An iterator over keys of a `BPlusTree` in ascending order.
+pub struct Keys<'a, K, V> {
+    inner: Range<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Keys<'a, K, V> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(key, _)| key)
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Keys<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(key, _)| key)
+    }
+}
+
+/// An iterator over values of a `BPlusTree` in ascending order of their keys.
+pub struct Values<'a, K, V> {
+    inner: Range<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for Values<'a, K, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, value)| value)
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for Values<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(_, value)| value)
+    }
+}
+
+/// An iterator over mutable references to values of a `BPlusTree` in ascending order of their keys.
+pub struct ValuesMut<'a, V> {
+    // Values of each leaf from left to right.
+    inner: Flatten<vec::IntoIter<&'a mut Vec<V>>>,
+}
+
+impl<'a, V> Iterator for ValuesMut<'a, V> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+impl<'a, V> DoubleEndedIterator for ValuesMut<'a, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+/// An iterator which consumes a `BPlusTree`, yielding keys in ascending order.
+pub struct IntoKeys<K> {
+    // Keys of each leaf from left to right.
+    inner: Flatten<vec::IntoIter<Vec<K>>>,
+}
+
+impl<K> Iterator for IntoKeys<K> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        self.inner.next()
+    }
+}
+
+impl<K> DoubleEndedIterator for IntoKeys<K> {
+    fn next_back(&mut self) -> Option<K> {
+        self.inner.next_back()
+    }
+}
+
+/// An iterator which consumes a `BPlusTree`, yielding values in ascending order of their keys.
+pub struct IntoValues<V> {
+    // Values of each leaf from left to right.
+    inner: Flatten<vec::IntoIter<Vec<V>>>,
+}
+
+impl<V> Iterator for IntoValues<V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        self.inner.next()
+    }
+}
+
+impl<V> DoubleEndedIterator for IntoValues<V> {
+    fn next_back(&mut self) -> Option<V> {
+        self.inner.next_back()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bplus_node::NodeId;
+    use crate::bplus_tree::BPlusTree;
+    use rand::Rng;
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+    use std::time::Duration;
+
+    // Asserts given subtree of a B+ tree is valid, collecting its leaves from left to right.
+    // Returns the height of the subtree.
+    fn is_valid_subtree<K: Ord + Clone, V>(
+        tree: &BPlusTree<K, V>,
+        id: NodeId,
+        leaves: &mut Vec<NodeId>,
+    ) -> usize {
+        let node = &tree.nodes[id];
+        assert!(node.keys.len() < tree.order);
+        if id != tree.root {
+            assert!(!tree.is_underflow(id));
+        }
+        assert!(node.keys.windows(2).all(|pair| pair[0] < pair[1]));
+        if node.is_leaf() {
+            assert_eq!(node.keys.len(), node.values.len());
+            leaves.push(id);
+            return 1;
+        }
+        assert!(node.values.is_empty());
+        assert!(node.prev.is_none() && node.next.is_none());
+        assert_eq!(node.keys.len() + 1, node.children.len());
+        for (i, &child) in node.children.iter().enumerate() {
+            let child = &tree.nodes[child];
+            // Separators must bound keys of the children on both sides.
+            if i > 0 {
+                assert!(child.keys.iter().all(|key| key >= &node.keys[i - 1]));
+            }
+            if i < node.keys.len() {
+                assert!(child.keys.iter().all(|key| key < &node.keys[i]));
+            }
+        }
+        let heights: Vec<usize> = node
+            .children
+            .iter()
+            .map(|&child| is_valid_subtree(tree, child, leaves))
+            .collect();
+        // All leaves must be at the same depth.
+        assert!(heights.iter().all(|&height| height == heights[0]));
+        heights[0] + 1
+    }
+
+    fn is_valid_bplus_tree<K: Ord + Clone, V>(tree: &BPlusTree<K, V>) {
+        let mut leaves = vec![];
+        is_valid_subtree(tree, tree.root, &mut leaves);
+        // Sibling links must chain the leaves in key order.
+        for (i, &leaf) in leaves.iter().enumerate() {
+            let node = &tree.nodes[leaf];
+            assert_eq!(node.prev, i.checked_sub(1).map(|i| leaves[i]));
+            assert_eq!(node.next, leaves.get(i + 1).copied());
+        }
+        // Every slot is either reachable or on the free list.
+        let reachable = tree.nodes.len() - tree.free.len();
+        assert!(reachable >= leaves.len());
+    }
+
+    #[test]
+    fn insert_and_get() {
+        let mut tree = BPlusTree::new(4);
+        for key in 0..100 {
+            assert_eq!(tree.insert(key, key * 2), None);
+        }
+        is_valid_bplus_tree(&tree);
+        assert_eq!(tree.len(), 100);
+        for key in 0..100 {
+            assert_eq!(tree.get(&key), Some(&(key * 2)));
+        }
+        assert_eq!(tree.get(&100), None);
+        assert_eq!(tree.insert(42, 0), Some(84));
+        assert_eq!(tree.get(&42), Some(&0));
+        assert_eq!(tree.len(), 100);
+        assert_eq!(tree[&43], 86);
+    }
+
+    #[test]
+    fn build_from_sorted_iter() {
+        for order in 3..8 {
+            for len in 0..100 {
+                let tree = BPlusTree::from_sorted_iter((0..len).map(|key| (key, key * 2)), order);
+                is_valid_bplus_tree(&tree);
+                assert_eq!(tree.len(), len);
+                let entries: Vec<_> = tree.iter().map(|(&key, &value)| (key, value)).collect();
+                assert_eq!(
+                    entries,
+                    (0..len).map(|key| (key, key * 2)).collect::<Vec<_>>()
+                );
+            }
+        }
+        let mut tree = BPlusTree::from_sorted_iter((0..500).map(|key| (key, ())), 4);
+        for key in (0..500).step_by(2) {
+            assert!(tree.remove(&key).is_some());
+            tree.insert(key + 1_000, ());
+        }
+        is_valid_bplus_tree(&tree);
+        assert_eq!(tree.len(), 500);
+    }
+
+    #[test]
+    fn convert_from_and_into_std() {
+        let map: BTreeMap<_, _> = (0..300).map(|key| (key, key.to_string())).collect();
+        let tree = BPlusTree::from(map.clone());
+        is_valid_bplus_tree(&tree);
+        assert_eq!(tree.order(), crate::btree::DEFAULT_ORDER);
+        assert!(tree.iter().eq(map.iter()));
+        assert_eq!(BTreeMap::from(tree), map);
+    }
+
+    #[test]
+    fn inspect_nodes() {
+        let mut tree = BPlusTree::new(4);
+        for key in 0..100 {
+            tree.insert(key, key * 2);
+        }
+        let root = tree.root_node();
+        assert_eq!(root.id(), tree.root);
+        assert!(!root.is_leaf());
+        assert_eq!(root.child_count(), root.keys().len() + 1);
+        assert!(root.child(root.child_count()).is_none());
+        assert!(root.next_leaf().is_none());
+
+        let mut leaf = root;
+        while let Some(child) = leaf.child(0) {
+            leaf = child;
+        }
+        assert!(leaf.prev_leaf().is_none());
+        let mut entries = vec![];
+        let mut leaf = Some(leaf);
+        while let Some(node) = leaf {
+            entries.extend(node.keys().iter().zip(node.values()));
+            leaf = node.next_leaf();
+        }
+        assert!(entries.into_iter().eq(tree.iter()));
+    }
+
+    #[test]
+    fn get_stored_key() {
+        // Ordered by the id alone.
+        #[derive(Clone, Debug)]
+        struct Entry {
+            id: u32,
+            name: &'static str,
+        }
+        impl PartialEq for Entry {
+            fn eq(&self, other: &Self) -> bool {
+                self.id == other.id
+            }
+        }
+        impl Eq for Entry {}
+        impl PartialOrd for Entry {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+        impl Ord for Entry {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                self.id.cmp(&other.id)
+            }
+        }
+
+        let mut tree = BPlusTree::new(3);
+        for id in 0..20 {
+            tree.insert(Entry { id, name: "stored" }, id * 2);
+        }
+        let (key, value) = tree
+            .get_key_value(&Entry {
+                id: 7,
+                name: "probe",
+            })
+            .unwrap();
+        assert_eq!((key.name, *value), ("stored", 14));
+        assert_eq!(
+            tree.get_key_value(&Entry {
+                id: 20,
+                name: "probe"
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn try_insert_only_new_keys() {
+        let mut tree = BPlusTree::new(3);
+        for key in 0..10 {
+            *tree.try_insert(key, key).unwrap() += 100;
+        }
+        let error = tree.try_insert(5, 0).unwrap_err();
+        assert_eq!((error.key, error.value), (5, 0));
+        *error.existing += 1;
+        assert_eq!(tree.get(&5), Some(&106));
+        assert_eq!(tree.len(), 10);
+        assert_eq!(
+            tree.try_insert(5, 0).unwrap_err().to_string(),
+            "key 5 is present already with value 106, so 0 was not inserted"
+        );
+    }
+
+    #[test]
+    fn try_insert_splits_like_insert() {
+        let mut rng = rand::thread_rng();
+        for order in 3..8 {
+            let mut tree = BPlusTree::new(order);
+            let mut inserted = BPlusTree::new(order);
+            let mut expected = BTreeMap::new();
+            for _ in 0..1_000 {
+                let key = rng.gen_range(0, 500u32);
+                match tree.try_insert(key, key) {
+                    Ok(value) => {
+                        *value += 1;
+                        assert!(expected.insert(key, key + 1).is_none());
+                        inserted.insert(key, key + 1);
+                    }
+                    Err(error) => assert_eq!(*error.existing, key + 1),
+                }
+            }
+            assert!(tree.iter().eq(expected.iter()));
+            assert_eq!(tree.len(), expected.len());
+            // Both trees split the same nodes in the same order.
+            assert_eq!((tree.root, &tree.nodes), (inserted.root, &inserted.nodes));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_with_missing_key() {
+        let mut tree = BPlusTree::new(3);
+        tree.insert(1, 1);
+        let _ = tree[&2];
+    }
+
+    #[test]
+    fn range_scan() {
+        let mut tree = BPlusTree::new(3);
+        for key in (0..50).rev() {
+            tree.insert(key * 2, key);
+        }
+        let keys: Vec<u32> = tree.range(10..20).map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec![10, 12, 14, 16, 18]);
+        let keys: Vec<u32> = tree.range(11..=20).map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec![12, 14, 16, 18, 20]);
+        let keys: Vec<u32> = tree.range(95..).map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec![96, 98]);
+        assert_eq!(tree.range(30..30).next(), None);
+        assert_eq!(tree.iter().count(), 50);
+    }
+
+    #[test]
+    fn range_scan_from_both_ends() {
+        let mut tree = BPlusTree::new(3);
+        for key in 0..30 {
+            tree.insert(key, ());
+        }
+        let keys: Vec<u32> = tree.range(5..15).rev().map(|(key, _)| *key).collect();
+        assert_eq!(keys, (5..15).rev().collect::<Vec<_>>());
+        let mut range = tree.range(5..=10);
+        assert_eq!(range.next(), Some((&5, &())));
+        assert_eq!(range.next_back(), Some((&10, &())));
+        let keys: Vec<u32> = range.map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec![6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn update_values_in_range() {
+        let mut tree = BPlusTree::new(3);
+        for key in (0..50).rev() {
+            tree.insert(key * 2, key);
+        }
+        for (key, value) in tree.range_mut(10..=40) {
+            *value = key * 100;
+        }
+        for (key, value) in tree.iter() {
+            let expected = if (10..=40).contains(key) {
+                key * 100
+            } else {
+                key / 2
+            };
+            assert_eq!(*value, expected);
+        }
+        let keys: Vec<u32> = tree.range_mut(11..20).rev().map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec![18, 16, 14, 12]);
+        let mut range = tree.range_mut(95..);
+        assert_eq!(range.next(), Some((&96, &mut 48)));
+        assert_eq!(range.next_back(), Some((&98, &mut 49)));
+        assert_eq!(range.next(), None);
+        assert_eq!(tree.range_mut(30..30).next(), None);
+        assert_eq!(tree.range_mut(31..32).next(), None);
+        assert_eq!(BPlusTree::<u32, u32>::new(3).range_mut(..).next(), None);
+    }
+
+    #[test]
+    fn project_keys_and_values() {
+        let mut tree = BPlusTree::new(3);
+        for key in (0..50).rev() {
+            tree.insert(key, key * 10);
+        }
+        tree.remove(&25);
+        assert!(tree.keys().copied().eq((0..50).filter(|&key| key != 25)));
+        assert!(tree
+            .values()
+            .rev()
+            .copied()
+            .eq((0..50).rev().filter(|&key| key != 25).map(|key| key * 10)));
+        for value in tree.values_mut() {
+            *value += 1;
+        }
+        is_valid_bplus_tree(&tree);
+        assert!(tree.iter().all(|(&key, &value)| value == key * 10 + 1));
+        assert_eq!(tree.values_mut().next_back(), Some(&mut 491));
+        assert_eq!(BPlusTree::<u32, u32>::new(3).values_mut().next(), None);
+    }
+
+    #[test]
+    fn consume_into_keys_and_values() {
+        let mut tree = BPlusTree::new(4);
+        for key in (0..100).rev() {
+            tree.insert(key, key.to_string());
+        }
+        for key in 20..40 {
+            tree.remove(&key);
+        }
+        let expected: Vec<u32> = (0..20).chain(40..100).collect();
+        assert_eq!(tree.clone().into_keys().collect::<Vec<_>>(), expected);
+        assert!(tree
+            .into_values()
+            .rev()
+            .eq(expected.iter().rev().map(|key| key.to_string())));
+        assert_eq!(BPlusTree::<u32, u32>::new(3).into_keys().next(), None);
+    }
+
+    #[test]
+    fn time_windows() {
+        let mut tree = BPlusTree::new(4);
+        for second in (0..100u64).step_by(5) {
+            tree.insert(second, second * 10);
+        }
+        let seconds = |range: &mut dyn Iterator<Item = (&u64, &u64)>| -> Vec<u64> {
+            range.map(|(second, _)| *second).collect()
+        };
+        assert_eq!(
+            seconds(&mut tree.range_last(&20, &60, 0, 10)),
+            vec![40, 45, 50, 55, 60]
+        );
+        assert_eq!(seconds(&mut tree.range_last(&20, &60, 1, 2)), vec![45, 50]);
+        // The window reaches before zero.
+        assert_eq!(seconds(&mut tree.range_last(&20, &7, 0, 10)), vec![0, 5]);
+        assert_eq!(
+            seconds(&mut tree.range_between(&12, &30, 0, 10)),
+            vec![15, 20, 25, 30]
+        );
+        assert_eq!(seconds(&mut tree.range_between(&12, &30, 3, 10)), vec![30]);
+        assert_eq!(tree.range_between(&12, &30, 0, 0).next(), None);
+
+        let mut tree = BPlusTree::new(4);
+        let epoch = Duration::from_secs(1_000);
+        for minute in 0..10 {
+            tree.insert(epoch + Duration::from_secs(60 * minute), minute);
+        }
+        let now = epoch + Duration::from_secs(300);
+        let minutes: Vec<u64> = tree
+            .range_last(&Duration::from_secs(120), &now, 0, usize::MAX)
+            .map(|(_, minute)| *minute)
+            .collect();
+        assert_eq!(minutes, vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn reject_small_orders() {
+        for order in 0..3 {
+            assert!(BPlusTree::<u32, ()>::try_new(order).is_err());
+        }
+        assert!(BPlusTree::<u32, ()>::try_new(3).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "order of a B-Tree must be at least 3")]
+    fn build_with_order_one() {
+        BPlusTree::from_sorted_iter((0..10).map(|key| (key, ())), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn range_with_start_after_end() {
+        let tree: BPlusTree<u32, ()> = BPlusTree::new(3);
+        tree.range((Bound::Included(5), Bound::Excluded(3)));
+    }
+
+    #[test]
+    fn random_operations() {
+        let mut rng = rand::thread_rng();
+        for &order in &[3, 4, 5, 16] {
+            let mut tree = BPlusTree::new(order);
+            let mut expected = BTreeMap::new();
+            for _ in 0..2000 {
+                let key: u32 = rng.gen_range(0, 300);
+                if rng.gen_bool(0.6) {
+                    assert_eq!(tree.insert(key, key + 1), expected.insert(key, key + 1));
+                } else {
+                    assert_eq!(tree.remove(&key), expected.remove(&key));
+                }
+                assert_eq!(tree.len(), expected.len());
+            }
+            is_valid_bplus_tree(&tree);
+            assert!(tree.iter().eq(expected.iter()));
+            assert!(tree.iter().rev().eq(expected.iter().rev()));
+            let (start, end) = (100, 200);
+            assert!(tree.range(start..end).eq(expected.range(start..end)));
+            assert!(tree
+                .range(start..=end)
+                .rev()
+                .eq(expected.range(start..=end).rev()));
+        }
+    }
+}
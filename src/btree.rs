@@ -1,266 +1,3427 @@
-use crate::node::{Node, NodeKind};
+use crate::compare::{Comparator, Counted, OrdComparator};
+use crate::error::{DuplicateKeyError, InvalidOrder, UnorderedKeyError};
+use crate::node::{Arena, Node, NodeId, NodeKind};
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt::{self, Write};
+use core::hash::{Hash, Hasher};
+use core::iter::Peekable;
+use core::mem;
+use core::ops::{Bound, RangeBounds};
+#[cfg(feature = "metrics")]
+use core::sync::atomic;
+#[cfg(feature = "rand")]
+use rand::Rng;
+
+/// The smallest order a tree accepts. Below it, splitting a full node would leave an empty half.
+pub const MIN_ORDER: usize = 3;
+
+/// The order of trees built without being given one, such as by conversions from other collections.
+/// Nodes of this order keep their keys inline.
+pub const DEFAULT_ORDER: usize = 8;
+
+/// The minimum fill of nodes of a `BTree` unless `BTree::set_min_fill` changes it.
+pub const DEFAULT_MIN_FILL: usize = 50;
 
 /// This structure represents a B-Tree node.
-#[derive(Clone, Debug)]
-pub struct BTree<T: Ord> {
-    root: Box<Node<T>>,
+/// Keys are ordered by a comparator, which is their `Ord` implementation unless
+/// the tree is created with `BTree::with_comparator`.
+///
+/// Trees share nodes with their snapshots, so a tree is `Send` if its keys are `Send` and `Sync`
+/// and its comparator is `Send`, and `Sync` if its comparator is `Sync` instead. Once it is no
+/// longer modified, it can be wrapped in a `SharedBTree` to serve lookups from many threads.
+/// Cloning a tree shares its nodes with the clone as `snapshot` does, besides copying the list
+/// of released slots. Neither copies a node, but both take time proportional to the number of
+/// nodes divided by 64.
+///
+/// `{:?}` formats the keys as a set, in ascending order. `{:#?}` shows the nodes instead.
+#[derive(Clone)]
+pub struct BTree<T, C = OrdComparator> {
+    // All nodes of the tree. Nodes refer to their children by index into it.
+    nodes: Arena<T>,
+    root: NodeId,
+    // Decides the order of keys.
+    cmp: Counted<C>,
+    split_policy: SplitPolicy,
+    duplicate_policy: DuplicatePolicy,
+    // The percentage of `order - 1` keys below which a node is refilled on removal.
+    min_fill: usize,
+}
+
+/// What `BTree::merge_iter` yields for a key which is in both trees.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MergePolicy {
+    /// Yield both keys, the one from the tree `merge_iter` is called on first.
+    KeepBoth,
+    /// Yield only the key from the tree `merge_iter` is called on.
+    KeepSelf,
+    /// Yield only the key from the other tree.
+    KeepOther,
+}
+
+/// How a `BTree` makes room when inserting a key overflows a node.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SplitPolicy {
+    /// Split the node into two half-full nodes.
+    #[default]
+    Split,
+    /// Move keys into an adjacent sibling with room, and split the node only if both are full.
+    Redistribute,
+    /// Move keys into an adjacent sibling with room, or if both nodes are full, split the two
+    /// of them into three nodes two-thirds full, like a B*-tree. Nodes are fuller, so the tree
+    /// takes less memory, but insertions move more keys around.
+    BStar,
+}
+
+/// What inserting a key into a `BTree` does when an equal key is present already.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Keep the key present and drop the new one.
+    #[default]
+    Ignore,
+    /// Replace the key present with the new one, as `BTree::replace` does.
+    Replace,
+    /// Fail: `BTree::try_insert` returns an error and `BTree::insert` panics.
+    Error,
+    /// Keep both keys, the new one after those equal to it, so that the tree is a multiset.
+    /// `get` and `remove` find any one of equal keys, and `rank`, `count_range` and the
+    /// `get_lt` family take all of them into account. Set operations such as `difference`,
+    /// and `extract_if`, expect keys to be unique.
+    Allow,
 }
 
 impl<T> BTree<T>
 where
     T: Ord + Clone,
 {
+    /// Create an empty tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_ORDER`. Use `try_new` to handle it as an error.
     pub fn new(order: usize) -> Self {
+        Self::with_comparator(order, OrdComparator)
+    }
+
+    /// Create an empty tree, failing if `order` is less than `MIN_ORDER`.
+    pub fn try_new(order: usize) -> Result<Self, InvalidOrder> {
+        Self::try_with_comparator(order, OrdComparator)
+    }
+
+    /// Build a tree from keys yielded in ascending order.
+    /// The tree is constructed bottom-up in linear time, which is much faster than inserting the keys one by one.
+    /// Consecutive duplicates are dropped, but the keys must otherwise be sorted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_ORDER`.
+    pub fn from_sorted_iter<I>(iter: I, order: usize) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        Self::build_sorted(iter, order, |nodes, keys| {
+            nodes.build_from_sorted_keys(keys, order)
+        })
+    }
+
+    /// Build a tree like `from_sorted_iter`, building runs of leaves on all available cores.
+    /// Only the upper levels, a small fraction of the nodes, are built on the calling thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_ORDER`.
+    #[cfg(feature = "std")]
+    pub fn par_from_sorted_iter<I>(iter: I, order: usize) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Send,
+    {
+        let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+        Self::build_sorted(iter, order, |nodes, keys| {
+            nodes.par_build_from_sorted_keys(keys, order, threads)
+        })
+    }
+
+    fn build_sorted<I>(
+        iter: I,
+        order: usize,
+        build: impl FnOnce(&mut Arena<T>, Vec<T>) -> NodeId,
+    ) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        if let Err(error) = check_order(order) {
+            panic!("{}", error);
+        }
+        let mut keys: Vec<T> = iter.into_iter().collect();
+        keys.dedup();
+        debug_assert!(
+            keys.windows(2).all(|pair| pair[0] < pair[1]),
+            "keys must be sorted in ascending order"
+        );
+        let mut nodes = Arena::new();
+        let root = build(&mut nodes, keys);
         Self {
-            root: Box::new(Node::new(order)),
+            nodes,
+            root,
+            cmp: Counted::new(OrdComparator),
+            split_policy: SplitPolicy::default(),
+            duplicate_policy: DuplicatePolicy::default(),
+            min_fill: DEFAULT_MIN_FILL,
         }
     }
+}
 
-    /// Search a node for a given key.
-    pub fn get<'a>(&self, key: &'a T) -> Option<&'a T> {
-        self.root.get(key)
+impl<T, C> BTree<T, C>
+where
+    T: Clone,
+    C: Comparator<T>,
+{
+    /// Create an empty tree whose keys are ordered by a given comparator instead of `Ord`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_ORDER`.
+    pub fn with_comparator(order: usize, cmp: C) -> Self {
+        Self::try_with_comparator(order, cmp).unwrap_or_else(|error| panic!("{}", error))
     }
 
-    pub fn insert(&mut self, key: T) {
-        self.root.insert(key);
-        if self.root.is_overflow() {
-            let index = self.root.order / 2;
-            let child_kind = if self.root.children.len() == 0 {
-                NodeKind::Leaf
-            } else {
-                NodeKind::Internal
-            };
-            let left_child = Node {
-                order: self.root.order,
-                kind: child_kind,
-                // Remove `to_vec()` to aviod requiring T to implement `Clone`.
-                keys: self.root.keys[..index].to_vec(),
-                children: if self.root.children.len() != 0 {
-                    self.root.children[..index + 1].to_vec()
-                } else {
-                    vec![]
-                },
-            };
-            let right_child = Node {
-                order: self.root.order,
-                kind: child_kind,
-                keys: self.root.keys[index + 1..].to_vec(),
-                children: if self.root.children.len() != 0 {
-                    self.root.children[index + 1..].to_vec()
-                } else {
-                    vec![]
-                },
+    /// Create an empty tree ordered by a given comparator, failing if `order` is less than `MIN_ORDER`.
+    pub fn try_with_comparator(order: usize, cmp: C) -> Result<Self, InvalidOrder> {
+        check_order(order)?;
+        let mut nodes = Arena::new();
+        let root = nodes.allocate(Node::new(order));
+        Ok(Self {
+            nodes,
+            root,
+            cmp: Counted::new(cmp),
+            split_policy: SplitPolicy::default(),
+            duplicate_policy: DuplicatePolicy::default(),
+            min_fill: DEFAULT_MIN_FILL,
+        })
+    }
+
+    /// Returns a copy of the tree without copying any node. Both trees share their nodes, and
+    /// a change to either of them copies the chunk of nodes it writes to, unless it is not shared.
+    /// Nodes are shared in chunks of 64, and the snapshot copies a pointer to each chunk, so it
+    /// takes O(n / 64) time for n nodes rather than constant time.
+    pub fn snapshot(&self) -> Self
+    where
+        C: Clone,
+    {
+        BTree {
+            nodes: self.nodes.snapshot(),
+            root: self.root,
+            cmp: Counted::new(self.cmp.inner.clone()),
+            split_policy: self.split_policy,
+            duplicate_policy: self.duplicate_policy,
+            min_fill: self.min_fill,
+        }
+    }
+
+    /// Move all keys of another tree into this one.
+    /// If both trees contain an equal key, the one from `other` is kept.
+    ///
+    /// If both trees have the same order and all keys of one of them are less than those of
+    /// the other, the nodes of the smaller tree are moved over and the trees are joined along
+    /// the border of the higher one, so only nodes along that border are restructured.
+    /// Otherwise the keys of both trees are merged and the tree is rebuilt in linear time.
+    pub fn append(&mut self, mut other: BTree<T, C>) {
+        let order = self.nodes[self.root].order;
+        if other.nodes[other.root].order == order {
+            if other.is_empty() {
+                return;
+            }
+            if self.is_empty() {
+                mem::swap(&mut self.nodes, &mut other.nodes);
+                mem::swap(&mut self.root, &mut other.root);
+                #[cfg(feature = "metrics")]
+                mem::swap(&mut self.nodes.counters, &mut other.nodes.counters);
+                return;
+            }
+            let is_before = |left: &BTree<T, C>, right: &BTree<T, C>| {
+                let mut last = vec![];
+                left.nodes.largest(left.root, 1, &mut last);
+                let first = right.first().unwrap();
+                self.cmp.compare(last[0], first) == Ordering::Less
             };
-            let root = Node {
-                order: self.root.order,
-                kind: NodeKind::Root,
-                keys: vec![self.root.keys[index].clone()],
-                children: vec![left_child, right_child],
+            let self_is_lower = if is_before(self, &other) {
+                true
+            } else if is_before(&other, self) {
+                false
+            } else {
+                return self.merge_and_rebuild(other);
             };
-            self.root = Box::new(root);
+            self.graft(other, self_is_lower);
+            return;
         }
+        self.merge_and_rebuild(other);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::btree::BTree;
-    use crate::node::{Node, NodeKind};
-    use rand::Rng;
+    /// Join another tree whose keys are all greater than those of this one, or all less if
+    /// `self_is_lower` is false. Both trees must have the same order and hold keys.
+    fn graft(&mut self, mut other: BTree<T, C>, mut self_is_lower: bool) {
+        // Move the nodes of the smaller tree into the arena of the larger one.
+        if self.len() < other.len() {
+            mem::swap(&mut self.nodes, &mut other.nodes);
+            mem::swap(&mut self.root, &mut other.root);
+            #[cfg(feature = "metrics")]
+            mem::swap(&mut self.nodes.counters, &mut other.nodes.counters);
+            self_is_lower = !self_is_lower;
+        }
+        let moved = other.nodes.transfer(other.root, &mut self.nodes);
+        let (mut lower, higher) = if self_is_lower {
+            (self.root, moved)
+        } else {
+            (moved, self.root)
+        };
+        let separator = self.nodes.remove_last(lower, self.min_keys());
+        shrink_root(&mut self.nodes, &mut lower);
+        self.root = self.nodes.join(lower, separator, higher);
+        shrink_root(&mut self.nodes, &mut self.root);
+        if self.nodes[self.root].is_overflow() {
+            self.grow();
+            self.nodes.split_children(self.root, 0);
+        }
+    }
 
-    // Asserts given B-Tree is valid.
-    fn is_valid_btree<T: Ord>(node: &Node<T>) -> bool {
-        assert!(node.keys.len() < node.order);
-        assert!(node.children.len() < node.order + 1);
-        match node.kind {
-            // A root node must have more than 2 children.
-            NodeKind::Root => assert!(node.children.len() >= 2),
-            // A internal node must have more than ceil(order / 2).
-            NodeKind::Internal => assert!(node.children.len() >= (node.order + 1) / 2),
-            // A leaf node must have no child.
-            NodeKind::Leaf => assert!(node.children.len() == 0),
+    /// Merge the keys of another tree into this one, rebuilding it bottom-up.
+    fn merge_and_rebuild(&mut self, mut other: BTree<T, C>) {
+        let order = self.nodes[self.root].order;
+        let mut keys = vec![];
+        self.nodes.take_sorted_keys(self.root, &mut keys);
+        let mut other_keys = vec![];
+        other.nodes.take_sorted_keys(other.root, &mut other_keys);
+        let keys = merge_sorted(keys, other_keys, &self.cmp);
+        self.nodes.clear();
+        self.root = self.nodes.build_from_sorted_keys(keys, order);
+    }
+
+    /// Split the tree at a given key, returning a new tree with all keys greater than or
+    /// equal to it. Only nodes on the paths to the key are restructured, but the nodes of the
+    /// new tree are moved to its own arena, which takes time proportional to their number.
+    pub fn split_off(&mut self, key: &T) -> BTree<T, C>
+    where
+        C: Clone,
+    {
+        let right_root = self.nodes.split_off(self.root, key, false, &self.cmp);
+        self.nodes.fix_right_border(self.root);
+        shrink_root(&mut self.nodes, &mut self.root);
+        let mut nodes = Arena::new();
+        let mut root = self.nodes.transfer(right_root, &mut nodes);
+        nodes.fix_left_border(root);
+        shrink_root(&mut nodes, &mut root);
+        BTree {
+            nodes,
+            root,
+            cmp: Counted::new(self.cmp.inner.clone()),
+            split_policy: self.split_policy,
+            duplicate_policy: self.duplicate_policy,
+            min_fill: self.min_fill,
         }
-        if node.kind != NodeKind::Leaf {
-            // If a node except leaf has `k` keys, it must have `k + 1` children.
-            assert!(node.keys.len() + 1 == node.children.len());
+    }
+
+    /// Remove all keys within a range, returning how many there were. The tree is cut along
+    /// the paths to both ends of the range, and the parts outside it are joined again, so only
+    /// nodes along those paths are restructured besides the removed ones.
+    /// A reversed range holds no key.
+    pub fn remove_range<R>(&mut self, range: R) -> usize
+    where
+        R: RangeBounds<T>,
+    {
+        let order = self.order();
+        // Cut the tree into the keys before the range, those within it and those after it.
+        let (before, within) = match range.start_bound() {
+            Bound::Included(key) => {
+                let within = self.nodes.split_off(self.root, key, false, &self.cmp);
+                (Some(self.root), within)
+            }
+            Bound::Excluded(key) => {
+                let within = self.nodes.split_off(self.root, key, true, &self.cmp);
+                (Some(self.root), within)
+            }
+            Bound::Unbounded => (None, self.root),
+        };
+        let after = match range.end_bound() {
+            Bound::Included(key) => Some(self.nodes.split_off(within, key, true, &self.cmp)),
+            Bound::Excluded(key) => Some(self.nodes.split_off(within, key, false, &self.cmp)),
+            Bound::Unbounded => None,
+        };
+        let mut removed = vec![];
+        self.nodes.take_sorted_keys(within, &mut removed);
+
+        // Refill the borders left underfull by the cuts, and drop parts without keys.
+        let before = before.and_then(|mut root| {
+            self.nodes.fix_right_border(root);
+            shrink_root(&mut self.nodes, &mut root);
+            self.take_nonempty(root)
+        });
+        let after = after.and_then(|mut root| {
+            self.nodes.fix_left_border(root);
+            shrink_root(&mut self.nodes, &mut root);
+            self.take_nonempty(root)
+        });
+        self.root = match (before, after) {
+            (Some(mut before), Some(after)) => {
+                let separator = self.nodes.remove_last(before, self.min_keys());
+                shrink_root(&mut self.nodes, &mut before);
+                self.nodes.join(before, separator, after)
+            }
+            (Some(root), None) | (None, Some(root)) => root,
+            (None, None) => self.nodes.allocate(Node::new(order)),
+        };
+        shrink_root(&mut self.nodes, &mut self.root);
+        if self.nodes[self.root].is_overflow() {
+            self.grow();
+            self.nodes.split_children(self.root, 0);
+        }
+        removed.len()
+    }
+
+    /// Returns a subtree if it has any key, releasing it otherwise.
+    fn take_nonempty(&mut self, root: NodeId) -> Option<NodeId> {
+        if self.nodes[root].size > 0 {
+            return Some(root);
+        }
+        self.nodes.take_sorted_keys(root, &mut vec![]);
+        None
+    }
+
+    /// Rebuild the tree bottom-up in linear time, with nodes as full as in `from_sorted_iter`.
+    /// Removals, and insertions in a skewed order, can leave nodes sparse.
+    pub fn rebalance(&mut self) {
+        let order = self.nodes[self.root].order;
+        let mut keys = vec![];
+        self.nodes.take_sorted_keys(self.root, &mut keys);
+        self.nodes.clear();
+        self.root = self.nodes.build_from_sorted_keys(keys, order);
+    }
+
+    /// Free memory the tree holds but does not use, in nodes with room for more keys, in nodes
+    /// released by removals and in slots of nodes left to the other tree by `split_off`.
+    /// Node ids change, so this takes time proportional to the size.
+    pub fn shrink_to_fit(&mut self) {
+        self.root = self.nodes.shrink_to_fit(self.root);
+    }
+
+    /// Returns how nodes overflowing on insertion make room.
+    pub fn split_policy(&self) -> SplitPolicy {
+        self.split_policy
+    }
+
+    /// Choose how nodes overflowing on insertion make room. Nodes already in the tree are left as they are.
+    pub fn set_split_policy(&mut self, policy: SplitPolicy) {
+        self.split_policy = policy;
+    }
+
+    /// Returns what inserting a key does when an equal key is present already.
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    /// Choose what inserting a key does when an equal key is present already.
+    /// Keys already in the tree are left as they are.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.duplicate_policy = policy;
+    }
+
+    /// Returns the percentage of keys a node can hold below which removals refill it.
+    pub fn min_fill(&self) -> usize {
+        self.min_fill
+    }
+
+    /// Choose the percentage of keys a node can hold below which removals refill it, by
+    /// borrowing keys from a sibling or merging with it. A higher percentage refills nodes
+    /// more often and keeps them fuller, while a lower one saves work on removals at the cost
+    /// of sparser nodes. It defaults to 50. Nodes already in the tree are left as they are.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `percent` is 0 or more than 100.
+    pub fn set_min_fill(&mut self, percent: usize) {
+        assert!(
+            (1..=100).contains(&percent),
+            "minimum fill must be between 1 and 100 percent"
+        );
+        self.min_fill = percent;
+    }
+
+    /// Returns the number of keys below which a node is refilled, keeping at least one key.
+    fn min_keys(&self) -> usize {
+        ((self.order() - 1) * self.min_fill / 100).max(1)
+    }
+
+    /// Remove a key from the tree, returning whether it was present.
+    pub fn remove(&mut self, key: &T) -> bool {
+        self.take(key).is_some()
+    }
+
+    /// Remove a key from the tree, returning the key which was stored in it.
+    pub fn take(&mut self, key: &T) -> Option<T> {
+        let removed = self
+            .nodes
+            .remove(self.root, key, &self.cmp, self.min_keys());
+        shrink_root(&mut self.nodes, &mut self.root);
+        removed
+    }
+
+    /// Remove many keys at once, returning how many of them were present.
+    /// A batch large enough to outweigh the size of the tree is removed in a single pass over
+    /// the keys of the tree, which is then rebuilt bottom-up once instead of being rebalanced
+    /// after each removal. Smaller batches are removed one by one.
+    pub fn remove_many<I>(&mut self, iter: I) -> usize
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut removed_keys: Vec<T> = iter.into_iter().collect();
+        let len = self.len();
+        // Removing keys one by one takes about log2(len) comparisons each, and rebuilding
+        // takes time proportional to the size.
+        let log_len = (usize::BITS - len.leading_zeros()) as usize;
+        if removed_keys.len() * log_len < len {
+            return removed_keys.iter().filter(|key| self.remove(key)).count();
+        }
+        removed_keys.sort_by(|a, b| self.cmp.compare(a, b));
+        let order = self.nodes[self.root].order;
+        let mut keys = Vec::with_capacity(len);
+        self.nodes.take_sorted_keys(self.root, &mut keys);
+        let mut removed_keys = removed_keys.into_iter().peekable();
+        keys.retain(|key| {
+            while removed_keys
+                .next_if(|removed| self.cmp.compare(removed, key) == Ordering::Less)
+                .is_some()
+            {}
+            // Each removed key takes out a single equal key, as `remove` does.
+            removed_keys
+                .next_if(|removed| self.cmp.compare(removed, key) == Ordering::Equal)
+                .is_none()
+        });
+        self.nodes.clear();
+        self.root = self.nodes.build_from_sorted_keys(keys, order);
+        len - self.len()
+    }
+
+    /// Returns an iterator which removes and yields the keys matching a predicate.
+    /// Keys are visited in ascending order, and only as far as the iterator is advanced;
+    /// keys not visited yet stay in the tree when it is dropped.
+    pub fn extract_if<F>(&mut self, pred: F) -> ExtractIf<'_, T, C, F>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        ExtractIf {
+            tree: self,
+            pred,
+            last: None,
+        }
+    }
+
+    /// Returns an iterator over keys in ascending order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter::new(&self.nodes, self.root)
+    }
+
+    /// Consume the tree, moving its keys out in ascending order without cloning them.
+    pub fn into_sorted_vec(mut self) -> Vec<T> {
+        let mut keys = Vec::with_capacity(self.len());
+        self.nodes.take_sorted_keys(self.root, &mut keys);
+        keys
+    }
+
+    /// Returns a cursor at the ghost position, so that `next` moves it to the first key
+    /// and `prev` to the last one.
+    pub fn cursor(&self) -> Cursor<'_, T, C> {
+        Cursor {
+            nodes: &self.nodes,
+            root: self.root,
+            cmp: &self.cmp,
+            position: Position::default(),
+        }
+    }
+
+    /// Returns a cursor which can insert and remove keys, at the ghost position.
+    pub fn cursor_mut(&mut self) -> CursorMut<'_, T, C> {
+        CursorMut {
+            tree: self,
+            position: Position::default(),
+        }
+    }
+
+    /// Returns an iterator over keys in this tree but not in `other`, in ascending order.
+    pub fn difference<'a>(&'a self, other: &'a BTree<T, C>) -> Difference<'a, T, C> {
+        Difference {
+            iter: self.iter(),
+            other: other.iter().peekable(),
+            cmp: &self.cmp,
+        }
+    }
+
+    /// Returns an iterator over keys in exactly one of this tree and `other`, in ascending order.
+    pub fn symmetric_difference<'a>(
+        &'a self,
+        other: &'a BTree<T, C>,
+    ) -> SymmetricDifference<'a, T, C> {
+        SymmetricDifference {
+            iter: self.iter().peekable(),
+            other: other.iter().peekable(),
+            cmp: &self.cmp,
+        }
+    }
+
+    /// Returns an iterator over keys of both this tree and `other` in ascending order,
+    /// without building their union. `policy` decides what to yield for keys in both trees.
+    pub fn merge_iter<'a>(
+        &'a self,
+        other: &'a BTree<T, C>,
+        policy: MergePolicy,
+    ) -> MergeIter<'a, T, C> {
+        MergeIter {
+            iter: self.iter().peekable(),
+            other: other.iter().peekable(),
+            cmp: &self.cmp,
+            policy,
+        }
+    }
+
+    /// Returns whether every key of this tree is also in `other`.
+    pub fn is_subset(&self, other: &BTree<T, C>) -> bool {
+        let mut other = other.iter();
+        'keys: for key in self.iter() {
+            for other_key in other.by_ref() {
+                match self.cmp.compare(other_key, key) {
+                    Ordering::Less => {}
+                    Ordering::Equal => continue 'keys,
+                    Ordering::Greater => return false,
+                }
+            }
+            return false;
         }
-        // Check if each child node satisfies requirements to be B-Tree.
-        assert!(node
-            .children
-            .iter()
-            .all(|tree| { node.order == tree.order && is_valid_btree(&tree) }));
         true
     }
 
-    #[test]
-    fn valid_leaf() {
-        let tree = Node {
-            order: 3,
-            kind: NodeKind::Leaf,
-            keys: vec![1, 2],
-            children: vec![],
-        };
-        is_valid_btree(&tree);
+    /// Returns whether every key of `other` is also in this tree.
+    pub fn is_superset(&self, other: &BTree<T, C>) -> bool {
+        other.is_subset(self)
     }
 
-    #[test]
-    #[should_panic]
-    fn invalid_leaf() {
-        let tree = Node {
-            order: 3,
-            kind: NodeKind::Leaf,
-            keys: vec![1, 2, 3],
-            children: vec![],
-        };
-        is_valid_btree(&tree);
+    /// Returns whether this tree and `other` have no key in common.
+    pub fn is_disjoint(&self, other: &BTree<T, C>) -> bool {
+        let mut iter = self.iter().peekable();
+        let mut other = other.iter().peekable();
+        while let (Some(key), Some(other_key)) = (iter.peek(), other.peek()) {
+            match self.cmp.compare(key, other_key) {
+                Ordering::Less => {
+                    iter.next();
+                }
+                Ordering::Greater => {
+                    other.next();
+                }
+                Ordering::Equal => return false,
+            }
+        }
+        true
     }
 
-    #[test]
-    fn valid_tree() {
-        let tree = Node {
-            order: 4,
-            kind: NodeKind::Root,
-            keys: vec![4],
-            children: vec![
-                Node {
-                    order: 4,
-                    kind: NodeKind::Internal,
-                    keys: vec![2],
-                    children: vec![
-                        Node {
-                            order: 4,
-                            kind: NodeKind::Leaf,
-                            keys: vec![1],
-                            children: vec![],
-                        },
-                        Node {
-                            order: 4,
-                            kind: NodeKind::Leaf,
-                            keys: vec![3],
-                            children: vec![],
-                        },
-                    ],
-                },
-                Node {
-                    order: 4,
-                    kind: NodeKind::Internal,
-                    keys: vec![6, 8],
-                    children: vec![
-                        Node {
-                            order: 4,
-                            kind: NodeKind::Leaf,
-                            keys: vec![5],
-                            children: vec![],
-                        },
-                        Node {
-                            order: 4,
-                            kind: NodeKind::Leaf,
-                            keys: vec![7],
-                            children: vec![],
-                        },
-                        Node {
-                            order: 4,
-                            kind: NodeKind::Leaf,
-                            keys: vec![9, 10],
-                            children: vec![],
-                        },
-                    ],
-                },
-            ],
+    /// Returns the number of keys in the tree.
+    pub fn len(&self) -> usize {
+        self.nodes[self.root].size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of children each node can hold.
+    pub fn order(&self) -> usize {
+        self.nodes[self.root].order
+    }
+
+    /// Returns an estimate of the bytes the tree takes, to help choose an order.
+    /// A higher order usually needs fewer bytes per key, but leaves more room unused in nodes.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.nodes.memory_usage(self.root)
+    }
+
+    /// Returns the counts of operations on the tree since it was created.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> Metrics {
+        let counters = &self.nodes.counters;
+        Metrics {
+            splits: counters.splits,
+            merges: counters.merges,
+            rotations: counters.rotations,
+            allocations: counters.allocations,
+            comparisons: self.cmp.comparisons.load(atomic::Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the `index`-th smallest key, counting from zero.
+    /// Each node knows how many keys its subtree holds, so this takes O(log n).
+    pub fn select(&self, index: usize) -> Option<&T> {
+        self.nodes.select(self.root, index)
+    }
+
+    /// Returns the `index`-th smallest key, counting from zero, in O(log n).
+    /// The same as `select`, named after `Iterator::nth`, which would visit `index` keys first.
+    pub fn nth(&self, index: usize) -> Option<&T> {
+        self.select(index)
+    }
+
+    /// Returns a key chosen uniformly at random, found in O(log n) by the key counts of subtrees.
+    #[cfg(feature = "rand")]
+    pub fn sample<R>(&self, rng: &mut R) -> Option<&T>
+    where
+        R: Rng + ?Sized,
+    {
+        if self.is_empty() {
+            return None;
+        }
+        self.select(rng.gen_range(0, self.len()))
+    }
+
+    /// Returns `n` distinct keys chosen uniformly at random in random order, or all keys if
+    /// the tree has fewer. Each key is found in O(log n) by the key counts of subtrees.
+    #[cfg(feature = "rand")]
+    pub fn sample_n<R>(&self, n: usize, rng: &mut R) -> Vec<&T>
+    where
+        R: Rng + ?Sized,
+    {
+        rand::seq::index::sample(rng, self.len(), n.min(self.len()))
+            .into_iter()
+            .map(|index| self.select(index).unwrap())
+            .collect()
+    }
+
+    /// Returns the number of keys in the tree less than a given key.
+    pub fn rank(&self, key: &T) -> usize {
+        self.nodes.rank(self.root, key, false, &self.cmp)
+    }
+
+    /// Returns the number of keys within a range in O(log n), counting them from the key counts
+    /// of subtrees instead of visiting them. A reversed range holds no key.
+    pub fn count_range<R>(&self, range: R) -> usize
+    where
+        R: RangeBounds<T>,
+    {
+        // The number of keys up to the bound, including the bound itself if `inclusive`.
+        let count_up_to =
+            |key: &T, inclusive: bool| self.nodes.rank(self.root, key, inclusive, &self.cmp);
+        let start = match range.start_bound() {
+            Bound::Included(key) => count_up_to(key, false),
+            Bound::Excluded(key) => count_up_to(key, true),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => count_up_to(key, true),
+            Bound::Excluded(key) => count_up_to(key, false),
+            Bound::Unbounded => self.len(),
         };
-        is_valid_btree(&tree);
+        end.saturating_sub(start)
     }
 
-    #[test]
-    fn get_tree() {
-        let tree = Node {
-            order: 4,
-            kind: NodeKind::Root,
-            keys: vec![4],
-            children: vec![
-                Node {
-                    order: 4,
-                    kind: NodeKind::Internal,
-                    keys: vec![2],
-                    children: vec![
-                        Node {
-                            order: 4,
-                            kind: NodeKind::Leaf,
-                            keys: vec![1],
-                            children: vec![],
-                        },
-                        Node {
-                            order: 4,
-                            kind: NodeKind::Leaf,
-                            keys: vec![3],
-                            children: vec![],
-                        },
-                    ],
-                },
-                Node {
-                    order: 4,
-                    kind: NodeKind::Internal,
-                    keys: vec![6, 8],
-                    children: vec![
-                        Node {
-                            order: 4,
-                            kind: NodeKind::Leaf,
-                            keys: vec![5],
-                            children: vec![],
-                        },
-                        Node {
-                            order: 4,
-                            kind: NodeKind::Leaf,
-                            keys: vec![7],
-                            children: vec![],
-                        },
-                        Node {
-                            order: 4,
-                            kind: NodeKind::Leaf,
-                            keys: vec![9, 10],
-                            children: vec![],
-                        },
-                    ],
-                },
-            ],
+    /// Returns the largest key less than a given key.
+    pub fn get_lt(&self, key: &T) -> Option<&T> {
+        self.nodes.last_below(self.root, key, false, &self.cmp)
+    }
+
+    /// Returns the largest key less than or equal to a given key.
+    pub fn get_le(&self, key: &T) -> Option<&T> {
+        self.nodes.last_below(self.root, key, true, &self.cmp)
+    }
+
+    /// Returns the smallest key greater than a given key.
+    pub fn get_gt(&self, key: &T) -> Option<&T> {
+        self.nodes.first_above(self.root, key, false, &self.cmp)
+    }
+
+    /// Returns the smallest key greater than or equal to a given key.
+    pub fn get_ge(&self, key: &T) -> Option<&T> {
+        self.nodes.first_above(self.root, key, true, &self.cmp)
+    }
+
+    /// Returns the key right before a key of the tree, or `None` if the key is the first one
+    /// or is not in the tree. Unlike `get_lt`, the key must be present.
+    pub fn predecessor(&self, key: &T) -> Option<&T> {
+        self.get(key)?;
+        self.get_lt(key)
+    }
+
+    /// Returns the key right after a key of the tree, or `None` if the key is the last one
+    /// or is not in the tree. Unlike `get_gt`, the key must be present.
+    pub fn successor(&self, key: &T) -> Option<&T> {
+        self.get(key)?;
+        self.get_gt(key)
+    }
+
+    /// Returns the smallest key.
+    pub fn first(&self) -> Option<&T> {
+        self.nodes.first(self.root)
+    }
+
+    /// Returns the `k` largest keys in descending order, walking back from the last key
+    /// without visiting the rest of the tree.
+    pub fn top_k(&self, k: usize) -> Vec<&T> {
+        let mut keys = Vec::with_capacity(k.min(self.len()));
+        self.nodes.largest(self.root, k, &mut keys);
+        keys
+    }
+
+    /// Returns the `k` smallest keys in ascending order, stopping the walk after them.
+    pub fn bottom_k(&self, k: usize) -> Vec<&T> {
+        self.iter().take(k).collect()
+    }
+
+    /// Remove the smallest key and return it.
+    /// Repeated calls, as when the tree serves as a priority queue, take keys straight from
+    /// the leftmost leaf while it has keys to spare, without descending from the root.
+    pub fn pop_first(&mut self) -> Option<T> {
+        if let Some(key) = self.nodes.pop_first(self.root, self.min_keys()) {
+            return Some(key);
+        }
+        let first = self.first()?.clone();
+        self.take(&first)
+    }
+
+    /// Search the tree for a given key, returning the key stored in the tree.
+    /// It differs from the given one only if the comparator ignores some of its contents.
+    pub fn get(&self, key: &T) -> Option<&T> {
+        self.nodes.get(self.root, key, &self.cmp)
+    }
+
+    /// Returns the stored key equal to a given one.
+    /// Callers must not change the key in a way which changes how it orders.
+    pub(crate) fn get_mut(&mut self, key: &T) -> Option<&mut T> {
+        self.nodes.get_mut(self.root, key, &self.cmp)
+    }
+
+    /// Insert a key, replacing an equal key already in the tree and returning it.
+    /// Unlike `insert`, this updates fields of the key which the comparator ignores.
+    pub fn replace(&mut self, key: T) -> Option<T> {
+        match self.get_mut(&key) {
+            Some(stored) => Some(mem::replace(stored, key)),
+            None => {
+                self.insert_key(key, false);
+                None
+            }
+        }
+    }
+
+    /// Insert a key. If an equal key is present already, the duplicate policy of the tree
+    /// decides what happens.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the policy is `DuplicatePolicy::Error` and an equal key is present.
+    pub fn insert(&mut self, key: T) {
+        if self.try_insert(key).is_err() {
+            panic!("an equal key is present already");
+        }
+    }
+
+    /// Insert a key as `insert` does, but give it back in an error instead of panicking
+    /// if the policy is `DuplicatePolicy::Error` and an equal key is present.
+    pub fn try_insert(&mut self, key: T) -> Result<(), DuplicateKeyError<T>> {
+        match self.duplicate_policy {
+            DuplicatePolicy::Ignore => {
+                self.insert_key(key, false);
+            }
+            DuplicatePolicy::Replace => {
+                self.replace(key);
+            }
+            DuplicatePolicy::Error if self.get(&key).is_some() => {
+                return Err(DuplicateKeyError { key });
+            }
+            DuplicatePolicy::Error => {
+                self.insert_key(key, false);
+            }
+            DuplicatePolicy::Allow => {
+                self.insert_key(key, true);
+            }
+        }
+        Ok(())
+    }
+
+    /// Insert a key without looking at the duplicate policy.
+    /// A key smaller than all others goes straight into the leftmost leaf if it has room,
+    /// so that keys inserted in descending order do not descend from the root each time.
+    fn insert_key(&mut self, key: T, allow_duplicates: bool) {
+        let key = match self.nodes.push_first(self.root, key, &self.cmp) {
+            Ok(()) => return,
+            Err(key) => key,
         };
-        // The tree contains 1, 2, ..., 10.
-        for i in 1..=10 {
-            assert_eq!(tree.get(&i), Some(&i));
+        self.nodes.insert(
+            self.root,
+            key,
+            &self.cmp,
+            self.split_policy,
+            allow_duplicates,
+        );
+        if self.nodes[self.root].is_overflow() {
+            self.grow();
+            self.nodes.split_children(self.root, 0);
         }
-        assert_eq!(tree.get(&11), None);
     }
 
-    fn generate_random_keys() -> Vec<u32> {
-        let mut rng = rand::thread_rng();
-        let mut keys = vec![];
-        let key_num = 100;
-        let key_range = 1000;
-        for _ in 0..key_num {
-            keys.push(rng.gen_range(0, key_range));
+    /// Insert many keys at once, returning how many of them were not present yet.
+    /// The keys are sorted first, and the tree is walked once, inserting the keys which fall
+    /// into the same leaf together instead of descending from the root for each key.
+    /// Unless the duplicate policy is `DuplicatePolicy::Ignore`, keys are inserted one by one
+    /// as `insert` does.
+    pub fn insert_many<I>(&mut self, iter: I) -> usize
+    where
+        I: IntoIterator<Item = T>,
+    {
+        if self.duplicate_policy != DuplicatePolicy::Ignore {
+            let mut added = 0;
+            for key in iter {
+                added += usize::from(self.get(&key).is_none());
+                self.insert(key);
+            }
+            return added;
         }
-        keys
+        let mut keys: Vec<T> = iter.into_iter().collect();
+        keys.sort_by(|a, b| self.cmp.compare(a, b));
+        keys.dedup_by(|a, b| self.cmp.compare(a, b) == Ordering::Equal);
+        let added = self.nodes.insert_sorted(self.root, keys, &self.cmp);
+        // The root may overflow so much that it takes more than one new level.
+        while self.nodes[self.root].keys.len() >= self.nodes[self.root].order {
+            self.grow();
+            self.nodes.split_overflowing(self.root, 0);
+        }
+        added
     }
 
-    #[test]
-    fn insert_elements() {
-        let keys = generate_random_keys();
-        let mut tree = BTree::new(4);
-        keys.iter().for_each(|key| tree.insert(key.clone()));
-        is_valid_btree(&*tree.root);
-        for key in &keys {
-            assert_eq!(tree.get(key), Some(key));
+    /// Put a new root above the current one, which the caller splits.
+    fn grow(&mut self) {
+        self.nodes.make_child(self.root);
+        let old_root = &self.nodes[self.root];
+        let mut new_root = Node::new(old_root.order);
+        new_root.size = old_root.size;
+        new_root.children.push(self.root);
+        self.root = self.nodes.allocate(new_root);
+    }
+}
+
+impl<T> BTree<T, OrdComparator>
+where
+    T: AsRef<[u8]>,
+{
+    /// Returns an iterator over the keys starting with a prefix, in ascending order.
+    /// It starts at the first such key, found in O(log n), and stops at the first key without
+    /// the prefix. This relies on keys being ordered by their bytes, as `Ord` orders strings
+    /// and byte vectors, so trees ordered by another comparator cannot scan by prefix:
+    ///
+    /// ```compile_fail
+    /// use rusty_btree::btree::BTree;
+    ///
+    /// let tree = BTree::with_comparator(4, |a: &String, b: &String| b.cmp(a));
+    /// tree.scan_prefix("ab");
+    /// ```
+    pub fn scan_prefix<'a, 'p, P>(&'a self, prefix: &'p P) -> ScanPrefix<'a, 'p, T>
+    where
+        P: AsRef<[u8]> + ?Sized,
+    {
+        let prefix = prefix.as_ref();
+        let mut position = Position::default();
+        position.descend(&self.nodes, self.root, |probe| probe.as_ref().cmp(prefix));
+        ScanPrefix {
+            nodes: &self.nodes,
+            root: self.root,
+            position,
+            prefix,
         }
     }
+}
 
-    #[test]
-    fn test_tree_contents() {
-        let mut keys = generate_random_keys();
-        let mut tree = BTree::new(18);
-        keys.iter().for_each(|key| tree.insert(key.clone()));
-        is_valid_btree(&*tree.root);
+impl<'a, T, C> IntoIterator for &'a BTree<T, C>
+where
+    T: Clone,
+    C: Comparator<T>,
+{
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T> From<BTreeSet<T>> for BTree<T>
+where
+    T: Ord + Clone,
+{
+    /// Build a tree of `DEFAULT_ORDER` in linear time, as keys of a set are sorted already.
+    fn from(set: BTreeSet<T>) -> Self {
+        Self::from_sorted_iter(set, DEFAULT_ORDER)
+    }
+}
+
+impl<T> From<Vec<T>> for BTree<T>
+where
+    T: Ord + Clone,
+{
+    /// Build a tree of `DEFAULT_ORDER` from keys in any order, keeping the first of equal keys.
+    fn from(mut keys: Vec<T>) -> Self {
         keys.sort();
-        keys.dedup();
-        assert_eq!(keys, tree.root.traverse());
+        Self::from_sorted_iter(keys, DEFAULT_ORDER)
+    }
+}
+
+impl<T, const N: usize> From<[T; N]> for BTree<T>
+where
+    T: Ord + Clone,
+{
+    /// Build a tree of `DEFAULT_ORDER` from keys in any order, keeping the first of equal keys.
+    fn from(keys: [T; N]) -> Self {
+        Self::from(Vec::from(keys))
+    }
+}
+
+/// Create a `BTree` of `DEFAULT_ORDER` holding given keys, which can be in any order.
+#[macro_export]
+macro_rules! btree {
+    ($($key:expr),* $(,)?) => {
+        $crate::btree::BTree::from([$($key),*])
+    };
+}
+
+impl<T, C> From<BTree<T, C>> for BTreeSet<T>
+where
+    T: Ord + Clone,
+    C: Comparator<T>,
+{
+    fn from(tree: BTree<T, C>) -> Self {
+        tree.into_sorted_vec().into_iter().collect()
+    }
+}
+
+impl<T, C> fmt::Debug for BTree<T, C>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            f.debug_struct("BTree")
+                .field("len", &self.nodes[self.root].size)
+                .field(
+                    "root",
+                    &NodeStructure {
+                        nodes: &self.nodes,
+                        id: self.root,
+                    },
+                )
+                .finish()
+        } else {
+            f.debug_set()
+                .entries(Iter::new(&self.nodes, self.root))
+                .finish()
+        }
+    }
+}
+
+impl<T, C> BTree<T, C>
+where
+    T: fmt::Display,
+{
+    /// Describe the nodes of the tree as nested JSON objects, for tools rendering or diffing
+    /// its shape. Each node has a `kind`, which is `"root"`, `"internal"` or `"leaf"`, its `keys`
+    /// as strings in their `Display` format and, unless it is a leaf, its `children`:
+    ///
+    /// `{"order":3,"len":3,"root":{"kind":"root","keys":["2"],"children":[...]}}`
+    pub fn to_json_structure(&self) -> String {
+        let mut json = String::new();
+        let root = &self.nodes[self.root];
+        // Writing to a string never fails.
+        write!(
+            json,
+            "{{\"order\":{},\"len\":{},\"root\":",
+            root.order, root.size
+        )
+        .unwrap();
+        self.write_json_node(&mut json, self.root).unwrap();
+        json.push('}');
+        json
+    }
+
+    /// Returns the nodes of the tree and the id of its root, for `to_svg` to lay them out.
+    #[cfg(feature = "viz")]
+    pub(crate) fn root_node(&self) -> (&Arena<T>, NodeId) {
+        (&self.nodes, self.root)
+    }
+
+    fn write_json_node(&self, json: &mut String, id: NodeId) -> fmt::Result {
+        let node = &self.nodes[id];
+        let kind = if id == self.root {
+            "root"
+        } else if node.is_leaf() {
+            "leaf"
+        } else {
+            "internal"
+        };
+        write!(json, "{{\"kind\":\"{}\",\"keys\":[", kind)?;
+        for (i, key) in node.keys.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write_json_string(json, &key.to_string())?;
+        }
+        json.push(']');
+        if !node.is_leaf() {
+            json.push_str(",\"children\":[");
+            for (i, &child) in node.children.iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                self.write_json_node(json, child)?;
+            }
+            json.push(']');
+        }
+        json.push('}');
+        Ok(())
+    }
+}
+
+/// Write `s` as a JSON string, escaping quotes, backslashes and control characters.
+fn write_json_string(json: &mut String, s: &str) -> fmt::Result {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32)?,
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    Ok(())
+}
+
+/// The bytes a `BTree` takes, as reported by `BTree::memory_usage`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MemoryUsage {
+    /// Bytes holding keys. Memory the keys own elsewhere, like the contents of strings, is not counted.
+    pub keys: usize,
+    /// Bytes of nodes besides their keys, such as references to children and counts of keys.
+    pub nodes: usize,
+    /// Bytes allocated but not used, in vectors with room for more items and in released nodes.
+    pub slack: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.keys + self.nodes + self.slack
+    }
+}
+
+/// Counts of operations on a `BTree`, as reported by `BTree::metrics`.
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Metrics {
+    /// Nodes split in two because they overflowed.
+    pub splits: usize,
+    /// Pairs of nodes merged into one because one of them underflowed.
+    pub merges: usize,
+    /// Keys moved between siblings through their parent.
+    pub rotations: usize,
+    /// Nodes allocated, including those built in bulk.
+    pub allocations: usize,
+    /// Comparisons between keys.
+    pub comparisons: usize,
+}
+
+/// Trees are equal when they hold equal keys in the same order, however their nodes are laid out.
+impl<T, C> PartialEq for BTree<T, C>
+where
+    T: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.nodes[self.root].size == other.nodes[other.root].size
+            && Iter::new(&self.nodes, self.root).eq(Iter::new(&other.nodes, other.root))
+    }
+}
+
+impl<T, C> Eq for BTree<T, C> where T: Eq {}
+
+/// Trees are ordered by their keys lexicographically, like std collections.
+impl<T, C> PartialOrd for BTree<T, C>
+where
+    T: PartialOrd,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Iter::new(&self.nodes, self.root).partial_cmp(Iter::new(&other.nodes, other.root))
+    }
+}
+
+impl<T, C> Ord for BTree<T, C>
+where
+    T: Ord,
+{
+    fn cmp(&self, other: &Self) -> Ordering {
+        Iter::new(&self.nodes, self.root).cmp(Iter::new(&other.nodes, other.root))
+    }
+}
+
+/// Trees holding equal keys hash equally, however their nodes are laid out.
+impl<T, C> Hash for BTree<T, C>
+where
+    T: Hash,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // The length keeps trees whose keys hash to the same stream apart, like std collections do.
+        state.write_usize(self.nodes[self.root].size);
+        for key in Iter::new(&self.nodes, self.root) {
+            key.hash(state);
+        }
+    }
+}
+
+/// Formats a subtree as nested nodes, for the alternate `Debug` format of a tree.
+struct NodeStructure<'a, T> {
+    nodes: &'a Arena<T>,
+    id: NodeId,
+}
+
+impl<'a, T> fmt::Debug for NodeStructure<'a, T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let node = &self.nodes[self.id];
+        let mut structure = f.debug_struct("Node");
+        structure.field("keys", &&node.keys[..]);
+        if !node.is_leaf() {
+            let children: Vec<_> = node
+                .children
+                .iter()
+                .map(|&id| NodeStructure {
+                    nodes: self.nodes,
+                    id,
+                })
+                .collect();
+            structure.field("children", &children);
+        }
+        structure.finish()
+    }
+}
+
+/// An iterator over keys of a `BTree` in ascending order.
+pub struct Iter<'a, T> {
+    nodes: &'a Arena<T>,
+    // Nodes on the path to the next key, paired with the index of the next key to yield in each.
+    stack: Vec<(&'a Node<T>, usize)>,
+}
+
+impl<'a, T> Iter<'a, T> {
+    fn new(nodes: &'a Arena<T>, root: NodeId) -> Self {
+        let mut iter = Self {
+            nodes,
+            stack: vec![],
+        };
+        iter.push_leftmost_path(root);
+        iter
+    }
+
+    fn push_leftmost_path(&mut self, id: NodeId) {
+        let mut node = &self.nodes[id];
+        loop {
+            self.stack.push((node, 0));
+            if node.is_leaf() {
+                return;
+            }
+            node = &self.nodes[node.children[0]];
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, index)) = self.stack.pop() {
+            if index < node.keys.len() {
+                self.stack.push((node, index + 1));
+                // Keys in the subtree right after the key come before the next key of this node.
+                if !node.is_leaf() {
+                    self.push_leftmost_path(node.children[index + 1]);
+                }
+                return Some(&node.keys[index]);
+            }
+        }
+        None
+    }
+}
+
+/// An iterator produced by `BTree::difference`.
+pub struct Difference<'a, T, C> {
+    iter: Iter<'a, T>,
+    other: Peekable<Iter<'a, T>>,
+    cmp: &'a Counted<C>,
+}
+
+impl<'a, T, C> Iterator for Difference<'a, T, C>
+where
+    C: Comparator<T>,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        'keys: loop {
+            let key = self.iter.next()?;
+            // Skip keys of `other` smaller than the key, which cannot match it or any later key.
+            while let Some(other_key) = self.other.peek() {
+                match self.cmp.compare(other_key, key) {
+                    Ordering::Less => {
+                        self.other.next();
+                    }
+                    Ordering::Equal => continue 'keys,
+                    Ordering::Greater => break,
+                }
+            }
+            return Some(key);
+        }
+    }
+}
+
+/// An iterator produced by `BTree::symmetric_difference`.
+pub struct SymmetricDifference<'a, T, C> {
+    iter: Peekable<Iter<'a, T>>,
+    other: Peekable<Iter<'a, T>>,
+    cmp: &'a Counted<C>,
+}
+
+impl<'a, T, C> Iterator for SymmetricDifference<'a, T, C>
+where
+    C: Comparator<T>,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let ordering = match (self.iter.peek(), self.other.peek()) {
+                (Some(key), Some(other_key)) => self.cmp.compare(key, other_key),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => return None,
+            };
+            match ordering {
+                Ordering::Less => return self.iter.next(),
+                Ordering::Greater => return self.other.next(),
+                Ordering::Equal => {
+                    self.iter.next();
+                    self.other.next();
+                }
+            }
+        }
+    }
+}
+
+/// An iterator produced by `BTree::merge_iter`.
+pub struct MergeIter<'a, T, C> {
+    iter: Peekable<Iter<'a, T>>,
+    other: Peekable<Iter<'a, T>>,
+    cmp: &'a Counted<C>,
+    policy: MergePolicy,
+}
+
+impl<'a, T, C> Iterator for MergeIter<'a, T, C>
+where
+    C: Comparator<T>,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ordering = match (self.iter.peek(), self.other.peek()) {
+            (Some(key), Some(other_key)) => self.cmp.compare(key, other_key),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return None,
+        };
+        match (ordering, self.policy) {
+            // The key of `other` is yielded by the next call, as it is less than any later key.
+            (Ordering::Less, _) | (Ordering::Equal, MergePolicy::KeepBoth) => self.iter.next(),
+            (Ordering::Greater, _) => self.other.next(),
+            (Ordering::Equal, MergePolicy::KeepSelf) => {
+                self.other.next();
+                self.iter.next()
+            }
+            (Ordering::Equal, MergePolicy::KeepOther) => {
+                self.iter.next();
+                self.other.next()
+            }
+        }
+    }
+}
+
+/// An iterator produced by `BTree::extract_if`.
+pub struct ExtractIf<'a, T, C, F> {
+    tree: &'a mut BTree<T, C>,
+    pred: F,
+    // The last key visited, from which the next one is searched.
+    last: Option<T>,
+}
+
+impl<'a, T, C, F> Iterator for ExtractIf<'a, T, C, F>
+where
+    T: Clone,
+    C: Comparator<T>,
+    F: FnMut(&T) -> bool,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tree = &mut *self.tree;
+        loop {
+            let key = tree
+                .nodes
+                .first_after(tree.root, self.last.as_ref(), &tree.cmp)?;
+            let is_extracted = (self.pred)(key);
+            self.last = Some(key.clone());
+            if is_extracted {
+                let key = self.last.as_ref().unwrap();
+                let min_keys = tree.min_keys();
+                let removed = tree.nodes.remove(tree.root, key, &tree.cmp, min_keys);
+                shrink_root(&mut tree.nodes, &mut tree.root);
+                return removed;
+            }
+        }
+    }
+}
+
+/// A cursor over keys of a `BTree`, which moves in both directions and seeks keys.
+/// It remembers the path to its key, so moving to an adjacent or nearby key does not
+/// start over from the root.
+///
+/// Besides the keys, the cursor can be at a ghost position, which lies after the last key
+/// and before the first one.
+pub struct Cursor<'a, T, C> {
+    nodes: &'a Arena<T>,
+    root: NodeId,
+    cmp: &'a Counted<C>,
+    position: Position,
+}
+
+impl<'a, T, C> Cursor<'a, T, C>
+where
+    C: Comparator<T>,
+{
+    /// Returns the key at the cursor, or `None` at the ghost position.
+    pub fn key(&self) -> Option<&'a T> {
+        self.position.key(self.nodes)
+    }
+
+    /// Move to the next key, or from the last key to the ghost position, returning the new key.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&'a T> {
+        self.position.move_next(self.nodes, self.root);
+        self.key()
+    }
+
+    /// Move to the previous key, or from the first key to the ghost position, returning the new key.
+    pub fn prev(&mut self) -> Option<&'a T> {
+        self.position.move_prev(self.nodes, self.root);
+        self.key()
+    }
+
+    /// Move to the first key not less than `key`, or to the ghost position if there is none.
+    /// Only the part of the path which does not cover `key` is searched again.
+    pub fn seek(&mut self, key: &T) {
+        self.position.seek(self.nodes, self.root, key, self.cmp);
+    }
+}
+
+/// A cursor over keys of a `BTree` which can also insert and remove keys where it is.
+/// It moves like a `Cursor`, and has the same ghost position.
+///
+/// An edit changes nodes on the path of the cursor, which then finds its key again.
+/// Each edit takes O(log n), like `insert` and `remove`.
+pub struct CursorMut<'a, T, C> {
+    tree: &'a mut BTree<T, C>,
+    position: Position,
+}
+
+impl<'a, T, C> CursorMut<'a, T, C>
+where
+    T: Clone,
+    C: Comparator<T>,
+{
+    /// Returns the key at the cursor, or `None` at the ghost position.
+    pub fn key(&self) -> Option<&T> {
+        self.position.key(&self.tree.nodes)
+    }
+
+    /// Move to the next key, or from the last key to the ghost position, returning the new key.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&T> {
+        self.position.move_next(&self.tree.nodes, self.tree.root);
+        self.key()
+    }
+
+    /// Move to the previous key, or from the first key to the ghost position, returning the new key.
+    pub fn prev(&mut self) -> Option<&T> {
+        self.position.move_prev(&self.tree.nodes, self.tree.root);
+        self.key()
+    }
+
+    /// Move to the first key not less than `key`, or to the ghost position if there is none.
+    pub fn seek(&mut self, key: &T) {
+        let tree = &*self.tree;
+        self.position.seek(&tree.nodes, tree.root, key, &tree.cmp);
+    }
+
+    /// Insert a key between the previous key and the one at the cursor, which stays where it is.
+    /// At the ghost position, the key becomes the last one.
+    ///
+    /// Fails without changing the tree if the key does not fit between its neighbors.
+    pub fn insert_before(&mut self, key: T) -> Result<(), UnorderedKeyError> {
+        let mut before = self.position.clone();
+        before.move_prev(&self.tree.nodes, self.tree.root);
+        let current = self.position.clone();
+        self.insert_between(key, &before, &current)
+    }
+
+    /// Insert a key between the one at the cursor, which stays where it is, and the next key.
+    /// At the ghost position, the key becomes the first one.
+    ///
+    /// Fails without changing the tree if the key does not fit between its neighbors.
+    pub fn insert_after(&mut self, key: T) -> Result<(), UnorderedKeyError> {
+        let mut after = self.position.clone();
+        after.move_next(&self.tree.nodes, self.tree.root);
+        let current = self.position.clone();
+        self.insert_between(key, &current, &after)
+    }
+
+    /// Insert a key as `BTree::insert` does and move to it, taking the position of the cursor
+    /// as a hint. The search starts from the deepest node on the path of the cursor whose
+    /// subtree would hold the key, so inserting near the cursor, as a sorted or nearly sorted
+    /// stream does, skips the descent from the root. The key counts of the nodes above are
+    /// still updated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the duplicate policy is `DuplicatePolicy::Error` and an equal key is present.
+    pub fn insert(&mut self, key: T) {
+        let tree = &mut *self.tree;
+        let depth = self.position.covering_depth(&tree.nodes, &key, &tree.cmp);
+        let id = self
+            .position
+            .path
+            .get(depth)
+            .map_or(tree.root, |&(id, _)| id);
+        let is_inserted = tree.nodes.insert(
+            id,
+            key.clone(),
+            &tree.cmp,
+            tree.split_policy,
+            tree.duplicate_policy == DuplicatePolicy::Allow,
+        );
+        if !is_inserted {
+            // No ancestor of the subtree is equal to the key, so the subtree holds the equal key.
+            let stored = tree.nodes.get_mut(id, &key, &tree.cmp).unwrap();
+            match tree.duplicate_policy {
+                DuplicatePolicy::Replace => *stored = key.clone(),
+                DuplicatePolicy::Error => panic!("an equal key is present already"),
+                _ => {}
+            }
+            self.position.path.truncate(depth);
+            self.position
+                .descend(&tree.nodes, id, |probe| tree.cmp.compare(probe, &key));
+            return;
+        }
+        // Count the key in the nodes above, making room in those which overflow. The highest
+        // node changed other than by its count still holds the key in its subtree.
+        let mut changed = (depth, id);
+        for depth in (0..depth).rev() {
+            let (parent, index) = self.position.path[depth];
+            tree.nodes[parent].size += 1;
+            if tree.nodes[tree.nodes[parent].children[index]].is_overflow() {
+                tree.nodes.make_room(parent, index, tree.split_policy);
+                changed = (depth, parent);
+            }
+        }
+        if tree.nodes[tree.root].is_overflow() {
+            tree.grow();
+            tree.nodes.split_children(tree.root, 0);
+            changed = (0, tree.root);
+        }
+        let (depth, id) = changed;
+        self.position.path.truncate(depth);
+        self.position
+            .descend(&tree.nodes, id, |probe| tree.cmp.compare(probe, &key));
+    }
+
+    /// Remove the key at the cursor, moving to the next key. Returns `None` at the ghost position.
+    pub fn remove_current(&mut self) -> Option<T> {
+        let key = self.key()?.clone();
+        let min_keys = self.tree.min_keys();
+        let removed = self
+            .tree
+            .nodes
+            .remove(self.tree.root, &key, &self.tree.cmp, min_keys);
+        shrink_root(&mut self.tree.nodes, &mut self.tree.root);
+        self.position = Position::default();
+        self.seek(&key);
+        removed
+    }
+
+    /// Insert a key if it lies between the keys at two adjacent positions.
+    fn insert_between(
+        &mut self,
+        key: T,
+        before: &Position,
+        after: &Position,
+    ) -> Result<(), UnorderedKeyError> {
+        let cmp = &self.tree.cmp;
+        let before = before.key(&self.tree.nodes);
+        let after = after.key(&self.tree.nodes);
+        let is_ordered = before.is_none_or(|before| cmp.compare(before, &key) == Ordering::Less)
+            && after.is_none_or(|after| cmp.compare(&key, after) == Ordering::Less);
+        if !is_ordered {
+            return Err(UnorderedKeyError);
+        }
+        let current = self.key().cloned();
+        self.tree.insert(key);
+        // Splits may have moved the current key to other nodes.
+        self.position = Position::default();
+        if let Some(current) = current {
+            self.seek(&current);
+        }
+        Ok(())
+    }
+}
+
+/// An iterator produced by `BTree::scan_prefix`.
+pub struct ScanPrefix<'a, 'p, T> {
+    nodes: &'a Arena<T>,
+    root: NodeId,
+    // At the next key to yield.
+    position: Position,
+    prefix: &'p [u8],
+}
+
+impl<'a, 'p, T> Iterator for ScanPrefix<'a, 'p, T>
+where
+    T: AsRef<[u8]>,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let key = self.position.key(self.nodes)?;
+        if !key.as_ref().starts_with(self.prefix) {
+            return None;
+        }
+        self.position.move_next(self.nodes, self.root);
+        Some(key)
+    }
+}
+
+/// The path from the root to the key a cursor is at.
+#[derive(Clone, Debug, Default)]
+struct Position {
+    // Nodes on the path to the current key. The last one is paired with the index of the key,
+    // and the others with the index of the child on the path. Empty at the ghost position.
+    path: Vec<(NodeId, usize)>,
+}
+
+impl Position {
+    fn key<'a, T>(&self, nodes: &'a Arena<T>) -> Option<&'a T> {
+        self.path.last().map(|&(id, index)| &nodes[id].keys[index])
+    }
+
+    fn move_next<T>(&mut self, nodes: &Arena<T>, root: NodeId) {
+        let (id, index) = match self.path.pop() {
+            Some(position) => position,
+            None => return self.push_leftmost_path(nodes, root),
+        };
+        let node = &nodes[id];
+        if !node.is_leaf() {
+            // Keys in the subtree right after the key come before the next key of this node.
+            self.path.push((id, index + 1));
+            self.push_leftmost_path(nodes, node.children[index + 1]);
+        } else if index + 1 < node.keys.len() {
+            self.path.push((id, index + 1));
+        } else {
+            self.ascend_to_next(nodes);
+        }
+    }
+
+    fn move_prev<T>(&mut self, nodes: &Arena<T>, root: NodeId) {
+        let (id, index) = match self.path.pop() {
+            Some(position) => position,
+            None => return self.push_rightmost_path(nodes, root),
+        };
+        let node = &nodes[id];
+        if !node.is_leaf() {
+            // The child before the key is at the same index.
+            self.path.push((id, index));
+            self.push_rightmost_path(nodes, node.children[index]);
+        } else if index > 0 {
+            self.path.push((id, index - 1));
+        } else {
+            // Climb to the first ancestor with a key before the subtree.
+            while let Some((id, index)) = self.path.pop() {
+                if index > 0 {
+                    self.path.push((id, index - 1));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Move to the first key not less than `key`, searching again only the part of the path
+    /// which does not cover it.
+    fn seek<T, C: Comparator<T>>(&mut self, nodes: &Arena<T>, root: NodeId, key: &T, cmp: &C) {
+        let depth = self.covering_depth(nodes, key, cmp);
+        let id = match self.path.get(depth) {
+            Some(&(id, _)) => id,
+            None => root,
+        };
+        self.path.truncate(depth);
+        self.descend(nodes, id, |probe| cmp.compare(probe, key));
+    }
+
+    /// Descend from a node to the first key which `f` does not order before the target,
+    /// or to the ghost position if there is none.
+    fn descend<T, F>(&mut self, nodes: &Arena<T>, mut id: NodeId, mut f: F)
+    where
+        F: FnMut(&T) -> Ordering,
+    {
+        loop {
+            let node = &nodes[id];
+            match node.keys.binary_search_by(&mut f) {
+                Ok(index) => {
+                    self.path.push((id, index));
+                    return;
+                }
+                Err(index) if node.is_leaf() => {
+                    if index < node.keys.len() {
+                        self.path.push((id, index));
+                    } else {
+                        self.ascend_to_next(nodes);
+                    }
+                    return;
+                }
+                Err(index) => {
+                    self.path.push((id, index));
+                    id = node.children[index];
+                }
+            }
+        }
+    }
+
+    /// Returns the depth of the deepest node on the path whose subtree would hold `key`.
+    /// Subtrees are tried from the deepest one up, so a key near the position takes only
+    /// a few comparisons.
+    fn covering_depth<T, C: Comparator<T>>(&self, nodes: &Arena<T>, key: &T, cmp: &C) -> usize {
+        (1..self.path.len())
+            .rev()
+            .find(|&depth| {
+                let (lower, upper) = self.bounds(nodes, depth);
+                lower.is_none_or(|lower| cmp.compare(lower, key) == Ordering::Less)
+                    && upper.is_none_or(|upper| cmp.compare(key, upper) == Ordering::Less)
+            })
+            .unwrap_or(0)
+    }
+
+    /// Returns the keys just before and after the subtree of the node at a depth on the path,
+    /// which are the nearest keys of its ancestors around the path.
+    fn bounds<'a, T>(&self, nodes: &'a Arena<T>, depth: usize) -> (Option<&'a T>, Option<&'a T>) {
+        let (mut lower, mut upper) = (None, None);
+        for &(parent, child) in self.path[..depth].iter().rev() {
+            let parent = &nodes[parent];
+            if lower.is_none() && child > 0 {
+                lower = Some(&parent.keys[child - 1]);
+            }
+            if upper.is_none() && child < parent.keys.len() {
+                upper = Some(&parent.keys[child]);
+            }
+            if lower.is_some() && upper.is_some() {
+                break;
+            }
+        }
+        (lower, upper)
+    }
+
+    /// Push the path from a subtree to its first key, or stay at the ghost position
+    /// if the subtree is empty.
+    fn push_leftmost_path<T>(&mut self, nodes: &Arena<T>, mut id: NodeId) {
+        loop {
+            let node = &nodes[id];
+            if node.is_leaf() {
+                if !node.keys.is_empty() {
+                    self.path.push((id, 0));
+                }
+                return;
+            }
+            self.path.push((id, 0));
+            id = node.children[0];
+        }
+    }
+
+    /// Push the path from a subtree to its last key, or stay at the ghost position
+    /// if the subtree is empty.
+    fn push_rightmost_path<T>(&mut self, nodes: &Arena<T>, mut id: NodeId) {
+        loop {
+            let node = &nodes[id];
+            let last = node.keys.len();
+            if node.is_leaf() {
+                if last > 0 {
+                    self.path.push((id, last - 1));
+                }
+                return;
+            }
+            self.path.push((id, last));
+            id = node.children[last];
+        }
+    }
+
+    /// Climb from a subtree whose keys are all visited to the first ancestor with a key after it.
+    fn ascend_to_next<T>(&mut self, nodes: &Arena<T>) {
+        while let Some((id, index)) = self.path.pop() {
+            if index < nodes[id].keys.len() {
+                self.path.push((id, index));
+                return;
+            }
+        }
+    }
+}
+
+/// Remove levels of a root which have no key left after a split or removal.
+fn shrink_root<T: Clone>(nodes: &mut Arena<T>, root: &mut NodeId) {
+    while nodes[*root].keys.is_empty() && !nodes[*root].is_leaf() {
+        let child = nodes[*root].children.pop().unwrap();
+        nodes.release(*root);
+        *root = child;
+    }
+    nodes[*root].kind = NodeKind::Root;
+}
+
+/// Merge two sorted sequences of keys. Keys from `right` replace equal ones from `left`.
+pub(crate) fn merge_sorted<T, C: Comparator<T>>(
+    mut left: Vec<T>,
+    mut right: Vec<T>,
+    cmp: &C,
+) -> Vec<T> {
+    // If the key ranges do not overlap, the sequences can simply be concatenated.
+    match (left.first(), left.last(), right.first(), right.last()) {
+        (None, _, _, _) => return right,
+        (_, _, None, _) => return left,
+        (_, Some(left_last), Some(right_first), _)
+            if cmp.compare(left_last, right_first) == Ordering::Less =>
+        {
+            left.append(&mut right);
+            return left;
+        }
+        (Some(left_first), _, _, Some(right_last))
+            if cmp.compare(right_last, left_first) == Ordering::Less =>
+        {
+            right.append(&mut left);
+            return right;
+        }
+        _ => {}
+    }
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(left_key), Some(right_key)) = (left.peek(), right.peek()) {
+        match cmp.compare(left_key, right_key) {
+            Ordering::Less => merged.push(left.next().unwrap()),
+            Ordering::Greater => merged.push(right.next().unwrap()),
+            Ordering::Equal => {
+                left.next();
+                merged.push(right.next().unwrap());
+            }
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    merged
+}
+
+pub(crate) fn check_order(order: usize) -> Result<(), InvalidOrder> {
+    if order < MIN_ORDER {
+        return Err(InvalidOrder::new(order));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::btree::{
+        BTree, DuplicatePolicy, MergePolicy, SplitPolicy, DEFAULT_ORDER, MIN_ORDER,
+    };
+    use crate::compare::OrdComparator;
+    use crate::error::UnorderedKeyError;
+    use crate::fuzz;
+    use crate::node::{Arena, Children, Node, NodeId, NodeKind};
+    use core::cmp::Ordering;
+    use core::ops::{Bound, RangeBounds};
+    use rand::seq::SliceRandom;
+    use rand::Rng;
+    use std::collections::{BTreeSet, HashSet};
+    use std::sync::Arc;
+
+    // Asserts given B-Tree is valid.
+    fn is_valid_btree<T: Ord + Clone>(nodes: &Arena<T>, id: NodeId) -> bool {
+        let node = &nodes[id];
+        assert!(node.keys.len() < node.order);
+        assert!(node.children.len() < node.order + 1);
+        match node.kind {
+            // A root node must be a leaf or have more than 2 children.
+            NodeKind::Root => assert!(node.children.is_empty() || node.children.len() >= 2),
+            // A internal node must have more than ceil(order / 2).
+            NodeKind::Internal => assert!(node.children.len() >= node.order.div_ceil(2)),
+            // A leaf node must have no child.
+            NodeKind::Leaf => assert!(node.children.is_empty()),
+        }
+        if node.kind != NodeKind::Root {
+            // A node except root must have at least ceil(order / 2) - 1 keys.
+            assert!(node.keys.len() + 1 >= node.order.div_ceil(2));
+        }
+        // A node must know the number of keys in its subtree.
+        assert_eq!(
+            node.size,
+            node.keys.len()
+                + node
+                    .children
+                    .iter()
+                    .map(|&child| nodes[child].size)
+                    .sum::<usize>()
+        );
+        if !node.is_leaf() {
+            // If a node except leaf has `k` keys, it must have `k + 1` children.
+            assert!(node.keys.len() + 1 == node.children.len());
+        }
+        // Check if each child node satisfies requirements to be B-Tree.
+        assert!(node
+            .children
+            .iter()
+            .all(|&child| { node.order == nodes[child].order && is_valid_btree(nodes, child) }));
+        true
+    }
+
+    // Builds a tree of order 4 holding 1, 2, ..., 10.
+    fn sample_tree() -> (Arena<u32>, NodeId) {
+        let mut nodes = Arena::new();
+        let leaves = vec![
+            nodes.with_children(4, NodeKind::Leaf, vec![1].into(), Children::new()),
+            nodes.with_children(4, NodeKind::Leaf, vec![3].into(), Children::new()),
+        ];
+        let left = nodes.with_children(4, NodeKind::Internal, vec![2].into(), leaves.into());
+        let leaves = vec![
+            nodes.with_children(4, NodeKind::Leaf, vec![5].into(), Children::new()),
+            nodes.with_children(4, NodeKind::Leaf, vec![7].into(), Children::new()),
+            nodes.with_children(4, NodeKind::Leaf, vec![9, 10].into(), Children::new()),
+        ];
+        let right = nodes.with_children(4, NodeKind::Internal, vec![6, 8].into(), leaves.into());
+        let root = nodes.with_children(4, NodeKind::Root, vec![4].into(), vec![left, right].into());
+        (nodes, root)
+    }
+
+    #[test]
+    fn valid_leaf() {
+        let mut nodes = Arena::new();
+        let tree = nodes.with_children(3, NodeKind::Leaf, vec![1, 2].into(), Children::new());
+        is_valid_btree(&nodes, tree);
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_leaf() {
+        let mut nodes = Arena::new();
+        let tree = nodes.with_children(3, NodeKind::Leaf, vec![1, 2, 3].into(), Children::new());
+        is_valid_btree(&nodes, tree);
+    }
+
+    #[test]
+    fn valid_tree() {
+        let (nodes, tree) = sample_tree();
+        is_valid_btree(&nodes, tree);
+    }
+
+    #[test]
+    fn get_tree() {
+        let (nodes, tree) = sample_tree();
+        // The tree contains 1, 2, ..., 10.
+        for i in 1..=10 {
+            assert_eq!(nodes.get(tree, &i, &OrdComparator), Some(&i));
+        }
+        assert_eq!(nodes.get(tree, &11, &OrdComparator), None);
+    }
+
+    fn generate_random_keys() -> Vec<u32> {
+        let mut rng = rand::thread_rng();
+        let mut keys = vec![];
+        let key_num = 100;
+        let key_range = 1000;
+        for _ in 0..key_num {
+            keys.push(rng.gen_range(0, key_range));
+        }
+        keys
+    }
+
+    #[test]
+    fn reject_small_order() {
+        for order in 0..MIN_ORDER {
+            let error = BTree::<u32>::try_new(order).unwrap_err();
+            assert_eq!(error.order(), order);
+        }
+        assert!(BTree::<u32>::try_new(MIN_ORDER).is_ok());
+    }
+
+    #[test]
+    #[should_panic]
+    fn new_panics_on_small_order() {
+        BTree::<u32>::new(1);
+    }
+
+    #[test]
+    fn insert_many_keys_into_deep_tree() {
+        let mut tree = BTree::new(3);
+        for i in 0..100_000u32 {
+            tree.insert(i.wrapping_mul(2_654_435_761));
+        }
+        assert_eq!(tree.len(), 100_000);
+        assert!(is_valid_btree(&tree.nodes, tree.root));
+        assert!(tree.iter().zip(tree.iter().skip(1)).all(|(a, b)| a < b));
+    }
+
+    #[test]
+    fn insert_elements() {
+        let keys = generate_random_keys();
+        let mut tree = BTree::new(4);
+        keys.iter().for_each(|key| tree.insert(*key));
+        is_valid_btree(&tree.nodes, tree.root);
+        for key in &keys {
+            assert_eq!(tree.get(key), Some(key));
+        }
+    }
+
+    #[test]
+    fn build_from_sorted_iter() {
+        for order in 3..8 {
+            for key_num in 0..200 {
+                let tree = BTree::from_sorted_iter(0..key_num, order);
+                is_valid_btree(&tree.nodes, tree.root);
+                assert_eq!(
+                    tree.nodes.traverse(tree.root),
+                    (0..key_num).collect::<Vec<_>>()
+                );
+                for key in 0..key_num {
+                    assert_eq!(tree.get(&key), Some(&key));
+                }
+                assert_eq!(tree.get(&key_num), None);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn build_from_sorted_iter_in_parallel() {
+        for order in 3..6 {
+            for key_num in (0..300).step_by(7) {
+                for threads in 1..6 {
+                    let mut tree = BTree::new(order);
+                    tree.root = tree.nodes.par_build_from_sorted_keys(
+                        (0..key_num).collect(),
+                        order,
+                        threads,
+                    );
+                    is_valid_btree(&tree.nodes, tree.root);
+                    assert_eq!(
+                        tree.nodes.traverse(tree.root),
+                        (0..key_num).collect::<Vec<_>>()
+                    );
+                    assert_eq!(tree.len(), key_num);
+                }
+            }
+        }
+        let tree = BTree::par_from_sorted_iter((0..10_000).chain(9_999..20_000), 16);
+        assert!(tree.iter().copied().eq(0..20_000));
+    }
+
+    #[test]
+    fn insert_into_sorted_build() {
+        let mut keys = generate_random_keys();
+        keys.sort();
+        keys.dedup();
+        let mut tree = BTree::from_sorted_iter(keys.iter().copied().step_by(2), 5);
+        keys.iter().for_each(|key| tree.insert(*key));
+        is_valid_btree(&tree.nodes, tree.root);
+        assert_eq!(keys, tree.nodes.traverse(tree.root));
+    }
+
+    #[test]
+    fn append_trees() {
+        let ranges = [
+            (0, 100, 100, 200),
+            (100, 200, 0, 100),
+            (0, 150, 50, 200),
+            (0, 0, 0, 50),
+        ];
+        for &(start, end, other_start, other_end) in &ranges {
+            let mut tree = BTree::from_sorted_iter(start..end, 4);
+            let other = BTree::from_sorted_iter(other_start..other_end, 5);
+            tree.append(other);
+            is_valid_btree(&tree.nodes, tree.root);
+            assert_eq!(tree.nodes[tree.root].order, 4);
+            let mut expected: Vec<u32> = (start..end).chain(other_start..other_end).collect();
+            expected.sort();
+            expected.dedup();
+            assert_eq!(tree.nodes.traverse(tree.root), expected);
+        }
+    }
+
+    #[test]
+    fn append_disjoint_trees_of_every_size() {
+        for order in 3..7 {
+            for len in 0..40 {
+                for other_len in 0..40 {
+                    let mut tree = BTree::from_sorted_iter(0..len, order);
+                    tree.append(BTree::from_sorted_iter(len..len + other_len, order));
+                    assert!(is_valid_btree(&tree.nodes, tree.root));
+                    assert!(tree.iter().copied().eq(0..len + other_len));
+                    let mut tree = BTree::from_sorted_iter(other_len..other_len + len, order);
+                    tree.append(BTree::from_sorted_iter(0..other_len, order));
+                    assert!(is_valid_btree(&tree.nodes, tree.root));
+                    assert!(tree.iter().copied().eq(0..len + other_len));
+                    assert_eq!(tree.len(), (len + other_len) as usize);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn append_disjoint_trees_restructures_the_border_only() {
+        // Nodes of a tree, except those on its left or right border, with their keys.
+        fn inner_nodes(tree: &BTree<u32>, right: bool) -> Vec<(NodeId, Vec<u32>)> {
+            let mut border = vec![tree.root];
+            while let Some(&child) = if right {
+                tree.nodes[*border.last().unwrap()].children.last()
+            } else {
+                tree.nodes[*border.last().unwrap()].children.first()
+            } {
+                border.push(child);
+            }
+            let mut nodes = vec![];
+            let mut stack = vec![tree.root];
+            while let Some(id) = stack.pop() {
+                stack.extend(tree.nodes[id].children.iter().copied());
+                if !border.contains(&id) {
+                    nodes.push((id, tree.nodes[id].keys.to_vec()));
+                }
+            }
+            nodes
+        }
+
+        for &order in &[4, 8] {
+            for &right in &[true, false] {
+                let (keys, other_keys) = if right {
+                    (0..10_000, 10_000..10_500)
+                } else {
+                    (500..10_500, 0..500)
+                };
+                let mut tree = BTree::from_sorted_iter(keys, order);
+                let untouched = inner_nodes(&tree, right);
+                tree.append(BTree::from_sorted_iter(other_keys, order));
+                assert!(is_valid_btree(&tree.nodes, tree.root));
+                assert!(tree.iter().copied().eq(0..10_500));
+                for (id, keys) in untouched {
+                    assert_eq!(tree.nodes[id].keys.to_vec(), keys);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn split_off_at_every_key() {
+        for order in 3..8 {
+            for split_key in 0..=61 {
+                let mut tree = BTree::from_sorted_iter((0..60).map(|key| key * 2 + 1), order);
+                let right = tree.split_off(&split_key);
+                is_valid_btree(&tree.nodes, tree.root);
+                is_valid_btree(&right.nodes, right.root);
+                let keys: Vec<u32> = (0..60).map(|key| key * 2 + 1).collect();
+                let (left_keys, right_keys): (Vec<u32>, Vec<u32>) =
+                    keys.into_iter().partition(|key| *key < split_key);
+                assert_eq!(tree.nodes.traverse(tree.root), left_keys);
+                assert_eq!(right.nodes.traverse(right.root), right_keys);
+            }
+        }
+    }
+
+    #[test]
+    fn split_off_releases_moved_keys() {
+        let keys: Vec<_> = (0..1_000u32).map(Arc::new).collect();
+        let mut tree = BTree::from_sorted_iter(keys.iter().cloned(), 4);
+        let right = tree.split_off(&keys[500]);
+        drop(right);
+        assert!(keys[..500].iter().all(|key| Arc::strong_count(key) == 2));
+        assert!(keys[500..].iter().all(|key| Arc::strong_count(key) == 1));
+    }
+
+    #[test]
+    fn remove_ranges() {
+        // Asserts all leaves of a subtree lie at the same depth, returning it.
+        fn height(nodes: &Arena<u32>, id: NodeId) -> usize {
+            let node = &nodes[id];
+            let heights: HashSet<_> = node
+                .children
+                .iter()
+                .map(|&child| height(nodes, child))
+                .collect();
+            assert!(heights.len() <= 1);
+            heights.into_iter().next().map_or(0, |height| height + 1)
+        }
+
+        let mut rng = rand::thread_rng();
+        for order in 3..8 {
+            for _ in 0..200 {
+                let mut expected: BTreeSet<u32> = (0..rng.gen_range(0, 300))
+                    .map(|_| rng.gen_range(0, 400))
+                    .collect();
+                let mut tree = BTree::from_sorted_iter(expected.iter().copied(), order);
+                let bound = |rng: &mut rand::rngs::ThreadRng| match rng.gen_range(0, 3) {
+                    0 => Bound::Included(rng.gen_range(0, 400)),
+                    1 => Bound::Excluded(rng.gen_range(0, 400)),
+                    _ => Bound::Unbounded,
+                };
+                for _ in 0..3 {
+                    let range = (bound(&mut rng), bound(&mut rng));
+                    let len = expected.len();
+                    expected.retain(|key| !range.contains(key));
+                    assert_eq!(tree.remove_range(range), len - expected.len());
+                    assert!(is_valid_btree(&tree.nodes, tree.root));
+                    height(&tree.nodes, tree.root);
+                    assert_eq!(tree.len(), expected.len());
+                    assert!(tree.iter().eq(expected.iter()));
+                }
+                // The tree keeps working after the cuts.
+                for key in 0..50 {
+                    tree.insert(key * 8);
+                    expected.insert(key * 8);
+                }
+                assert!(is_valid_btree(&tree.nodes, tree.root));
+                assert!(tree.iter().eq(expected.iter()));
+            }
+        }
+    }
+
+    #[test]
+    fn split_off_random_tree() {
+        let mut keys = generate_random_keys();
+        keys.sort();
+        keys.dedup();
+        for order in 3..8 {
+            for &split_key in &[0, 250, 500, 750, 1000] {
+                let mut tree = BTree::new(order);
+                keys.iter().rev().for_each(|key| tree.insert(*key));
+                let right = tree.split_off(&split_key);
+                is_valid_btree(&tree.nodes, tree.root);
+                is_valid_btree(&right.nodes, right.root);
+                let (left_keys, right_keys): (Vec<u32>, Vec<u32>) =
+                    keys.iter().partition(|key| **key < split_key);
+                assert_eq!(tree.nodes.traverse(tree.root), left_keys);
+                assert_eq!(right.nodes.traverse(right.root), right_keys);
+            }
+        }
+    }
+
+    #[test]
+    fn remove_elements() {
+        let mut rng = rand::thread_rng();
+        for order in 3..8 {
+            let mut tree = BTree::new(order);
+            let mut expected = BTreeSet::new();
+            for _ in 0..2000 {
+                let key: u32 = rng.gen_range(0, 300);
+                if rng.gen_bool(0.5) {
+                    tree.insert(key);
+                    expected.insert(key);
+                } else {
+                    assert_eq!(tree.remove(&key), expected.remove(&key));
+                }
+            }
+            is_valid_btree(&tree.nodes, tree.root);
+            assert_eq!(
+                tree.nodes.traverse(tree.root),
+                expected.into_iter().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn extract_if_matching_keys() {
+        let mut tree = BTree::from_sorted_iter(0..100, 4);
+        let extracted: Vec<u32> = tree.extract_if(|key| key % 3 == 0).collect();
+        assert_eq!(extracted, (0..100).step_by(3).collect::<Vec<_>>());
+        is_valid_btree(&tree.nodes, tree.root);
+        assert_eq!(
+            tree.nodes.traverse(tree.root),
+            (0..100).filter(|key| key % 3 != 0).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn extract_if_is_lazy() {
+        let mut tree = BTree::from_sorted_iter(0..100, 4);
+        let extracted: Vec<u32> = tree.extract_if(|key| key % 2 == 0).take(5).collect();
+        assert_eq!(extracted, vec![0, 2, 4, 6, 8]);
+        is_valid_btree(&tree.nodes, tree.root);
+        let mut expected: Vec<u32> = (0..100).collect();
+        expected.retain(|key| key % 2 == 1 || *key > 8);
+        assert_eq!(tree.nodes.traverse(tree.root), expected);
+    }
+
+    fn random_set(order: usize) -> (BTree<u32>, BTreeSet<u32>) {
+        let keys = generate_random_keys();
+        let mut tree = BTree::new(order);
+        keys.iter().for_each(|key| tree.insert(*key));
+        (tree, keys.into_iter().collect())
+    }
+
+    #[test]
+    fn iterate_in_order() {
+        let (tree, expected) = random_set(4);
+        assert!(tree.iter().eq(expected.iter()));
+        assert_eq!(BTree::<u32>::new(4).iter().next(), None);
+    }
+
+    #[test]
+    fn difference_of_trees() {
+        let (tree, expected) = random_set(4);
+        let (other, other_expected) = random_set(5);
+        assert!(tree
+            .difference(&other)
+            .eq(expected.difference(&other_expected)));
+        assert!(other
+            .difference(&tree)
+            .eq(other_expected.difference(&expected)));
+        assert_eq!(tree.difference(&tree).next(), None);
+    }
+
+    #[test]
+    fn symmetric_difference_of_trees() {
+        let (tree, expected) = random_set(4);
+        let (other, other_expected) = random_set(3);
+        assert!(tree
+            .symmetric_difference(&other)
+            .eq(expected.symmetric_difference(&other_expected)));
+        assert_eq!(tree.symmetric_difference(&tree).next(), None);
+    }
+
+    #[test]
+    fn subset_and_superset() {
+        let tree = BTree::from_sorted_iter(0..100, 4);
+        let evens = BTree::from_sorted_iter((0..100).step_by(2), 3);
+        let empty = BTree::new(4);
+        assert!(evens.is_subset(&tree));
+        assert!(!tree.is_subset(&evens));
+        assert!(tree.is_superset(&evens));
+        assert!(!evens.is_superset(&tree));
+        assert!(tree.is_subset(&tree));
+        assert!(empty.is_subset(&tree));
+        assert!(!tree.is_subset(&empty));
+        let beyond = BTree::from_sorted_iter(50..101, 5);
+        assert!(!beyond.is_subset(&tree));
+    }
+
+    #[test]
+    fn disjoint_trees() {
+        let evens = BTree::from_sorted_iter((0..100).step_by(2), 4);
+        let odds = BTree::from_sorted_iter((1..100).step_by(2), 5);
+        assert!(evens.is_disjoint(&odds));
+        assert!(odds.is_disjoint(&evens));
+        assert!(evens.is_disjoint(&BTree::new(3)));
+        let mut odds = odds;
+        odds.insert(42);
+        assert!(!evens.is_disjoint(&odds));
+    }
+
+    #[test]
+    fn select_and_rank() {
+        let (mut tree, expected) = random_set(4);
+        let expected: Vec<u32> = expected.into_iter().collect();
+        assert_eq!(tree.len(), expected.len());
+        for (i, key) in expected.iter().enumerate() {
+            assert_eq!(tree.select(i), Some(key));
+            assert_eq!(tree.nth(i), Some(key));
+            assert_eq!(tree.rank(key), i);
+            assert_eq!(tree.rank(&(key + 1)), i + 1);
+        }
+        assert_eq!(tree.select(expected.len()), None);
+        assert_eq!(tree.rank(&u32::MAX), expected.len());
+
+        for key in expected.iter().step_by(2) {
+            tree.remove(key);
+        }
+        is_valid_btree(&tree.nodes, tree.root);
+        let remaining: Vec<u32> = expected.into_iter().skip(1).step_by(2).collect();
+        for (i, key) in remaining.iter().enumerate() {
+            assert_eq!(tree.select(i), Some(key));
+            assert_eq!(tree.rank(key), i);
+        }
+    }
+
+    #[test]
+    fn reversed_comparator() {
+        let keys = generate_random_keys();
+        let mut tree = BTree::with_comparator(4, |a: &u32, b: &u32| b.cmp(a));
+        keys.iter().for_each(|key| tree.insert(*key));
+        is_valid_btree(&tree.nodes, tree.root);
+        let mut expected = keys.clone();
+        expected.sort_by(|a, b| b.cmp(a));
+        expected.dedup();
+        assert!(tree.iter().eq(expected.iter()));
+        assert_eq!(tree.rank(&expected[3]), 3);
+        for key in &keys {
+            assert_eq!(tree.get(key), Some(key));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "simd")]
+    fn simd_comparator() {
+        use crate::compare::SimdComparator;
+
+        let mut rng = rand::thread_rng();
+        for &order in &[4, 16, 64, 100] {
+            let mut tree = BTree::with_comparator(order, SimdComparator);
+            let mut expected = BTreeSet::new();
+            for _ in 0..2_000 {
+                let key: i64 = rng.gen_range(-1_000, 1_000);
+                tree.insert(key);
+                expected.insert(key);
+            }
+            assert!(is_valid_btree(&tree.nodes, tree.root));
+            assert!(tree.iter().eq(expected.iter()));
+            for key in -1_100..1_100 {
+                assert_eq!(tree.get(&key), expected.get(&key));
+            }
+        }
+        // Unsigned keys with the highest bit set are larger than the others.
+        let mut tree = BTree::with_comparator(64, SimdComparator);
+        let keys = [0, 1 << 63, u64::MAX, 1, (1 << 63) - 1, (1 << 63) + 1];
+        keys.iter().for_each(|&key| tree.insert(key));
+        let mut expected = keys.to_vec();
+        expected.sort_unstable();
+        assert!(tree.iter().eq(expected.iter()));
+        for key in &keys {
+            assert_eq!(tree.get(key), Some(key));
+        }
+        assert_eq!(tree.get(&2), None);
+    }
+
+    #[test]
+    fn compare_by_field() {
+        #[derive(Clone, Debug, PartialEq)]
+        struct Entry {
+            id: u32,
+            name: &'static str,
+        }
+        let mut tree = BTree::with_comparator(3, |a: &Entry, b: &Entry| a.id.cmp(&b.id));
+        tree.insert(Entry { id: 2, name: "b" });
+        tree.insert(Entry { id: 1, name: "a" });
+        tree.insert(Entry { id: 3, name: "c" });
+        let names: Vec<&str> = tree.iter().map(|entry| entry.name).collect();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        let stored = tree.get(&Entry { id: 2, name: "" });
+        assert_eq!(stored, Some(&Entry { id: 2, name: "b" }));
+        assert!(tree.remove(&Entry { id: 2, name: "" }));
+        assert_eq!(tree.len(), 2);
+
+        let old = tree.replace(Entry { id: 3, name: "d" });
+        assert_eq!(old, Some(Entry { id: 3, name: "c" }));
+        assert_eq!(tree.replace(Entry { id: 4, name: "e" }), None);
+        let names: Vec<&str> = tree.iter().map(|entry| entry.name).collect();
+        assert_eq!(names, vec!["a", "d", "e"]);
+
+        let taken = tree.take(&Entry { id: 1, name: "" });
+        assert_eq!(taken, Some(Entry { id: 1, name: "a" }));
+        assert_eq!(tree.take(&Entry { id: 1, name: "" }), None);
+        assert_eq!(tree.len(), 2);
+    }
+
+    #[test]
+    fn test_tree_contents() {
+        let mut keys = generate_random_keys();
+        let mut tree = BTree::new(18);
+        keys.iter().for_each(|key| tree.insert(*key));
+        is_valid_btree(&tree.nodes, tree.root);
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys, tree.nodes.traverse(tree.root));
+    }
+
+    #[test]
+    fn move_cursor_both_ways() {
+        for order in 3..7 {
+            let tree = BTree::from_sorted_iter(0..100, order);
+            let mut cursor = tree.cursor();
+            assert_eq!(cursor.key(), None);
+            for key in 0..100 {
+                assert_eq!(cursor.next(), Some(&key));
+            }
+            assert_eq!(cursor.next(), None);
+            for key in (0..100).rev() {
+                assert_eq!(cursor.prev(), Some(&key));
+            }
+            assert_eq!(cursor.prev(), None);
+            assert_eq!(cursor.prev(), Some(&99));
+            assert_eq!(cursor.next(), None);
+            assert_eq!(cursor.next(), Some(&0));
+        }
+        let empty: BTree<u32> = BTree::new(4);
+        let mut cursor = empty.cursor();
+        assert_eq!(cursor.next(), None);
+        assert_eq!(cursor.prev(), None);
+    }
+
+    #[test]
+    fn seek_with_cursor() {
+        let tree = BTree::from_sorted_iter((0..200).map(|key| key * 2), 4);
+        let mut cursor = tree.cursor();
+        // Seek forward, backward and far away from the current key.
+        for &key in &[0, 1, 2, 57, 58, 59, 10, 398, 399, 150, 3] {
+            cursor.seek(&key);
+            let expected = (key + 1) / 2 * 2;
+            assert_eq!(
+                cursor.key(),
+                if expected < 400 {
+                    Some(&expected)
+                } else {
+                    None
+                }
+            );
+            if expected < 398 {
+                assert_eq!(cursor.next(), Some(&(expected + 2)));
+                assert_eq!(cursor.prev(), Some(&expected));
+            }
+        }
+        cursor.seek(&1_000);
+        assert_eq!(cursor.key(), None);
+        assert_eq!(cursor.prev(), Some(&398));
+    }
+
+    #[test]
+    fn merge_join_with_cursors() {
+        let left = BTree::from_sorted_iter((0..300).map(|key| key * 3), 5);
+        let right = BTree::from_sorted_iter((0..200).map(|key| key * 5), 4);
+        let (mut left_cursor, mut right_cursor) = (left.cursor(), right.cursor());
+        let mut joined = vec![];
+        let (mut left_key, mut right_key) = (left_cursor.next(), right_cursor.next());
+        while let (Some(&l), Some(&r)) = (left_key, right_key) {
+            if l == r {
+                joined.push(l);
+                left_key = left_cursor.next();
+            } else if l < r {
+                left_cursor.seek(&r);
+                left_key = left_cursor.key();
+            } else {
+                right_cursor.seek(&l);
+                right_key = right_cursor.key();
+            }
+        }
+        assert_eq!(joined, (0..60).map(|key| key * 15).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn edit_with_cursor() {
+        let mut tree = BTree::from_sorted_iter((0..50).map(|key| key * 4), 3);
+        let mut cursor = tree.cursor_mut();
+        // Apply a sorted patch: put odd keys around each multiple of 8, and remove the others.
+        cursor.next();
+        while let Some(&key) = cursor.key() {
+            if key % 8 == 0 {
+                cursor.insert_before(key - 1).unwrap();
+                cursor.insert_after(key + 1).unwrap();
+                assert_eq!(cursor.key(), Some(&key));
+                cursor.next();
+                cursor.next();
+            } else {
+                assert_eq!(cursor.remove_current(), Some(key));
+            }
+        }
+        assert_eq!(cursor.remove_current(), None);
+        assert_eq!(cursor.next(), Some(&-1));
+        assert_eq!(cursor.insert_before(5), Err(UnorderedKeyError));
+        assert_eq!(cursor.insert_after(-1), Err(UnorderedKeyError));
+        // Around the ghost position, keys go after the last key or before the first one.
+        cursor.prev();
+        assert_eq!(cursor.insert_before(1_000), Ok(()));
+        assert_eq!(cursor.insert_after(1_000), Err(UnorderedKeyError));
+        assert_eq!(cursor.insert_after(-2), Ok(()));
+        assert_eq!(cursor.key(), None);
+
+        is_valid_btree(&tree.nodes, tree.root);
+        let mut expected: Vec<i32> = (0..25)
+            .flat_map(|i| vec![i * 8 - 1, i * 8, i * 8 + 1])
+            .collect();
+        expected.insert(0, -2);
+        expected.push(1_000);
+        assert_eq!(tree.nodes.traverse(tree.root), expected);
+    }
+
+    #[test]
+    fn insert_with_hint() {
+        let mut rng = rand::thread_rng();
+        for order in 3..8 {
+            for &policy in &[
+                SplitPolicy::Split,
+                SplitPolicy::Redistribute,
+                SplitPolicy::BStar,
+            ] {
+                let mut tree = BTree::new(order);
+                tree.set_split_policy(policy);
+                let mut expected = BTreeSet::new();
+                let mut cursor = tree.cursor_mut();
+                // A nearly sorted stream, with some keys out of place.
+                for i in 0..1_000 {
+                    let key = if rng.gen_bool(0.1) {
+                        rng.gen_range(0, 1_000)
+                    } else {
+                        i
+                    };
+                    cursor.insert(key);
+                    assert_eq!(cursor.key(), Some(&key));
+                    expected.insert(key);
+                }
+                assert!(is_valid_btree(&tree.nodes, tree.root));
+                assert!(tree.iter().eq(expected.iter()));
+            }
+        }
+
+        let mut tree = BTree::with_comparator(4, |a: &(u32, char), b: &(u32, char)| a.0.cmp(&b.0));
+        tree.insert_many((0..20).map(|key| (key, 'a')));
+        tree.set_duplicate_policy(DuplicatePolicy::Replace);
+        let mut cursor = tree.cursor_mut();
+        cursor.seek(&(10, ' '));
+        cursor.insert((11, 'b'));
+        assert_eq!(cursor.key(), Some(&(11, 'b')));
+        tree.set_duplicate_policy(DuplicatePolicy::Allow);
+        let mut cursor = tree.cursor_mut();
+        cursor.insert((11, 'c'));
+        cursor.insert((11, 'd'));
+        assert_eq!(tree.len(), 22);
+        assert!(tree.count_range((11, ' ')..=(11, ' ')) == 3);
+        assert!(is_valid_btree(&tree.nodes, tree.root));
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn hint_saves_comparisons() {
+        let mut tree = BTree::from_sorted_iter((0..100_000).map(|key| key * 2), 4);
+        let before = tree.metrics().comparisons;
+        let mut cursor = tree.cursor_mut();
+        cursor.seek(&5_000);
+        for key in 2_500..3_000 {
+            cursor.insert(key * 2 + 1);
+        }
+        let hinted = tree.metrics().comparisons - before;
+        let before = tree.metrics().comparisons;
+        for key in 3_000..3_500 {
+            tree.insert(key * 2 + 1);
+        }
+        let plain = tree.metrics().comparisons - before;
+        assert!(
+            hinted * 3 < plain * 2,
+            "{} comparisons with hints, {} without",
+            hinted,
+            plain
+        );
+    }
+
+    #[test]
+    fn nearest_keys() {
+        for order in 3..7 {
+            let tree = BTree::from_sorted_iter((0..100).map(|key| key * 3), order);
+            for probe in 0..305 {
+                let keys = (0..100).map(|key| key * 3);
+                assert_eq!(
+                    tree.get_lt(&probe),
+                    keys.clone().rfind(|&key| key < probe).as_ref()
+                );
+                assert_eq!(
+                    tree.get_le(&probe),
+                    keys.clone().rfind(|&key| key <= probe).as_ref()
+                );
+                assert_eq!(
+                    tree.get_gt(&probe),
+                    keys.clone().find(|&key| key > probe).as_ref()
+                );
+                assert_eq!(
+                    tree.get_ge(&probe),
+                    keys.clone().find(|&key| key >= probe).as_ref()
+                );
+            }
+        }
+        let empty: BTree<u32> = BTree::new(3);
+        assert_eq!(empty.get_le(&1), None);
+        assert_eq!(empty.get_ge(&1), None);
+    }
+
+    #[test]
+    fn adjacent_keys() {
+        let tree = BTree::from_sorted_iter((0..200u32).map(|key| key * 2), 4);
+        for key in 0..200u32 {
+            let key = key * 2;
+            assert_eq!(tree.predecessor(&key), key.checked_sub(2).as_ref());
+            assert_eq!(
+                tree.successor(&key),
+                Some(key + 2).filter(|&key| key < 400).as_ref()
+            );
+            // Keys which are not in the tree have no neighbors.
+            assert_eq!(tree.predecessor(&(key + 1)), None);
+            assert_eq!(tree.successor(&(key + 1)), None);
+        }
+    }
+
+    #[test]
+    fn count_keys_in_range() {
+        let tree = BTree::from_sorted_iter((0..100u32).map(|key| key * 2), 5);
+        assert_eq!(tree.count_range(..), 100);
+        assert_eq!(tree.count_range(10..20), 5);
+        assert_eq!(tree.count_range(10..=20), 6);
+        assert_eq!(tree.count_range(11..=19), 4);
+        assert_eq!(
+            tree.count_range((Bound::Excluded(10), Bound::Included(20))),
+            5
+        );
+        assert_eq!(tree.count_range(..5), 3);
+        assert_eq!(tree.count_range(190..), 5);
+        assert_eq!(tree.count_range(500..), 0);
+        assert_eq!(
+            tree.count_range((Bound::Included(20), Bound::Excluded(10))),
+            0
+        );
+        for start in 0..50 {
+            for end in start..60 {
+                let expected = (0..100)
+                    .map(|key| key * 2)
+                    .filter(|key| (start..end).contains(key));
+                assert_eq!(tree.count_range(start..end), expected.count());
+            }
+        }
+    }
+
+    #[test]
+    fn scan_keys_with_prefix() {
+        let words = [
+            "app", "apple", "applet", "apply", "apt", "b", "ban", "band", "bandana",
+        ];
+        for order in 3..6 {
+            let mut tree = BTree::new(order);
+            words.iter().for_each(|word| tree.insert(word.to_string()));
+            let scan = |prefix: &str| {
+                tree.scan_prefix(prefix)
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(scan("appl"), vec!["apple", "applet", "apply"]);
+            assert_eq!(scan("ap"), vec!["app", "apple", "applet", "apply", "apt"]);
+            assert_eq!(scan("band"), vec!["band", "bandana"]);
+            assert_eq!(scan(""), words.to_vec());
+            assert!(scan("c").is_empty());
+            assert!(scan("aq").is_empty());
+        }
+        let bytes = BTree::from_sorted_iter(vec![vec![0u8, 1], vec![0, 1, 2], vec![0, 2]], 3);
+        assert_eq!(bytes.scan_prefix(&[0u8, 1][..]).count(), 2);
+    }
+
+    #[test]
+    fn debug_format() {
+        let tree = BTree::from_sorted_iter(1..=5, 3);
+        assert_eq!(format!("{:?}", tree), "{1, 2, 3, 4, 5}");
+        assert_eq!(format!("{:?}", BTree::<u32>::new(3)), "{}");
+        let structure = format!("{:#?}", tree);
+        assert!(structure.starts_with("BTree {\n    len: 5,\n    root: Node {\n        keys: [\n"));
+        assert!(structure.matches("Node {").count() > 1);
+        assert!(structure.contains("children: ["));
+    }
+
+    #[test]
+    fn json_structure() {
+        let tree = BTree::from_sorted_iter(1..=3, 3);
+        assert_eq!(
+            tree.to_json_structure(),
+            concat!(
+                r#"{"order":3,"len":3,"root":{"kind":"root","keys":["2"],"children":["#,
+                r#"{"kind":"leaf","keys":["1"]},{"kind":"leaf","keys":["3"]}]}}"#
+            )
+        );
+        assert_eq!(
+            BTree::<u32>::new(3).to_json_structure(),
+            r#"{"order":3,"len":0,"root":{"kind":"root","keys":[]}}"#
+        );
+        let strings = BTree::from_sorted_iter(vec!["a\"b\\c\n\u{1}"], 3);
+        assert_eq!(
+            strings.to_json_structure(),
+            r#"{"order":3,"len":1,"root":{"kind":"root","keys":["a\"b\\c\n\u0001"]}}"#
+        );
+        let deep = BTree::from_sorted_iter(0..100, 3).to_json_structure();
+        assert!(deep.contains(r#""kind":"internal""#));
+        assert_eq!(deep.matches(r#""kind":"root""#).count(), 1);
+    }
+
+    #[test]
+    fn compare_contents() {
+        let mut rng = rand::thread_rng();
+        let mut keys: Vec<u32> = (0..200).collect();
+        keys.shuffle(&mut rng);
+        let mut tree = BTree::new(4);
+        for &key in &keys {
+            tree.insert(key);
+        }
+        let built = BTree::from_sorted_iter(0..200, 7);
+        assert_eq!(tree, built);
+        tree.remove(&100);
+        assert_ne!(tree, built);
+        assert_ne!(built, BTree::from_sorted_iter(0..199, 7));
+        assert_eq!(BTree::<u32>::new(3), BTree::new(5));
+    }
+
+    #[test]
+    fn order_lexicographically() {
+        let short = BTree::from_sorted_iter(1..4, 3);
+        let long = BTree::from_sorted_iter(1..10, 3);
+        let greater = BTree::from_sorted_iter(vec![1, 5], 3);
+        assert!(BTree::new(3) < short);
+        assert!(short < long);
+        assert!(long < greater);
+        assert_eq!(
+            short.cmp(&BTree::from_sorted_iter(1..4, 5)),
+            Ordering::Equal
+        );
+
+        let mut trees = vec![greater.clone(), long.clone(), short.clone()];
+        trees.sort();
+        assert_eq!(trees, vec![short, long, greater]);
+    }
+
+    #[test]
+    // With the `metrics` feature, trees count comparisons through a shared reference,
+    // which does not change their hash.
+    #[allow(clippy::mutable_key_type)]
+    fn hash_contents() {
+        let mut tree = BTree::new(3);
+        for key in (0..100).rev() {
+            tree.insert(key);
+        }
+        let mut trees = HashSet::new();
+        assert!(trees.insert(tree));
+        assert!(!trees.insert(BTree::from_sorted_iter(0..100, 6)));
+        assert!(trees.insert(BTree::from_sorted_iter(0..99, 6)));
+        assert!(trees.insert(BTree::new(3)));
+        assert_eq!(trees.len(), 3);
+    }
+
+    #[test]
+    fn convert_from_and_into_std() {
+        let set: BTreeSet<u32> = (0..500).map(|key| key * 7 % 1_000).collect();
+        let tree = BTree::from(set.clone());
+        assert!(is_valid_btree(&tree.nodes, tree.root));
+        assert_eq!(tree.order(), DEFAULT_ORDER);
+        assert!(tree.iter().eq(set.iter()));
+        assert_eq!(BTreeSet::from(tree), set);
+    }
+
+    #[test]
+    fn pop_first_as_priority_queue() {
+        let mut rng = rand::thread_rng();
+        for order in 3..8 {
+            let mut tree = BTree::new(order);
+            let mut expected = BTreeSet::new();
+            for _ in 0..3_000 {
+                if rng.gen_bool(0.6) {
+                    let key: u32 = rng.gen_range(0, 1_000);
+                    tree.insert(key);
+                    expected.insert(key);
+                } else {
+                    assert_eq!(tree.first(), expected.iter().next());
+                    assert_eq!(tree.pop_first(), expected.pop_first());
+                }
+                assert!(is_valid_btree(&tree.nodes, tree.root));
+                assert_eq!(tree.len(), expected.len());
+            }
+            while let Some(key) = expected.pop_first() {
+                assert_eq!(tree.pop_first(), Some(key));
+            }
+            assert!(tree.is_empty());
+            assert_eq!(tree.pop_first(), None);
+        }
+    }
+
+    #[test]
+    fn insert_descending() {
+        for order in 3..8 {
+            let mut tree = BTree::new(order);
+            for key in (0..2_000).rev() {
+                tree.insert(key);
+                // Equal keys are not pushed in front of the leftmost leaf.
+                tree.insert(key);
+                assert_eq!(tree.first(), Some(&key));
+            }
+            assert!(is_valid_btree(&tree.nodes, tree.root));
+            assert_eq!(tree.len(), 2_000);
+            assert!(tree.iter().copied().eq(0..2_000));
+            assert_eq!(tree.pop_first(), Some(0));
+            tree.insert(0);
+            assert!(is_valid_btree(&tree.nodes, tree.root));
+            assert_eq!(tree.len(), 2_000);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn insert_descending_saves_comparisons() {
+        let mut tree = BTree::new(8);
+        for key in (0..10_000).rev() {
+            tree.insert(key);
+        }
+        let descending = tree.metrics().comparisons;
+        let mut tree = BTree::new(8);
+        for key in 0..10_000 {
+            tree.insert(key);
+        }
+        let ascending = tree.metrics().comparisons;
+        assert!(
+            descending * 2 < ascending,
+            "{} comparisons descending, {} ascending",
+            descending,
+            ascending
+        );
+    }
+
+    #[test]
+    fn largest_and_smallest_keys() {
+        let mut rng = rand::thread_rng();
+        for order in 3..8 {
+            let mut tree = BTree::new(order);
+            let mut expected = BTreeSet::new();
+            for _ in 0..500 {
+                let key: u32 = rng.gen_range(0, 1_000);
+                tree.insert(key);
+                expected.insert(key);
+            }
+            for k in [0, 1, 2, 10, 100, expected.len(), expected.len() + 1] {
+                assert!(tree.top_k(k).into_iter().eq(expected.iter().rev().take(k)));
+                assert!(tree.bottom_k(k).into_iter().eq(expected.iter().take(k)));
+            }
+        }
+        let tree: BTree<u32> = BTree::new(3);
+        assert!(tree.top_k(3).is_empty());
+        assert!(tree.bottom_k(3).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "rand")]
+    fn sample_keys_uniformly() {
+        let mut rng = rand::thread_rng();
+        let mut tree = BTree::new(3);
+        assert_eq!(tree.sample(&mut rng), None);
+        assert!(tree.sample_n(3, &mut rng).is_empty());
+        for key in 0..10 {
+            tree.insert(key);
+        }
+        let mut counts = [0; 10];
+        for _ in 0..10_000 {
+            counts[*tree.sample(&mut rng).unwrap()] += 1;
+        }
+        assert!(counts.iter().all(|&count| 800 < count && count < 1_200));
+
+        let mut counts = [0; 10];
+        for _ in 0..2_000 {
+            let mut keys = tree.sample_n(5, &mut rng);
+            keys.sort();
+            keys.dedup();
+            assert_eq!(keys.len(), 5);
+            for &key in keys {
+                counts[key] += 1;
+            }
+        }
+        assert!(counts.iter().all(|&count| 800 < count && count < 1_200));
+        let mut keys = tree.sample_n(20, &mut rng);
+        keys.sort();
+        assert!(keys.into_iter().eq(tree.iter()));
+    }
+
+    #[test]
+    fn remove_with_min_fill() {
+        // Asserts every node but the root holds at least `min_keys` keys.
+        fn is_filled(nodes: &Arena<u32>, id: NodeId, min_keys: usize) -> bool {
+            let node = &nodes[id];
+            node.children.iter().all(|&child| {
+                nodes[child].keys.len() >= min_keys && is_filled(nodes, child, min_keys)
+            })
+        }
+
+        let mut rng = rand::thread_rng();
+        for order in 3..10 {
+            for &percent in &[1, 25, 50, 66, 100] {
+                let mut tree = BTree::new(order);
+                tree.set_min_fill(percent);
+                assert_eq!(tree.min_fill(), percent);
+                let mut expected = BTreeSet::new();
+                for _ in 0..3_000 {
+                    let key: u32 = rng.gen_range(0, 500);
+                    if rng.gen_bool(0.5) {
+                        tree.insert(key);
+                        expected.insert(key);
+                    } else {
+                        assert_eq!(tree.remove(&key), expected.remove(&key));
+                    }
+                }
+                assert!(tree.iter().eq(expected.iter()));
+                assert_eq!(tree.len(), expected.len());
+                let min_keys = ((order - 1) * percent / 100).clamp(1, (order - 1) / 2);
+                assert!(is_filled(&tree.nodes, tree.root, min_keys));
+                if percent >= 50 {
+                    assert!(is_valid_btree(&tree.nodes, tree.root));
+                }
+                while let Some(key) = tree.pop_first() {
+                    assert_eq!(Some(key), expected.pop_first());
+                }
+                assert!(expected.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_min_fill() {
+        BTree::<u32>::new(4).set_min_fill(0);
+    }
+
+    #[test]
+    fn insert_duplicates_by_policy() {
+        let mut tree = BTree::with_comparator(4, |a: &(u32, char), b: &(u32, char)| a.0.cmp(&b.0));
+        assert_eq!(tree.duplicate_policy(), DuplicatePolicy::Ignore);
+        tree.insert((1, 'a'));
+        tree.insert((1, 'b'));
+        assert_eq!(tree.get(&(1, ' ')), Some(&(1, 'a')));
+
+        tree.set_duplicate_policy(DuplicatePolicy::Replace);
+        tree.insert((1, 'c'));
+        assert_eq!(tree.get(&(1, ' ')), Some(&(1, 'c')));
+        assert_eq!(tree.len(), 1);
+
+        tree.set_duplicate_policy(DuplicatePolicy::Error);
+        let error = tree.try_insert((1, 'd')).unwrap_err();
+        assert_eq!(error.key, (1, 'd'));
+        assert_eq!(error.to_string(), "key (1, 'd') is present already");
+        assert!(tree.try_insert((2, 'a')).is_ok());
+        assert_eq!(tree.insert_many(vec![(3, 'a'), (4, 'a')]), 2);
+        assert_eq!(tree.len(), 4);
+
+        tree.set_duplicate_policy(DuplicatePolicy::Allow);
+        tree.insert((1, 'e'));
+        assert!(tree
+            .iter()
+            .eq(&[(1, 'c'), (1, 'e'), (2, 'a'), (3, 'a'), (4, 'a')]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_duplicate_with_error_policy() {
+        let mut tree = BTree::new(4);
+        tree.set_duplicate_policy(DuplicatePolicy::Error);
+        tree.insert(1);
+        tree.insert(1);
+    }
+
+    #[test]
+    fn allow_duplicates() {
+        let mut rng = rand::thread_rng();
+        for order in 3..8 {
+            let mut tree = BTree::new(order);
+            tree.set_duplicate_policy(DuplicatePolicy::Allow);
+            let mut expected: Vec<u32> = vec![];
+            for _ in 0..2_000 {
+                let key = rng.gen_range(0, 50);
+                if rng.gen_bool(0.7) {
+                    tree.insert(key);
+                    let index = expected.partition_point(|&probe| probe <= key);
+                    expected.insert(index, key);
+                } else {
+                    let index = expected.binary_search(&key).ok();
+                    assert_eq!(tree.remove(&key), index.is_some());
+                    index.map(|index| expected.remove(index));
+                }
+                assert!(is_valid_btree(&tree.nodes, tree.root));
+            }
+            assert!(tree.iter().eq(expected.iter()));
+            for key in 0..=50 {
+                let below = expected.partition_point(|&probe| probe < key);
+                let above = expected.partition_point(|&probe| probe <= key);
+                assert_eq!(tree.rank(&key), below);
+                assert_eq!(tree.count_range(key..=key), above - below);
+                assert_eq!(tree.count_range(..=key), above);
+                assert_eq!(
+                    tree.get_lt(&key),
+                    below.checked_sub(1).map(|i| &expected[i])
+                );
+                assert_eq!(tree.get_gt(&key), expected.get(above));
+                assert_eq!(
+                    tree.get_le(&key),
+                    above.checked_sub(1).map(|i| &expected[i])
+                );
+                assert_eq!(tree.get_ge(&key), expected.get(below));
+            }
+        }
+    }
+
+    #[test]
+    fn merge_two_trees() {
+        let tree = BTree::with_comparator(4, |a: &(u32, char), b: &(u32, char)| a.0.cmp(&b.0));
+        let mut left = tree.clone();
+        let mut right = tree.clone();
+        for key in 0..100 {
+            if key % 2 == 0 {
+                left.insert((key, 'l'));
+            }
+            if key % 3 == 0 {
+                right.insert((key, 'r'));
+            }
+        }
+        let merge =
+            |policy| -> Vec<(u32, char)> { left.merge_iter(&right, policy).copied().collect() };
+        let mut expected = vec![];
+        for key in 0..100 {
+            match (key % 2 == 0, key % 3 == 0) {
+                (true, true) => expected.extend([(key, 'l'), (key, 'r')]),
+                (true, false) => expected.push((key, 'l')),
+                (false, true) => expected.push((key, 'r')),
+                (false, false) => {}
+            }
+        }
+        assert_eq!(merge(MergePolicy::KeepBoth), expected);
+        let keep_self: Vec<_> = expected
+            .iter()
+            .filter(|&&(key, side)| side == 'l' || key % 2 != 0)
+            .copied()
+            .collect();
+        assert_eq!(merge(MergePolicy::KeepSelf), keep_self);
+        let keep_other: Vec<_> = expected
+            .iter()
+            .filter(|&&(key, side)| side == 'r' || key % 3 != 0)
+            .copied()
+            .collect();
+        assert_eq!(merge(MergePolicy::KeepOther), keep_other);
+        let empty = tree;
+        assert!(empty
+            .merge_iter(&left, MergePolicy::KeepSelf)
+            .eq(left.iter()));
+    }
+
+    #[test]
+    fn move_keys_into_sorted_vec() {
+        let mut rng = rand::thread_rng();
+        let mut tree = BTree::new(4);
+        for _ in 0..1_000 {
+            tree.insert(rng.gen_range(0, 2_000).to_string());
+        }
+        for _ in 0..500 {
+            tree.remove(&rng.gen_range(0, 2_000).to_string());
+        }
+        let expected = tree.nodes.traverse(tree.root);
+        assert_eq!(tree.into_sorted_vec(), expected);
+        assert_eq!(BTree::<u8>::new(3).into_sorted_vec(), vec![]);
+    }
+
+    #[test]
+    fn build_from_unsorted_keys() {
+        let mut keys: Vec<u32> = (0..1_000).map(|key| key / 2).collect();
+        keys.shuffle(&mut rand::thread_rng());
+        let tree = BTree::from(keys);
+        assert!(is_valid_btree(&tree.nodes, tree.root));
+        assert!(tree.iter().copied().eq(0..500));
+
+        let tree = crate::btree!["b", "a", "c", "a",];
+        assert!(tree.iter().eq(&["a", "b", "c"]));
+        assert_eq!(BTree::from([5, 4, 4]), BTree::from(vec![4, 5]));
+        let empty: BTree<u8> = crate::btree![];
+        assert!(empty.is_empty());
+    }
+
+    // Applies random operations to a tree and to `std::collections::BTreeSet` through the fuzz
+    // driver, asserting that they behave the same and the tree stays valid after every operation.
+    #[test]
+    fn differential_against_std() {
+        let mut rng = rand::thread_rng();
+        for &order in &[3, 4, 5, 8, 32] {
+            let mut data = vec![order - MIN_ORDER as u8];
+            data.extend((0..4 * 3_000).map(|_| rng.gen::<u8>()));
+            // Keep keys below 512, so that operations often hit keys inserted before.
+            for op in data[1..].chunks_exact_mut(4) {
+                op[2] %= 2;
+            }
+            fuzz::run_with(&data, |tree| {
+                assert!(is_valid_btree(&tree.nodes, tree.root))
+            });
+        }
+    }
+
+    #[test]
+    fn measure_memory_usage() {
+        // Keys of a tree of order 4 are inline, while those of order 32 are on the heap.
+        for &order in &[4, 32] {
+            let mut tree = BTree::from_sorted_iter(0..1_000u64, order);
+            let usage = tree.memory_usage();
+            assert_eq!(usage.keys, 1_000 * 8);
+            assert!(usage.nodes > 0);
+            for key in 0..500 {
+                tree.remove(&key);
+            }
+            let after = tree.memory_usage();
+            assert_eq!(after.keys, 500 * 8);
+            assert!(after.slack > usage.slack);
+        }
+        let usage = BTree::<u64>::new(4).memory_usage();
+        assert_eq!(usage.keys, 0);
+        assert_eq!(usage.nodes + usage.slack, usage.total());
+        // An empty tree holds room for its root only.
+        assert!(usage.total() < 2 * std::mem::size_of::<Node<u64>>());
+    }
+
+    #[test]
+    fn insert_large_keys_on_small_stack() {
+        std::thread::Builder::new()
+            .stack_size(2 << 20)
+            .spawn(|| {
+                let mut tree = BTree::new(4);
+                tree.insert([1u8; 4096]);
+                assert_eq!(tree.get(&[1; 4096]), Some(&[1; 4096]));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn count_operations() {
+        let mut tree = BTree::new(3);
+        let metrics = tree.metrics();
+        assert_eq!(
+            (metrics.splits, metrics.comparisons, metrics.allocations),
+            (0, 0, 1)
+        );
+        for key in 0..100 {
+            tree.insert(key);
+        }
+        let metrics = tree.metrics();
+        assert!(metrics.splits > 0);
+        assert_eq!(metrics.merges, 0);
+        // Each split allocates a node, and growing the tree allocates a new root too.
+        assert!(metrics.allocations > metrics.splits);
+        assert!(metrics.comparisons > 100);
+
+        tree.get(&50);
+        assert!(tree.metrics().comparisons > metrics.comparisons);
+        for key in 0..100 {
+            tree.remove(&key);
+        }
+        let metrics = tree.metrics();
+        assert!(metrics.merges > 0);
+        assert!(metrics.rotations > 0);
+    }
+
+    fn count_nodes<T>(nodes: &Arena<T>, id: NodeId) -> usize {
+        1 + nodes[id]
+            .children
+            .iter()
+            .map(|&child| count_nodes(nodes, child))
+            .sum::<usize>()
+    }
+
+    #[test]
+    fn insert_with_redistribution() {
+        let mut rng = rand::thread_rng();
+        for &policy in &[SplitPolicy::Redistribute, SplitPolicy::BStar] {
+            for &order in &[3, 4, 5, 8, 16] {
+                let mut split = BTree::new(order);
+                let mut tree = BTree::new(order);
+                tree.set_split_policy(policy);
+                assert_eq!(tree.split_policy(), policy);
+                let mut expected = BTreeSet::new();
+                for _ in 0..2_000 {
+                    let key: u32 = rng.gen_range(0, 10_000);
+                    split.insert(key);
+                    tree.insert(key);
+                    expected.insert(key);
+                    assert!(is_valid_btree(&tree.nodes, tree.root));
+                }
+                assert!(tree.iter().eq(expected.iter()));
+                assert_eq!(tree.len(), expected.len());
+                // Nodes are fuller, so there are fewer of them.
+                assert!(
+                    count_nodes(&tree.nodes, tree.root) < count_nodes(&split.nodes, split.root)
+                );
+                for key in 0..5_000 {
+                    assert_eq!(tree.remove(&key), expected.remove(&key));
+                }
+                assert!(is_valid_btree(&tree.nodes, tree.root));
+                assert!(tree.iter().eq(expected.iter()));
+            }
+        }
+    }
+
+    #[test]
+    fn rebalance_sparse_tree() {
+        let mut rng = rand::thread_rng();
+        let mut tree = BTree::new(8);
+        for _ in 0..5_000 {
+            tree.insert(rng.gen_range(0, 10_000u32));
+        }
+        tree.extract_if(|key| key % 3 != 0).for_each(drop);
+        let keys: Vec<u32> = tree.iter().copied().collect();
+        let sparse = count_nodes(&tree.nodes, tree.root);
+        tree.rebalance();
+        assert!(is_valid_btree(&tree.nodes, tree.root));
+        assert!(tree.iter().copied().eq(keys.iter().copied()));
+        assert_eq!(tree.len(), keys.len());
+        assert!(count_nodes(&tree.nodes, tree.root) < sparse);
+    }
+
+    #[test]
+    fn shrink_to_fit_after_removals() {
+        for &order in &[4, 32] {
+            let mut tree = BTree::new(order);
+            for key in 0..2_000u64 {
+                tree.insert(key);
+            }
+            tree.extract_if(|key| key % 4 != 0).for_each(drop);
+            let before = tree.memory_usage();
+            tree.shrink_to_fit();
+            let after = tree.memory_usage();
+            assert!(is_valid_btree(&tree.nodes, tree.root));
+            assert!(tree.iter().copied().eq((0..2_000).step_by(4)));
+            assert_eq!(after.keys, before.keys);
+            assert!(after.slack < before.slack);
+            assert!(after.total() < before.total());
+            tree.insert(1);
+            assert_eq!(tree.get(&1), Some(&1));
+        }
+    }
+
+    #[test]
+    fn insert_many_in_batches() {
+        let mut rng = rand::thread_rng();
+        for &order in &[3, 4, 5, 8, 32] {
+            let mut tree = BTree::new(order);
+            let mut expected = BTreeSet::new();
+            for &batch_len in &[0, 1, 10, 500, 3_000, 50] {
+                let batch: Vec<u32> = (0..batch_len).map(|_| rng.gen_range(0, 20_000)).collect();
+                let added = batch.iter().filter(|&&key| expected.insert(key)).count();
+                assert_eq!(tree.insert_many(batch), added);
+                assert!(is_valid_btree(&tree.nodes, tree.root));
+                assert_eq!(tree.len(), expected.len());
+                assert!(tree.iter().eq(expected.iter()));
+            }
+        }
+        // A batch much larger than the tree adds several levels above the old root.
+        let mut tree = BTree::from_sorted_iter(vec![5_000], 3);
+        assert_eq!(tree.insert_many((0..10_000).rev()), 9_999);
+        assert!(is_valid_btree(&tree.nodes, tree.root));
+        assert!(tree.iter().copied().eq(0..10_000));
+    }
+
+    #[test]
+    fn remove_many_in_batches() {
+        let mut rng = rand::thread_rng();
+        for &order in &[3, 4, 8, 32] {
+            let mut tree = BTree::new(order);
+            let mut expected = BTreeSet::new();
+            tree.insert_many(0..5_000u32);
+            expected.extend(0..5_000u32);
+            // Small batches are removed one by one, and large ones by rebuilding the tree.
+            for &batch_len in &[0, 1, 20, 3_000, 100, 2_000] {
+                let batch: Vec<u32> = (0..batch_len).map(|_| rng.gen_range(0, 6_000)).collect();
+                let removed = batch.iter().filter(|&key| expected.remove(key)).count();
+                assert_eq!(tree.remove_many(batch), removed);
+                assert!(is_valid_btree(&tree.nodes, tree.root));
+                assert_eq!(tree.len(), expected.len());
+                assert!(tree.iter().eq(expected.iter()));
+            }
+        }
+    }
+
+    #[test]
+    fn snapshot_shares_nodes() {
+        let mut tree = BTree::from_sorted_iter(0..10_000u32, 4);
+        let snapshot = tree.snapshot();
+        let (shared, chunks) = tree.nodes.shared_chunks(&snapshot.nodes);
+        assert_eq!(shared, chunks);
+        // A removal copies the chunks along one path.
+        tree.remove(&5_000);
+        let (shared, chunks) = tree.nodes.shared_chunks(&snapshot.nodes);
+        assert!(
+            chunks - shared <= 8,
+            "{} of {} chunks copied",
+            chunks - shared,
+            chunks
+        );
+
+        let mut rng = rand::thread_rng();
+        let mut snapshots = vec![(tree.snapshot(), tree.iter().copied().collect::<Vec<_>>())];
+        for round in 0..5 {
+            for _ in 0..2_000 {
+                let key = rng.gen_range(0, 20_000);
+                if rng.gen_bool(0.5) {
+                    tree.insert(key);
+                } else {
+                    tree.remove(&key);
+                }
+            }
+            let (snapshot, _) = &mut snapshots[round];
+            // Snapshots change apart from the tree, too.
+            snapshot.insert(30_000);
+            snapshots.push((tree.snapshot(), tree.iter().copied().collect()));
+        }
+        assert!(is_valid_btree(&tree.nodes, tree.root));
+        for (round, (snapshot, keys)) in snapshots.iter().enumerate() {
+            assert!(is_valid_btree(&snapshot.nodes, snapshot.root));
+            let extra = Some(30_000).filter(|_| round < 5);
+            assert!(snapshot
+                .iter()
+                .copied()
+                .eq(keys.iter().copied().chain(extra)));
+        }
+        assert!(snapshot.iter().copied().eq(0..10_000));
+    }
+
+    #[test]
+    fn remove_many_duplicates() {
+        let mut tree = BTree::new(4);
+        tree.set_duplicate_policy(DuplicatePolicy::Allow);
+        for _ in 0..3 {
+            tree.insert_many(0..1_000u32);
+        }
+        // Counts of each key left in the tree.
+        let mut expected = vec![3; 1_000];
+        // A small batch is removed one by one, and a large one by rebuilding the tree.
+        // Either way, each key in the batch removes a single equal key.
+        let small = vec![5, 5, 7, 2_000];
+        let large: Vec<u32> = (0..1_000).chain(0..500).chain(0..100).collect();
+        for batch in [small, large] {
+            let mut removed = 0;
+            for &key in &batch {
+                if let Some(count) = expected.get_mut(key as usize).filter(|count| **count > 0) {
+                    *count -= 1;
+                    removed += 1;
+                }
+            }
+            assert_eq!(tree.remove_many(batch), removed);
+            assert!(is_valid_btree(&tree.nodes, tree.root));
+            let keys: Vec<u32> = (0..1_000)
+                .flat_map(|key| core::iter::repeat_n(key, expected[key as usize]))
+                .collect();
+            assert!(tree.iter().eq(keys.iter()));
+        }
     }
 }
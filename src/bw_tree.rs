@@ -0,0 +1,666 @@
+use crate::segments::Segments;
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{self, AtomicPtr, AtomicUsize};
+
+/// The number of keys above which a consolidated page is split.
+const PAGE_CAPACITY: usize = 32;
+/// The number of delta records a page collects before it is consolidated.
+const MAX_CHAIN_LENGTH: usize = 8;
+
+type PageId = usize;
+
+/// An experimental latch-free B-Tree in the style of the Bw-tree.
+///
+/// Pages are never changed in place. A change is a delta record put in front of the page with
+/// a single compare-and-swap on its entry in a mapping table from page ids to records, and
+/// long chains of deltas are consolidated into a new page the same way.
+/// A page is split by a split delta which sends readers to its new right sibling, and the parent
+/// learns about the sibling later from an index entry delta, so no operation waits for another.
+///
+/// There is no epoch scheme to tell when no thread reads a replaced record any more,
+/// so replaced records are freed only when the tree is dropped, and pages are never merged.
+#[derive(Debug)]
+pub struct BwTree<T> {
+    // The mapping table.
+    pages: Segments<AtomicPtr<Record<T>>>,
+    root: AtomicUsize,
+    len: AtomicUsize,
+    // Chains which were consolidated away, freed on drop.
+    retired: AtomicPtr<Retired<T>>,
+    // The tree owns keys, which threads sharing it clone and drop.
+    marker: PhantomData<*const T>,
+}
+
+// SAFETY: Records are only changed before they are published, and the keys in them are cloned
+// and dropped by whichever thread uses the tree, which `T: Send + Sync` allows.
+unsafe impl<T: Send + Sync> Send for BwTree<T> {}
+unsafe impl<T: Send + Sync> Sync for BwTree<T> {}
+
+#[derive(Debug)]
+struct Record<T> {
+    kind: RecordKind<T>,
+    // The record this one was put in front of, or null for a base page.
+    next: *mut Record<T>,
+    // The number of deltas from this record to the base page.
+    chain_length: usize,
+    is_leaf: bool,
+}
+
+#[derive(Clone, Debug)]
+enum RecordKind<T> {
+    // Base pages. Keys at or above the key of `sibling` have moved to the sibling page.
+    Leaf {
+        keys: Vec<T>,
+        sibling: Option<(T, PageId)>,
+    },
+    Inner {
+        keys: Vec<T>,
+        children: Vec<PageId>,
+        sibling: Option<(T, PageId)>,
+    },
+    Insert(T),
+    Delete(T),
+    // Keys at or above `separator` have moved to the `right` sibling page.
+    Split {
+        separator: T,
+        right: PageId,
+    },
+    // Keys in `low..high` are in `child`.
+    IndexEntry {
+        low: T,
+        high: Option<T>,
+        child: PageId,
+    },
+}
+
+#[derive(Debug)]
+struct Retired<T> {
+    chain: *mut Record<T>,
+    next: *mut Retired<T>,
+}
+
+impl<T> BwTree<T>
+where
+    T: Ord + Clone,
+{
+    pub fn new() -> Self {
+        let tree = Self {
+            pages: Segments::new(),
+            root: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+            retired: AtomicPtr::new(ptr::null_mut()),
+            marker: PhantomData,
+        };
+        let root = tree.publish(Record::base(
+            RecordKind::Leaf {
+                keys: vec![],
+                sibling: None,
+            },
+            true,
+        ));
+        tree.root.store(root, atomic::Ordering::SeqCst);
+        tree
+    }
+
+    /// Returns the number of keys in the tree.
+    /// Updates running concurrently may or may not be counted yet.
+    pub fn len(&self) -> usize {
+        self.len.load(atomic::Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, key: &T) -> bool {
+        let mut id = self.find_leaf(key, &mut vec![]);
+        loop {
+            match search_leaf(self.head(id).1, key) {
+                Ok(is_found) => return is_found,
+                Err(right) => id = right,
+            }
+        }
+    }
+
+    /// Insert a key, returning whether it was not present yet.
+    pub fn insert(&self, key: T) -> bool {
+        self.update(RecordKind::Insert(key))
+    }
+
+    /// Remove a key, returning whether it was present.
+    pub fn remove(&self, key: &T) -> bool {
+        self.update(RecordKind::Delete(key.clone()))
+    }
+
+    /// Put an insert or delete delta in front of the leaf page a key belongs to,
+    /// unless it would not change the page.
+    fn update(&self, mut delta: RecordKind<T>) -> bool {
+        let is_insert = matches!(delta, RecordKind::Insert(_));
+        let mut path = vec![];
+        let mut id = match &delta {
+            RecordKind::Insert(key) | RecordKind::Delete(key) => self.find_leaf(key, &mut path),
+            _ => unreachable!("only inserts and deletes update a leaf"),
+        };
+        loop {
+            let (head, record) = self.head(id);
+            let key = match &delta {
+                RecordKind::Insert(key) | RecordKind::Delete(key) => key,
+                _ => unreachable!("only inserts and deletes update a leaf"),
+            };
+            match search_leaf(record, key) {
+                Err(right) => {
+                    id = right;
+                    continue;
+                }
+                Ok(is_found) if is_found == is_insert => return false,
+                Ok(_) => {}
+            }
+            // The key was checked against this very chain, so the delta applies if it is
+            // still the head.
+            match self.try_prepend(id, head, delta) {
+                Ok(chain_length) => {
+                    if is_insert {
+                        self.len.fetch_add(1, atomic::Ordering::SeqCst);
+                    } else {
+                        self.len.fetch_sub(1, atomic::Ordering::SeqCst);
+                    }
+                    if chain_length > MAX_CHAIN_LENGTH {
+                        self.consolidate(id, &path);
+                    }
+                    return true;
+                }
+                Err(rejected) => delta = rejected,
+            }
+        }
+    }
+
+    /// Returns the leaf page a key belongs to, pushing the inner pages on the way onto `path`.
+    fn find_leaf(&self, key: &T, path: &mut Vec<PageId>) -> PageId {
+        let mut id = self.root.load(atomic::Ordering::SeqCst);
+        loop {
+            let (_, record) = self.head(id);
+            if record.is_leaf {
+                return id;
+            }
+            match route(record, key) {
+                Ok(child) => {
+                    path.push(id);
+                    id = child;
+                }
+                Err(right) => id = right,
+            }
+        }
+    }
+
+    /// Returns the first record of a page.
+    fn head(&self, id: PageId) -> (*mut Record<T>, &Record<T>) {
+        let head = self.pages.get(id).load(atomic::Ordering::SeqCst);
+        // SAFETY: A page is published before its id is shared, and published records
+        // are freed only when the tree is dropped.
+        (head, unsafe { &*head })
+    }
+
+    /// Store a page in a new entry of the mapping table.
+    fn publish(&self, record: Record<T>) -> PageId {
+        let id = self.pages.allocate();
+        self.pages
+            .get(id)
+            .store(Box::into_raw(Box::new(record)), atomic::Ordering::SeqCst);
+        id
+    }
+
+    /// Put a delta in front of a page if `head` is still its first record, returning the length
+    /// of the new chain, or the delta back if another thread changed the page first.
+    fn try_prepend(
+        &self,
+        id: PageId,
+        head: *mut Record<T>,
+        kind: RecordKind<T>,
+    ) -> Result<usize, RecordKind<T>> {
+        // SAFETY: `head` was read from the mapping table, see `head`.
+        let next = unsafe { &*head };
+        let chain_length = next.chain_length + 1;
+        let delta = Box::into_raw(Box::new(Record {
+            kind,
+            next: head,
+            chain_length,
+            is_leaf: next.is_leaf,
+        }));
+        match self.pages.get(id).compare_exchange(
+            head,
+            delta,
+            atomic::Ordering::SeqCst,
+            atomic::Ordering::SeqCst,
+        ) {
+            Ok(_) => Ok(chain_length),
+            // SAFETY: The delta was never published.
+            Err(_) => Err(unsafe { Box::from_raw(delta) }.kind),
+        }
+    }
+
+    /// Replace the chain of a page with a single base page, splitting it if it grew too large.
+    /// Gives up if another thread changes the page meanwhile.
+    fn consolidate(&self, id: PageId, path: &[PageId]) {
+        let (head, record) = self.head(id);
+        let base = Box::into_raw(Box::new(record.consolidated()));
+        if self
+            .pages
+            .get(id)
+            .compare_exchange(
+                head,
+                base,
+                atomic::Ordering::SeqCst,
+                atomic::Ordering::SeqCst,
+            )
+            .is_err()
+        {
+            // SAFETY: The page was never published.
+            drop(unsafe { Box::from_raw(base) });
+            return;
+        }
+        self.retire(head);
+        // SAFETY: `base` is published now, see `head`.
+        if unsafe { &*base }.kind.len() > PAGE_CAPACITY {
+            self.split(id, base, path);
+        }
+    }
+
+    /// Move the upper half of a base page to a new right sibling, and tell the parent about it.
+    fn split(&self, id: PageId, base: *mut Record<T>, path: &[PageId]) {
+        // SAFETY: `base` was published by the caller, see `head`.
+        let record = unsafe { &*base };
+        let (separator, right, high) = match &record.kind {
+            RecordKind::Leaf { keys, sibling } => {
+                let at = keys.len() / 2;
+                let right = RecordKind::Leaf {
+                    keys: keys[at..].to_vec(),
+                    sibling: sibling.clone(),
+                };
+                (keys[at].clone(), right, sibling)
+            }
+            RecordKind::Inner {
+                keys,
+                children,
+                sibling,
+            } => {
+                // The separator moves up to the parent, so neither half keeps it.
+                let at = keys.len() / 2;
+                let right = RecordKind::Inner {
+                    keys: keys[at + 1..].to_vec(),
+                    children: children[at + 1..].to_vec(),
+                    sibling: sibling.clone(),
+                };
+                (keys[at].clone(), right, sibling)
+            }
+            _ => unreachable!("only base pages are split"),
+        };
+        let high = high.as_ref().map(|(high, _)| high.clone());
+        // If the split delta loses the race, the sibling stays unreachable until the tree is dropped.
+        let right = self.publish(Record::base(right, record.is_leaf));
+        let split = RecordKind::Split {
+            separator: separator.clone(),
+            right,
+        };
+        if self.try_prepend(id, base, split).is_err() {
+            return;
+        }
+        match path.split_last() {
+            Some((&parent, path)) => {
+                let entry = RecordKind::IndexEntry {
+                    low: separator,
+                    high,
+                    child: right,
+                };
+                self.post_index_entry(parent, entry, path);
+            }
+            None => self.grow(id, separator, right),
+        }
+    }
+
+    /// Put an index entry delta in front of a parent page. The split delta already sends
+    /// readers to the new page, so the entry only shortens their way.
+    fn post_index_entry(&self, parent: PageId, mut entry: RecordKind<T>, path: &[PageId]) {
+        loop {
+            let (head, _) = self.head(parent);
+            match self.try_prepend(parent, head, entry) {
+                Ok(chain_length) => {
+                    if chain_length > MAX_CHAIN_LENGTH {
+                        self.consolidate(parent, path);
+                    }
+                    return;
+                }
+                Err(rejected) => entry = rejected,
+            }
+        }
+    }
+
+    /// Put a new root above the root page which was just split.
+    /// Gives up if the page is no longer the root, leaving the sibling to be found by its split delta.
+    fn grow(&self, id: PageId, separator: T, right: PageId) {
+        let root = self.publish(Record::base(
+            RecordKind::Inner {
+                keys: vec![separator],
+                children: vec![id, right],
+                sibling: None,
+            },
+            false,
+        ));
+        let _ = self.root.compare_exchange(
+            id,
+            root,
+            atomic::Ordering::SeqCst,
+            atomic::Ordering::SeqCst,
+        );
+    }
+
+    /// Keep a chain which was replaced to free it when the tree is dropped,
+    /// since other threads may still be reading it.
+    fn retire(&self, chain: *mut Record<T>) {
+        let retired = Box::into_raw(Box::new(Retired {
+            chain,
+            next: self.retired.load(atomic::Ordering::SeqCst),
+        }));
+        loop {
+            // SAFETY: `retired` is not shared until the exchange below succeeds.
+            let next = unsafe { (*retired).next };
+            match self.retired.compare_exchange_weak(
+                next,
+                retired,
+                atomic::Ordering::SeqCst,
+                atomic::Ordering::SeqCst,
+            ) {
+                Ok(_) => return,
+                // SAFETY: The exchange failed, so `retired` is still owned by this thread alone.
+                Err(current) => unsafe { (*retired).next = current },
+            }
+        }
+    }
+}
+
+impl<T> Default for BwTree<T>
+where
+    T: Ord + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for BwTree<T> {
+    fn drop(&mut self) {
+        for id in 0..self.pages.len() {
+            free_chain(self.pages.get(id).load(atomic::Ordering::SeqCst));
+        }
+        let mut retired = *self.retired.get_mut();
+        while !retired.is_null() {
+            // SAFETY: Each retired chain was pushed once, and nothing reads it any more.
+            let node = unsafe { Box::from_raw(retired) };
+            free_chain(node.chain);
+            retired = node.next;
+        }
+    }
+}
+
+/// Free the records of a chain. Every record is in exactly one chain, either still
+/// in the mapping table or retired.
+fn free_chain<T>(mut record: *mut Record<T>) {
+    while !record.is_null() {
+        // SAFETY: The tree is being dropped, so no thread reads the record any more.
+        let freed = unsafe { Box::from_raw(record) };
+        record = freed.next;
+    }
+}
+
+impl<T> Record<T> {
+    fn base(kind: RecordKind<T>, is_leaf: bool) -> Self {
+        Self {
+            kind,
+            next: ptr::null_mut(),
+            chain_length: 0,
+            is_leaf,
+        }
+    }
+
+    fn next(&self) -> Option<&Record<T>> {
+        // SAFETY: Records in a published chain are freed only when the tree is dropped.
+        unsafe { self.next.as_ref() }
+    }
+
+    /// Returns a base page with the deltas of this chain applied.
+    fn consolidated(&self) -> Self
+    where
+        T: Ord + Clone,
+    {
+        let mut deltas = vec![];
+        let mut record = self;
+        while let Some(next) = record.next() {
+            deltas.push(&record.kind);
+            record = next;
+        }
+        let mut kind = record.kind.clone();
+        for delta in deltas.into_iter().rev() {
+            kind.apply(delta);
+        }
+        Self::base(kind, self.is_leaf)
+    }
+}
+
+impl<T> RecordKind<T>
+where
+    T: Ord + Clone,
+{
+    /// Returns the number of keys in a base page.
+    fn len(&self) -> usize {
+        match self {
+            Self::Leaf { keys, .. } | Self::Inner { keys, .. } => keys.len(),
+            _ => unreachable!("only base pages have a length"),
+        }
+    }
+
+    /// Apply a delta to a base page.
+    fn apply(&mut self, delta: &Self) {
+        match (self, delta) {
+            (Self::Leaf { keys, .. }, Self::Insert(key)) => {
+                if let Err(index) = keys.binary_search(key) {
+                    keys.insert(index, key.clone());
+                }
+            }
+            (Self::Leaf { keys, .. }, Self::Delete(key)) => {
+                if let Ok(index) = keys.binary_search(key) {
+                    keys.remove(index);
+                }
+            }
+            (Self::Leaf { keys, sibling }, Self::Split { separator, right }) => {
+                keys.truncate(keys.partition_point(|key| key < separator));
+                *sibling = Some((separator.clone(), *right));
+            }
+            (
+                Self::Inner {
+                    keys,
+                    children,
+                    sibling,
+                },
+                Self::Split { separator, right },
+            ) => {
+                let len = keys.partition_point(|key| key < separator);
+                keys.truncate(len);
+                children.truncate(len + 1);
+                *sibling = Some((separator.clone(), *right));
+            }
+            (
+                Self::Inner {
+                    keys,
+                    children,
+                    sibling,
+                },
+                Self::IndexEntry { low, child, .. },
+            ) => {
+                // An entry posted after this page split may belong to the sibling.
+                if sibling.as_ref().is_some_and(|(high, _)| low >= high) {
+                    return;
+                }
+                if let Err(index) = keys.binary_search(low) {
+                    keys.insert(index, low.clone());
+                    children.insert(index + 1, *child);
+                }
+            }
+            _ => unreachable!("delta does not apply to this kind of page"),
+        }
+    }
+}
+
+/// Returns whether a key is in the leaf page starting at `record`,
+/// or the sibling page to look in instead.
+fn search_leaf<T: Ord>(mut record: &Record<T>, key: &T) -> Result<bool, PageId> {
+    loop {
+        match &record.kind {
+            RecordKind::Insert(inserted) if inserted == key => return Ok(true),
+            RecordKind::Delete(deleted) if deleted == key => return Ok(false),
+            RecordKind::Split { separator, right } if key >= separator => return Err(*right),
+            RecordKind::Leaf { keys, sibling } => {
+                return match sibling {
+                    Some((high, right)) if key >= high => Err(*right),
+                    _ => Ok(keys.binary_search(key).is_ok()),
+                };
+            }
+            _ => {}
+        }
+        record = record.next().expect("a chain ends in a base page");
+    }
+}
+
+/// Returns the child of the inner page starting at `record` which a key belongs to,
+/// or the sibling page to look in instead.
+fn route<T: Ord>(mut record: &Record<T>, key: &T) -> Result<PageId, PageId> {
+    loop {
+        match &record.kind {
+            RecordKind::IndexEntry { low, high, child }
+                if low <= key && high.as_ref().is_none_or(|high| key < high) =>
+            {
+                return Ok(*child)
+            }
+            RecordKind::Split { separator, right } if key >= separator => return Err(*right),
+            RecordKind::Inner {
+                keys,
+                children,
+                sibling,
+            } => {
+                return match sibling {
+                    Some((high, right)) if key >= high => Err(*right),
+                    _ => Ok(children[keys.partition_point(|separator| separator <= key)]),
+                };
+            }
+            _ => {}
+        }
+        record = record.next().expect("a chain ends in a base page");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bw_tree::{BwTree, RecordKind};
+    use rand::Rng;
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    // Collects the keys of the tree by walking its leaves from the left.
+    fn collect_keys<T: Ord + Clone>(tree: &BwTree<T>) -> Vec<T> {
+        let mut id = tree.root.load(std::sync::atomic::Ordering::SeqCst);
+        let mut keys = vec![];
+        loop {
+            match tree.head(id).1.consolidated().kind {
+                RecordKind::Inner { children, .. } => id = children[0],
+                RecordKind::Leaf {
+                    keys: leaf,
+                    sibling,
+                } => {
+                    keys.extend(leaf);
+                    match sibling {
+                        Some((_, right)) => id = right,
+                        None => return keys,
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn update_like_btree_set() {
+        let tree = BwTree::new();
+        let mut expected = BTreeSet::new();
+        let mut rng = rand::thread_rng();
+        for _ in 0..20_000 {
+            let key: u32 = rng.gen_range(0, 3_000);
+            if rng.gen_bool(0.7) {
+                assert_eq!(tree.insert(key), expected.insert(key));
+            } else {
+                assert_eq!(tree.remove(&key), expected.remove(&key));
+            }
+        }
+        assert_eq!(tree.len(), expected.len());
+        assert!(
+            !tree
+                .head(tree.root.load(std::sync::atomic::Ordering::SeqCst))
+                .1
+                .is_leaf
+        );
+        for key in 0..3_000 {
+            assert_eq!(tree.contains(&key), expected.contains(&key));
+        }
+        assert_eq!(
+            collect_keys(&tree),
+            expected.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn update_from_many_threads() {
+        let tree = Arc::new(BwTree::new());
+        let handles: Vec<_> = (0..4u32)
+            .map(|thread_index| {
+                let tree = Arc::clone(&tree);
+                // Threads insert overlapping keys, and remove the multiples of 3 they inserted.
+                thread::spawn(move || {
+                    let mut inserted = 0;
+                    for key in (0..5_000u32).map(|i| (i * 7 + thread_index * 1_000) % 12_000) {
+                        if tree.insert(key) {
+                            inserted += 1;
+                            if key % 3 == 0 {
+                                assert!(tree.remove(&key));
+                                inserted -= 1;
+                            }
+                        }
+                    }
+                    inserted
+                })
+            })
+            .collect();
+        let inserted: usize = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum();
+        assert_eq!(tree.len(), inserted);
+
+        let keys = collect_keys(&tree);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(keys.len(), inserted);
+        assert!(keys.iter().all(|&key| key % 3 != 0 && tree.contains(&key)));
+    }
+
+    #[test]
+    fn drop_every_key_once() {
+        let keys: Vec<_> = (0..1_000).map(Arc::new).collect();
+        let tree = BwTree::new();
+        for key in &keys {
+            tree.insert(Arc::clone(key));
+        }
+        for key in keys.iter().step_by(2) {
+            tree.remove(key);
+        }
+        drop(tree);
+        assert!(keys.iter().all(|key| Arc::strong_count(key) == 1));
+    }
+}
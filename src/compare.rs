@@ -0,0 +1,161 @@
+use core::cmp::Ordering;
+#[cfg(feature = "metrics")]
+use core::sync::atomic::{self, AtomicUsize};
+
+/// Comparator decides the order of keys in a tree.
+/// Any closure taking two keys and returning their `Ordering` is a comparator.
+pub trait Comparator<T> {
+    /// Nodes of trees whose order is up to this are searched with `count_less` instead of
+    /// bisection. Scanning wider nodes takes too many comparisons, as `benches/lookup.rs` shows.
+    const LINEAR_SEARCH_ORDER: usize = 16;
+
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+
+    /// Returns the number of keys less than `key` in sorted `keys`.
+    fn count_less(&self, keys: &[T], key: &T) -> usize {
+        // Counting all smaller keys instead of stopping at the first greater one leaves no branch
+        // to mispredict within the loop.
+        keys.iter()
+            .filter(|probe| self.compare(probe, key) == Ordering::Less)
+            .count()
+    }
+}
+
+/// A comparator ordering keys by their `Ord` implementation.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct OrdComparator;
+
+impl<T> Comparator<T> for OrdComparator
+where
+    T: Ord,
+{
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Integer keys which `SimdComparator` compares with many keys at once.
+#[cfg(feature = "simd")]
+pub trait SimdKey: Ord + Copy {
+    /// Returns the number of keys less than `key` in `keys`.
+    fn count_less(keys: &[Self], key: Self) -> usize;
+}
+
+#[cfg(feature = "simd")]
+macro_rules! impl_simd_key {
+    ($($int:ty),*) => {
+        $(
+            impl SimdKey for $int {
+                fn count_less(keys: &[Self], key: Self) -> usize {
+                    // Summing the outcomes of all comparisons, without branches, lets
+                    // the compiler compare a vector of keys at a time.
+                    #[inline(always)]
+                    fn count(keys: &[$int], key: $int) -> usize {
+                        keys.iter().map(|&probe| usize::from(probe < key)).sum()
+                    }
+
+                    // Baseline x86-64 has no instruction comparing 64-bit integers in vectors,
+                    // so the loop is compiled for AVX2 as well and picked if the CPU has it.
+                    #[cfg(target_arch = "x86_64")]
+                    {
+                        #[target_feature(enable = "avx2")]
+                        unsafe fn count_avx2(keys: &[$int], key: $int) -> usize {
+                            count(keys, key)
+                        }
+
+                        if std::is_x86_feature_detected!("avx2") {
+                            // SAFETY: The CPU supports AVX2.
+                            return unsafe { count_avx2(keys, key) };
+                        }
+                    }
+                    count(keys, key)
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "simd")]
+impl_simd_key!(u32, u64, i64);
+
+/// A comparator ordering integer keys as `OrdComparator` does, but comparing a key with many
+/// keys of a node at once, using AVX2 if the CPU has it. Nodes are scanned instead of bisected
+/// up to a wider order than with other comparators.
+#[cfg(feature = "simd")]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SimdComparator;
+
+#[cfg(feature = "simd")]
+impl<T> Comparator<T> for SimdComparator
+where
+    T: SimdKey,
+{
+    const LINEAR_SEARCH_ORDER: usize = 64;
+
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn count_less(&self, keys: &[T], key: &T) -> usize {
+        T::count_less(keys, *key)
+    }
+}
+
+/// The comparator of a `BTree`, which counts the comparisons it makes when the `metrics`
+/// feature is enabled.
+#[derive(Debug)]
+pub(crate) struct Counted<C> {
+    pub(crate) inner: C,
+    // Atomic so that trees shared between threads can still count lookups.
+    #[cfg(feature = "metrics")]
+    pub(crate) comparisons: AtomicUsize,
+}
+
+impl<C> Counted<C> {
+    pub(crate) fn new(inner: C) -> Self {
+        Self {
+            inner,
+            #[cfg(feature = "metrics")]
+            comparisons: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<C: Clone> Clone for Counted<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            #[cfg(feature = "metrics")]
+            comparisons: AtomicUsize::new(self.comparisons.load(atomic::Ordering::Relaxed)),
+        }
+    }
+}
+
+impl<T, C> Comparator<T> for Counted<C>
+where
+    C: Comparator<T>,
+{
+    const LINEAR_SEARCH_ORDER: usize = C::LINEAR_SEARCH_ORDER;
+
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        #[cfg(feature = "metrics")]
+        self.comparisons.fetch_add(1, atomic::Ordering::Relaxed);
+        self.inner.compare(a, b)
+    }
+
+    fn count_less(&self, keys: &[T], key: &T) -> usize {
+        #[cfg(feature = "metrics")]
+        self.comparisons
+            .fetch_add(keys.len(), atomic::Ordering::Relaxed);
+        self.inner.count_less(keys, key)
+    }
+}
+
+impl<T, F> Comparator<T> for F
+where
+    F: Fn(&T, &T) -> Ordering,
+{
+    fn compare(&self, a: &T, b: &T) -> Ordering {
+        self(a, b)
+    }
+}
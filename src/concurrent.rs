@@ -0,0 +1,293 @@
+use std::cmp::Ordering;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// The smallest order a `ConcurrentBTree` accepts. A full node is split before an insertion
+/// descends into it, and both halves must keep at least one key.
+pub const MIN_CONCURRENT_ORDER: usize = 4;
+
+/// A B-Tree which many threads can read and insert into at once.
+/// Every node has its own latch, and operations hold at most a parent and a child latch at a time
+/// while descending (lock coupling), so writers in different subtrees do not block each other.
+///
+/// Full nodes are split on the way down, which guarantees that a split never propagates to
+/// a parent whose latch has already been released.
+///
+/// Readers take shared latches too, as reading a node of arbitrary keys while a writer changes
+/// it would be a data race. `OptimisticBTree` keeps integer keys in atomics instead, so its
+/// readers validate node versions without latching.
+#[derive(Debug)]
+pub struct ConcurrentBTree<T> {
+    // The maximum number of children each node can accomodates.
+    order: usize,
+    // The latch on the pointer is held only until the latch on the root itself is taken.
+    root: RwLock<Arc<RwLock<ConcurrentNode<T>>>>,
+    len: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct ConcurrentNode<T> {
+    keys: Vec<T>,
+    children: Vec<Arc<RwLock<ConcurrentNode<T>>>>,
+}
+
+impl<T> ConcurrentBTree<T>
+where
+    T: Ord,
+{
+    /// Create an empty tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_CONCURRENT_ORDER`.
+    pub fn new(order: usize) -> Self {
+        assert!(
+            order >= MIN_CONCURRENT_ORDER,
+            "order of a ConcurrentBTree must be at least {}, but got {}",
+            MIN_CONCURRENT_ORDER,
+            order
+        );
+        Self {
+            order,
+            root: RwLock::new(Arc::new(RwLock::new(ConcurrentNode::new()))),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of keys in the tree.
+    /// Insertions running concurrently may or may not be counted yet.
+    pub fn len(&self) -> usize {
+        self.len.load(atomic::Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, key: &T) -> bool {
+        self.find(key, |_| ()).is_some()
+    }
+
+    /// Search the tree for a given key, returning a copy of the element stored in the tree.
+    pub fn get(&self, key: &T) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.find(key, T::clone)
+    }
+
+    /// Look a key up, applying `f` to the stored element while its node is latched.
+    fn find<R>(&self, key: &T, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let root_pointer = self.root.read().unwrap();
+        let root = Arc::clone(&root_pointer);
+        let guard = root.read().unwrap();
+        drop(root_pointer);
+        find_in(guard, key, f)
+    }
+
+    /// Insert a key, returning whether it was not present yet.
+    pub fn insert(&self, key: T) -> bool {
+        loop {
+            let root_pointer = self.root.read().unwrap();
+            let root = Arc::clone(&root_pointer);
+            let guard = root.write().unwrap();
+            drop(root_pointer);
+            if !guard.is_full(self.order) {
+                let is_inserted = insert_into(guard, key, self.order);
+                if is_inserted {
+                    self.len.fetch_add(1, atomic::Ordering::AcqRel);
+                }
+                return is_inserted;
+            }
+            drop(guard);
+            self.grow();
+        }
+    }
+
+    /// Put a new root above the current one if it is full.
+    fn grow(&self) {
+        let mut root_pointer = self.root.write().unwrap();
+        let old_root = Arc::clone(&root_pointer);
+        let mut old_guard = old_root.write().unwrap();
+        // Another thread may have grown the tree since the caller released the root.
+        if !old_guard.is_full(self.order) {
+            return;
+        }
+        let mut new_root = ConcurrentNode::new();
+        new_root.children.push(Arc::clone(&old_root));
+        new_root.split_children(0, &mut old_guard);
+        *root_pointer = Arc::new(RwLock::new(new_root));
+    }
+}
+
+impl<T> ConcurrentNode<T>
+where
+    T: Ord,
+{
+    fn new() -> Self {
+        Self {
+            keys: vec![],
+            children: vec![],
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    fn is_full(&self, order: usize) -> bool {
+        self.keys.len() == order - 1
+    }
+
+    /// Split a full child, whose latch the caller holds, moving its median key up to this node.
+    fn split_children(&mut self, index: usize, child: &mut ConcurrentNode<T>) {
+        let split_at = child.keys.len() / 2;
+        let right_child = ConcurrentNode {
+            keys: child.keys.split_off(split_at + 1),
+            children: if child.is_leaf() {
+                vec![]
+            } else {
+                child.children.split_off(split_at + 1)
+            },
+        };
+        let ascending_key = child.keys.pop().unwrap();
+        self.keys.insert(index, ascending_key);
+        self.children
+            .insert(index + 1, Arc::new(RwLock::new(right_child)));
+    }
+}
+
+/// Look a key up below a latched node, taking the latch of a child before releasing its parent.
+fn find_in<T: Ord, R>(
+    guard: RwLockReadGuard<'_, ConcurrentNode<T>>,
+    key: &T,
+    f: impl FnOnce(&T) -> R,
+) -> Option<R> {
+    let index = match guard.keys.binary_search(key) {
+        Ok(index) => return Some(f(&guard.keys[index])),
+        Err(_) if guard.is_leaf() => return None,
+        Err(index) => index,
+    };
+    let child = Arc::clone(&guard.children[index]);
+    let child_guard = child.read().unwrap();
+    drop(guard);
+    find_in(child_guard, key, f)
+}
+
+/// Insert a key below a latched node which is not full, splitting full children on the way down.
+fn insert_into<T: Ord>(
+    mut guard: RwLockWriteGuard<'_, ConcurrentNode<T>>,
+    key: T,
+    order: usize,
+) -> bool {
+    let index = match guard.keys.binary_search(&key) {
+        Ok(_) => return false,
+        Err(index) if guard.is_leaf() => {
+            guard.keys.insert(index, key);
+            return true;
+        }
+        Err(index) => index,
+    };
+    let child = Arc::clone(&guard.children[index]);
+    let mut child_guard = child.write().unwrap();
+    if !child_guard.is_full(order) {
+        drop(guard);
+        return insert_into(child_guard, key, order);
+    }
+    guard.split_children(index, &mut child_guard);
+    let index = match key.cmp(&guard.keys[index]) {
+        Ordering::Less => {
+            drop(guard);
+            return insert_into(child_guard, key, order);
+        }
+        Ordering::Equal => return false,
+        Ordering::Greater => index + 1,
+    };
+    drop(child_guard);
+    let child = Arc::clone(&guard.children[index]);
+    let child_guard = child.write().unwrap();
+    drop(guard);
+    insert_into(child_guard, key, order)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concurrent::{ConcurrentBTree, ConcurrentNode};
+    use std::sync::Arc;
+    use std::thread;
+
+    // Asserts given subtree is valid, collecting its keys in order, and returns its height.
+    fn is_valid_subtree(node: &ConcurrentNode<u32>, order: usize, keys: &mut Vec<u32>) -> usize {
+        assert!(node.keys.len() < order);
+        if node.is_leaf() {
+            keys.extend(node.keys.iter().copied());
+            return 1;
+        }
+        assert_eq!(node.keys.len() + 1, node.children.len());
+        let mut heights = vec![];
+        for (i, child) in node.children.iter().enumerate() {
+            let child = child.read().unwrap();
+            assert!(!child.keys.is_empty());
+            heights.push(is_valid_subtree(&child, order, keys));
+            if let Some(key) = node.keys.get(i) {
+                keys.push(*key);
+            }
+        }
+        // All leaves must be at the same depth.
+        assert!(heights.iter().all(|&height| height == heights[0]));
+        heights[0] + 1
+    }
+
+    #[test]
+    fn insert_from_many_threads() {
+        for &order in &[4, 5, 16] {
+            let tree = Arc::new(ConcurrentBTree::new(order));
+            let handles: Vec<_> = (0..4u32)
+                .map(|thread_index| {
+                    let tree = Arc::clone(&tree);
+                    // Threads insert overlapping keys, so some insertions find the key present.
+                    thread::spawn(move || {
+                        (0..5_000u32)
+                            .map(|i| (i * 7 + thread_index * 1_000) % 12_000)
+                            .filter(|&key| tree.insert(key))
+                            .count()
+                    })
+                })
+                .collect();
+            let inserted: usize = handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .sum();
+            assert_eq!(tree.len(), inserted);
+
+            let mut keys = vec![];
+            let root = tree.root.read().unwrap();
+            is_valid_subtree(&root.read().unwrap(), order, &mut keys);
+            assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(keys.len(), inserted);
+            for key in keys {
+                assert!(tree.contains(&key));
+                assert_eq!(tree.get(&key), Some(key));
+            }
+        }
+    }
+
+    #[test]
+    fn lookup_while_inserting() {
+        let tree = Arc::new(ConcurrentBTree::new(4));
+        let writer = {
+            let tree = Arc::clone(&tree);
+            thread::spawn(move || (0..10_000u32).for_each(|key| assert!(tree.insert(key * 2))))
+        };
+        // Odd keys are never inserted, and even keys once found stay in the tree.
+        let mut found = 0;
+        while found < 10_000 {
+            assert!(!tree.contains(&(found * 2 + 1)));
+            if tree.contains(&(found * 2)) {
+                found += 1;
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(tree.len(), 10_000);
+    }
+}
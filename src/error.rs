@@ -0,0 +1,145 @@
+use crate::btree::MIN_ORDER;
+use core::error::Error;
+use core::fmt;
+
+/// An error returned when a tree is created with an order too small to keep it balanced.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InvalidOrder {
+    order: usize,
+}
+
+impl InvalidOrder {
+    pub(crate) fn new(order: usize) -> Self {
+        Self { order }
+    }
+
+    /// Returns the rejected order.
+    pub fn order(&self) -> usize {
+        self.order
+    }
+}
+
+impl fmt::Display for InvalidOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "order of a B-Tree must be at least {}, but got {}",
+            MIN_ORDER, self.order
+        )
+    }
+}
+
+impl Error for InvalidOrder {}
+
+/// An error returned when a cursor is asked to insert a key which does not fit between
+/// the keys around its position.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UnorderedKeyError;
+
+impl fmt::Display for UnorderedKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key is not properly ordered relative to neighbors")
+    }
+}
+
+impl Error for UnorderedKeyError {}
+
+/// An error returned by `BTree::try_insert` when the duplicate policy is
+/// `DuplicatePolicy::Error` and an equal key is present already. It gives back the rejected key.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DuplicateKeyError<T> {
+    pub key: T,
+}
+
+impl<T: fmt::Debug> fmt::Display for DuplicateKeyError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "key {:?} is present already", self.key)
+    }
+}
+
+impl<T: fmt::Debug> Error for DuplicateKeyError<T> {}
+
+/// An error returned by `BPlusTree::try_insert` when the key is present already.
+/// It gives back the rejected key and value, along with the value already in the tree.
+#[derive(Debug, Eq, PartialEq)]
+pub struct OccupiedError<'a, K, V> {
+    pub key: K,
+    pub value: V,
+    pub existing: &'a mut V,
+}
+
+impl<K, V> fmt::Display for OccupiedError<'_, K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "key {:?} is present already with value {:?}, so {:?} was not inserted",
+            self.key, self.existing, self.value
+        )
+    }
+}
+
+impl<K, V> Error for OccupiedError<'_, K, V>
+where
+    K: fmt::Debug,
+    V: fmt::Debug,
+{
+}
+
+/// An error returned by `BTree::to_wire` and `BTree::from_wire`.
+#[cfg(feature = "wire")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WireError {
+    /// The bytes do not start like the wire format.
+    NotATree,
+    /// The bytes were written by a newer version of the format than this one reads.
+    UnsupportedVersion { found: u16, supported: u16 },
+    /// The bytes are cut short, or hold an invalid order, keys out of order or trailing bytes.
+    InvalidData(String),
+    /// The tree to encode holds equal keys, which the format cannot hold.
+    EqualKeys,
+}
+
+#[cfg(feature = "wire")]
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::NotATree => write!(f, "not a tree in the wire format"),
+            WireError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "wire format version {} is newer than the supported version {}",
+                found, supported
+            ),
+            WireError::InvalidData(reason) => write!(f, "invalid wire data: {}", reason),
+            WireError::EqualKeys => write!(f, "a tree with equal keys cannot be encoded"),
+        }
+    }
+}
+
+#[cfg(feature = "wire")]
+impl Error for WireError {}
+
+/// An error returned by `BTree::replay` when the sequence numbers of the records skip
+/// or repeat an operation, so the records are not a whole log.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReplayError {
+    /// The sequence number the next record should have had.
+    pub expected: u64,
+    /// The sequence number of the record found instead.
+    pub found: u64,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected operation {} of the log, but found operation {}",
+            self.expected, self.found
+        )
+    }
+}
+
+impl Error for ReplayError {}
@@ -0,0 +1,78 @@
+//! A driver applying operations decoded from arbitrary bytes to a `BTree` and to
+//! `BTreeSet`, panicking as soon as they disagree. A cargo-fuzz target only needs to call `run`:
+//!
+//! `fuzz_target!(|data: &[u8]| rusty_btree::fuzz::run(data));`
+
+use crate::btree::{BTree, MIN_ORDER};
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+/// Keys are taken modulo this, so that operations often hit keys inserted before.
+const KEY_SPACE: u16 = 1024;
+
+/// Apply the operations encoded in `data` and check the tree against `BTreeSet`.
+/// The first byte chooses the order, and each operation takes four bytes: the kind of the
+/// operation, a key of two bytes and a length for range operations.
+pub fn run(data: &[u8]) {
+    run_with(data, |_| {});
+}
+
+/// Same as `run`, calling `check` on the tree after every operation.
+pub(crate) fn run_with(data: &[u8], mut check: impl FnMut(&BTree<u32>)) {
+    let (&order, ops) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let mut tree = BTree::new(MIN_ORDER + order as usize % 30);
+    let mut expected = BTreeSet::new();
+    for op in ops.chunks_exact(4) {
+        let key = u32::from(u16::from_le_bytes([op[1], op[2]]) % KEY_SPACE);
+        let len = op[3];
+        let end = key + u32::from(len);
+        match op[0] % 8 {
+            0 | 1 => {
+                tree.insert(key);
+                expected.insert(key);
+            }
+            2 => assert_eq!(tree.remove(&key), expected.remove(&key)),
+            3 => assert_eq!(tree.get(&key), expected.get(&key)),
+            4 => {
+                assert_eq!(tree.count_range(key..end), expected.range(key..end).count());
+                assert_eq!(tree.get_ge(&key), expected.range(key..).next());
+                assert_eq!(tree.get_lt(&key), expected.range(..key).next_back());
+            }
+            5 => {
+                let index = len as usize % (expected.len() + 1);
+                assert_eq!(tree.select(index), expected.iter().nth(index));
+                assert_eq!(tree.rank(&key), expected.range(..key).count());
+            }
+            6 => {
+                // Walk forward from the key, then back from it.
+                let mut cursor = tree.cursor();
+                cursor.seek(&key);
+                let mut forward = Vec::new();
+                let mut current = cursor.key();
+                while let Some(found) = current.filter(|_| forward.len() < len as usize) {
+                    forward.push(found);
+                    current = cursor.next();
+                }
+                let expected_forward: Vec<_> = expected.range(key..).take(len as usize).collect();
+                assert_eq!(forward, expected_forward);
+
+                cursor.seek(&key);
+                let backward: Vec<_> = (0..len).map_while(|_| cursor.prev()).collect();
+                let expected_backward: Vec<_> =
+                    expected.range(..key).rev().take(len as usize).collect();
+                assert_eq!(backward, expected_backward);
+            }
+            _ => {
+                let removed = expected.range(key..end).count();
+                expected.retain(|stored| !(key..end).contains(stored));
+                assert_eq!(tree.remove_range(key..end), removed);
+            }
+        }
+        check(&tree);
+        assert_eq!(tree.len(), expected.len());
+    }
+    assert!(tree.iter().eq(expected.iter()));
+}
@@ -0,0 +1,516 @@
+use crate::btree::check_order;
+use crate::error::InvalidOrder;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::mem;
+use core::ops::Range;
+
+/// Index of a node in the arena of an `IntervalTree`.
+type NodeId = usize;
+
+/// This structure represents a set of half-open intervals, laid out as a B-Tree ordered by
+/// the start and then the end of intervals. Each node also knows the largest end in its subtree,
+/// so a query skips subtrees whose intervals all end before the queried point or range.
+#[derive(Clone, Debug)]
+pub struct IntervalTree<T> {
+    // The maximum number of children each node can accomodates.
+    order: usize,
+    // All nodes of the tree. Nodes refer to their children by index into this vector.
+    nodes: Vec<IntervalNode<T>>,
+    // Slots of `nodes` released by merges, reused by later splits.
+    free: Vec<NodeId>,
+    root: NodeId,
+    len: usize,
+}
+
+/// A node of an `IntervalTree`.
+#[derive(Clone, Debug)]
+struct IntervalNode<T> {
+    intervals: Vec<Range<T>>,
+    // Child nodes. Empty for leaves.
+    children: Vec<NodeId>,
+    // The largest end of the intervals in the subtree, or `None` if it has no interval.
+    max_end: Option<T>,
+}
+
+impl<T> IntervalTree<T>
+where
+    T: Ord + Clone,
+{
+    /// Create an empty tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_ORDER`. Use `try_new` to handle it as an error.
+    pub fn new(order: usize) -> Self {
+        Self::try_new(order).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Create an empty tree, failing if `order` is less than `MIN_ORDER`.
+    pub fn try_new(order: usize) -> Result<Self, InvalidOrder> {
+        check_order(order)?;
+        Ok(Self {
+            order,
+            nodes: vec![IntervalNode::new()],
+            free: vec![],
+            root: 0,
+            len: 0,
+        })
+    }
+
+    /// Returns the number of intervals in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Insert an interval, returning whether it was not present yet.
+    pub fn insert(&mut self, interval: Range<T>) -> bool {
+        if !self.insert_at(self.root, interval) {
+            return false;
+        }
+        self.len += 1;
+        if self.nodes[self.root].intervals.len() == self.order {
+            let mut root = IntervalNode::new();
+            root.children.push(self.root);
+            root.max_end = self.nodes[self.root].max_end.clone();
+            self.root = self.allocate(root);
+            self.split_children(self.root, 0);
+        }
+        true
+    }
+
+    /// Remove an interval, returning whether it was present.
+    pub fn remove(&mut self, interval: &Range<T>) -> bool {
+        if !self.remove_at(self.root, interval) {
+            return false;
+        }
+        self.len -= 1;
+        // The root has lost its last interval, so its only child becomes the new root.
+        let root = &self.nodes[self.root];
+        if root.intervals.is_empty() && !root.children.is_empty() {
+            let child = root.children[0];
+            self.release(self.root);
+            self.root = child;
+        }
+        true
+    }
+
+    /// Returns the intervals containing a point, in ascending order of their starts.
+    pub fn query_point(&self, point: &T) -> Vec<&Range<T>> {
+        let mut found = vec![];
+        self.collect(self.root, point, &|start| start <= point, &mut found);
+        found
+    }
+
+    /// Returns the intervals sharing at least one point with a range, in ascending order
+    /// of their starts. Empty intervals hold no point, so they overlap nothing.
+    pub fn query_overlap(&self, range: &Range<T>) -> Vec<&Range<T>> {
+        let mut found = vec![];
+        if range.start < range.end {
+            self.collect(
+                self.root,
+                &range.start,
+                &|start| start < &range.end,
+                &mut found,
+            );
+        }
+        found
+    }
+
+    /// Collect non-empty intervals of a subtree which end after `after` and whose starts
+    /// satisfy `is_before`. Intervals are ordered by their starts, so the search stops at
+    /// the first start which does not.
+    /// Returns `false` once it has stopped, so that callers skip the rest of the tree.
+    fn collect<'a>(
+        &'a self,
+        id: NodeId,
+        after: &T,
+        is_before: &dyn Fn(&T) -> bool,
+        found: &mut Vec<&'a Range<T>>,
+    ) -> bool {
+        let node = &self.nodes[id];
+        if node.max_end.as_ref().is_none_or(|end| end <= after) {
+            return true;
+        }
+        for index in 0..=node.intervals.len() {
+            if let Some(&child) = node.children.get(index) {
+                if !self.collect(child, after, is_before, found) {
+                    return false;
+                }
+            }
+            let Some(interval) = node.intervals.get(index) else {
+                break;
+            };
+            if !is_before(&interval.start) {
+                return false;
+            }
+            if &interval.end > after && interval.start < interval.end {
+                found.push(interval);
+            }
+        }
+        true
+    }
+
+    /// Insert an interval into a subtree, returning whether it was not present yet.
+    /// Children which overflow are split, but the node `id` may be left overflowing.
+    fn insert_at(&mut self, id: NodeId, interval: Range<T>) -> bool {
+        let node = &self.nodes[id];
+        let index = match node.search(&interval) {
+            Ok(_) => return false,
+            Err(index) => index,
+        };
+        let end = interval.end.clone();
+        if node.children.is_empty() {
+            self.nodes[id].intervals.insert(index, interval);
+        } else {
+            let child = node.children[index];
+            if !self.insert_at(child, interval) {
+                return false;
+            }
+            if self.nodes[child].intervals.len() == self.order {
+                self.split_children(id, index);
+            }
+        }
+        let node = &mut self.nodes[id];
+        if node.max_end.as_ref().is_none_or(|max_end| *max_end < end) {
+            node.max_end = Some(end);
+        }
+        true
+    }
+
+    /// Split an overflowing child into two and move its middle interval up into the node.
+    /// The largest end of the node does not change, while both halves compute theirs again.
+    fn split_children(&mut self, id: NodeId, index: usize) {
+        let child_id = self.nodes[id].children[index];
+        let split_at = self.order / 2;
+        let child = &mut self.nodes[child_id];
+        let mut right_child = IntervalNode::new();
+        right_child.intervals = child.intervals.split_off(split_at + 1);
+        if !child.children.is_empty() {
+            right_child.children = child.children.split_off(split_at + 1);
+        }
+        let middle = child.intervals.pop().unwrap();
+        let right_id = self.allocate(right_child);
+        self.update_max_end(child_id);
+        self.update_max_end(right_id);
+        let node = &mut self.nodes[id];
+        node.intervals.insert(index, middle);
+        node.children.insert(index + 1, right_id);
+    }
+
+    /// Remove an interval from a subtree, returning whether it was present.
+    /// Children which underflow are refilled, but the node `id` may be left underflowing.
+    fn remove_at(&mut self, id: NodeId, interval: &Range<T>) -> bool {
+        let node = &mut self.nodes[id];
+        match (node.search(interval), node.children.is_empty()) {
+            (Ok(index), true) => {
+                node.intervals.remove(index);
+            }
+            (Err(_), true) => return false,
+            (Ok(index), false) => {
+                // Replace the interval with its predecessor, the last one of the left subtree.
+                let child = node.children[index];
+                self.nodes[id].intervals[index] = self.remove_last(child);
+                self.refill_child(id, index);
+            }
+            (Err(index), false) => {
+                let child = node.children[index];
+                if !self.remove_at(child, interval) {
+                    return false;
+                }
+                self.refill_child(id, index);
+            }
+        }
+        self.update_max_end(id);
+        true
+    }
+
+    /// Remove the last interval of a subtree, refilling its children on the way.
+    fn remove_last(&mut self, id: NodeId) -> Range<T> {
+        let node = &mut self.nodes[id];
+        let last = if node.children.is_empty() {
+            node.intervals.pop().unwrap()
+        } else {
+            let index = node.children.len() - 1;
+            let child = node.children[index];
+            let last = self.remove_last(child);
+            self.refill_child(id, index);
+            last
+        };
+        self.update_max_end(id);
+        last
+    }
+
+    /// Refill a child left with fewer than the minimum number of intervals, by moving
+    /// an interval over from a sibling through the node, or merging it with a sibling.
+    /// The largest end of the node itself is left to the caller.
+    fn refill_child(&mut self, id: NodeId, index: usize) {
+        let min_len = (self.order - 1) / 2;
+        let node = &self.nodes[id];
+        if self.nodes[node.children[index]].intervals.len() >= min_len {
+            return;
+        }
+        let sibling_len = |index: usize| {
+            node.children
+                .get(index)
+                .map_or(0, |&sibling| self.nodes[sibling].intervals.len())
+        };
+        if index > 0 && sibling_len(index - 1) > min_len {
+            self.rotate(id, index - 1, true);
+        } else if sibling_len(index + 1) > min_len {
+            self.rotate(id, index, false);
+        } else if index > 0 {
+            self.merge_children(id, index - 1);
+        } else {
+            self.merge_children(id, index);
+        }
+    }
+
+    /// Move an interval between `children[index]` and `children[index + 1]` through the node,
+    /// to the right one if `to_right`, along with the child next to it.
+    fn rotate(&mut self, id: NodeId, index: usize, to_right: bool) {
+        let (left, right) = (
+            self.nodes[id].children[index],
+            self.nodes[id].children[index + 1],
+        );
+        let (from, to) = if to_right {
+            (left, right)
+        } else {
+            (right, left)
+        };
+        let from_node = &mut self.nodes[from];
+        let (interval, child) = if to_right {
+            (from_node.intervals.pop().unwrap(), from_node.children.pop())
+        } else {
+            let child = (!from_node.children.is_empty()).then(|| from_node.children.remove(0));
+            (from_node.intervals.remove(0), child)
+        };
+        let separator = mem::replace(&mut self.nodes[id].intervals[index], interval);
+        let to_node = &mut self.nodes[to];
+        if to_right {
+            to_node.intervals.insert(0, separator);
+            to_node.children.splice(0..0, child);
+        } else {
+            to_node.intervals.push(separator);
+            to_node.children.extend(child);
+        }
+        self.update_max_end(from);
+        self.update_max_end(to);
+    }
+
+    /// Merge `children[index + 1]` of a node into `children[index]`, with the interval
+    /// between them.
+    fn merge_children(&mut self, id: NodeId, index: usize) {
+        let node = &mut self.nodes[id];
+        let separator = node.intervals.remove(index);
+        let right = node.children.remove(index + 1);
+        let left = node.children[index];
+        let right = self.release(right);
+        let left_node = &mut self.nodes[left];
+        left_node.intervals.push(separator);
+        left_node.intervals.extend(right.intervals);
+        left_node.children.extend(right.children);
+        self.update_max_end(left);
+    }
+
+    fn allocate(&mut self, node: IntervalNode<T>) -> NodeId {
+        match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, id: NodeId) -> IntervalNode<T> {
+        self.free.push(id);
+        mem::replace(&mut self.nodes[id], IntervalNode::new())
+    }
+
+    fn update_max_end(&mut self, id: NodeId) {
+        let node = &self.nodes[id];
+        let ends = node.intervals.iter().map(|interval| &interval.end);
+        let child_ends = node
+            .children
+            .iter()
+            .filter_map(|&child| self.nodes[child].max_end.as_ref());
+        let max_end = ends.chain(child_ends).max().cloned();
+        self.nodes[id].max_end = max_end;
+    }
+}
+
+impl<T> IntervalNode<T> {
+    fn new() -> Self {
+        Self {
+            intervals: vec![],
+            children: vec![],
+            max_end: None,
+        }
+    }
+}
+
+impl<T: Ord> IntervalNode<T> {
+    /// Binary search the intervals of this node, ordered by their starts and then their ends.
+    fn search(&self, interval: &Range<T>) -> Result<usize, usize> {
+        self.intervals
+            .binary_search_by(|probe| compare(probe, interval))
+    }
+}
+
+fn compare<T: Ord>(a: &Range<T>, b: &Range<T>) -> Ordering {
+    a.start.cmp(&b.start).then_with(|| a.end.cmp(&b.end))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interval::{IntervalTree, NodeId};
+    use core::ops::Range;
+    use rand::Rng;
+
+    // Asserts every node knows the largest end in its subtree, returning it.
+    fn check_max_end(tree: &IntervalTree<u32>, id: NodeId) -> Option<u32> {
+        let node = &tree.nodes[id];
+        let ends = node.intervals.iter().map(|interval| Some(interval.end));
+        let child_ends = node
+            .children
+            .iter()
+            .map(|&child| check_max_end(tree, child));
+        let max_end = ends.chain(child_ends).max().flatten();
+        assert_eq!(node.max_end, max_end);
+        max_end
+    }
+
+    // Asserts every node but the root is at least half full and leaves are all at the same
+    // depth, returning the height of the subtree.
+    fn check_shape(tree: &IntervalTree<u32>, id: NodeId) -> usize {
+        let node = &tree.nodes[id];
+        assert!(node.intervals.len() < tree.order);
+        if id != tree.root {
+            assert!(node.intervals.len() >= (tree.order - 1) / 2);
+        }
+        if node.children.is_empty() {
+            return 0;
+        }
+        assert_eq!(node.children.len(), node.intervals.len() + 1);
+        let heights: Vec<_> = node
+            .children
+            .iter()
+            .map(|&child| check_shape(tree, child))
+            .collect();
+        assert!(heights.windows(2).all(|pair| pair[0] == pair[1]));
+        heights[0] + 1
+    }
+
+    #[test]
+    fn reject_small_orders() {
+        for order in 0..3 {
+            assert!(IntervalTree::<u32>::try_new(order).is_err());
+        }
+        assert!(IntervalTree::<u32>::try_new(3).is_ok());
+    }
+
+    #[test]
+    fn remove_intervals() {
+        let mut rng = rand::thread_rng();
+        for order in 3..8 {
+            let mut tree = IntervalTree::new(order);
+            let mut expected: Vec<Range<u32>> = vec![];
+            for _ in 0..2_000 {
+                let start = rng.gen_range(0, 300);
+                let interval = start..start + rng.gen_range(0, 50);
+                let is_present = expected.contains(&interval);
+                if rng.gen_bool(0.5) {
+                    assert_eq!(tree.insert(interval.clone()), !is_present);
+                    if !is_present {
+                        expected.push(interval);
+                    }
+                } else {
+                    assert_eq!(tree.remove(&interval), is_present);
+                    expected.retain(|present| *present != interval);
+                }
+                check_max_end(&tree, tree.root);
+                check_shape(&tree, tree.root);
+                assert_eq!(tree.len(), expected.len());
+            }
+            expected.sort_by_key(|interval| (interval.start, interval.end));
+            for point in 0..360 {
+                let containing: Vec<_> = expected
+                    .iter()
+                    .filter(|interval| interval.contains(&point))
+                    .collect();
+                assert_eq!(tree.query_point(&point), containing);
+            }
+            for interval in expected.clone() {
+                assert!(tree.remove(&interval));
+            }
+            assert!(tree.is_empty());
+            assert_eq!(tree.nodes[tree.root].max_end, None);
+            // Every node but the root has been released for reuse.
+            assert_eq!(tree.nodes.len() - tree.free.len(), 1);
+        }
+    }
+
+    #[test]
+    fn query_points_and_ranges() {
+        let mut rng = rand::thread_rng();
+        for order in 3..8 {
+            let mut tree = IntervalTree::new(order);
+            let mut expected: Vec<Range<u32>> = vec![];
+            for _ in 0..500 {
+                let start = rng.gen_range(0, 1_000);
+                let interval = start..start + rng.gen_range(0, 50);
+                let is_new = !expected.contains(&interval);
+                assert_eq!(tree.insert(interval.clone()), is_new);
+                if is_new {
+                    expected.push(interval);
+                }
+            }
+            check_max_end(&tree, tree.root);
+            assert_eq!(tree.len(), expected.len());
+            expected.sort_by_key(|interval| (interval.start, interval.end));
+
+            for _ in 0..200 {
+                let point = rng.gen_range(0, 1_100);
+                let containing: Vec<_> = expected
+                    .iter()
+                    .filter(|interval| interval.contains(&point))
+                    .collect();
+                assert_eq!(tree.query_point(&point), containing);
+
+                let start = rng.gen_range(0, 1_100);
+                let range = start..start + rng.gen_range(0, 30);
+                let overlapping: Vec<_> = expected
+                    .iter()
+                    .filter(|interval| {
+                        !interval.is_empty()
+                            && !range.is_empty()
+                            && interval.start < range.end
+                            && range.start < interval.end
+                    })
+                    .collect();
+                assert_eq!(tree.query_overlap(&range), overlapping);
+            }
+        }
+    }
+
+    #[test]
+    fn ignore_empty_intervals() {
+        let mut tree = IntervalTree::new(3);
+        assert!(tree.insert(5..5));
+        assert!(tree.insert(1..10));
+        assert!(!tree.insert(1..10));
+        assert_eq!(tree.query_overlap(&(4..6)), vec![&(1..10)]);
+        assert_eq!(tree.query_point(&5), vec![&(1..10)]);
+        assert!(tree.query_overlap(&(3..3)).is_empty());
+        assert!(tree.query_point(&10).is_empty());
+    }
+}
@@ -1,5 +1,41 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod array_btree;
+mod bplus_node;
+pub mod bplus_tree;
 pub mod btree;
+#[cfg(feature = "bw-tree")]
+pub mod bw_tree;
+pub mod compare;
+#[cfg(feature = "std")]
+pub mod concurrent;
+pub mod error;
+#[cfg(any(feature = "fuzz", test))]
+pub mod fuzz;
+pub mod interval;
+pub mod multimap;
+#[cfg(feature = "std")]
+pub mod mvcc;
 mod node;
+pub mod oplog;
+#[cfg(feature = "std")]
+pub mod optimistic;
+pub mod persistent;
+pub mod prefix;
+#[cfg(feature = "std")]
+mod segments;
+pub mod shared;
+mod small_vec;
+#[cfg(feature = "std")]
+pub mod snapshot;
+pub mod time;
+pub mod tombstone;
+#[cfg(feature = "viz")]
+pub mod viz;
+#[cfg(feature = "wire")]
+pub mod wire;
 
 #[cfg(test)]
 mod tests {
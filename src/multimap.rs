@@ -0,0 +1,122 @@
+use crate::bplus_tree::BPlusTree;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::slice;
+
+/// This structure represents a map which can associate multiple values with each key.
+/// Values of a key are kept in the order they were inserted.
+#[derive(Clone, Debug)]
+pub struct BTreeMultiMap<K, V> {
+    map: BPlusTree<K, Vec<V>>,
+    // The total number of values over all keys.
+    len: usize,
+}
+
+impl<K, V> BTreeMultiMap<K, V>
+where
+    K: Ord + Clone,
+{
+    pub fn new(order: usize) -> Self {
+        Self {
+            map: BPlusTree::new(order),
+            len: 0,
+        }
+    }
+
+    /// Returns the number of values in the map.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Add a value to a key, keeping the values already associated with it.
+    pub fn insert(&mut self, key: K, value: V) {
+        match self.map.get_mut(&key) {
+            Some(values) => values.push(value),
+            None => {
+                self.map.insert(key, vec![value]);
+            }
+        }
+        self.len += 1;
+    }
+
+    /// Returns an iterator over the values associated with a key.
+    pub fn get_all(&self, key: &K) -> slice::Iter<'_, V> {
+        match self.map.get(key) {
+            Some(values) => values.iter(),
+            None => [].iter(),
+        }
+    }
+
+    /// Remove the first occurrence of a value from a key, returning whether it was found.
+    /// The key itself is removed once it has no value.
+    pub fn remove_entry(&mut self, key: &K, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        let values = match self.map.get_mut(key) {
+            Some(values) => values,
+            None => return false,
+        };
+        let index = match values.iter().position(|v| v == value) {
+            Some(index) => index,
+            None => return false,
+        };
+        values.remove(index);
+        if values.is_empty() {
+            self.map.remove(key);
+        }
+        self.len -= 1;
+        true
+    }
+
+    /// Remove a key, returning all the values which were associated with it.
+    pub fn remove_all(&mut self, key: &K) -> Vec<V> {
+        let values = self.map.remove(key).unwrap_or_default();
+        self.len -= values.len();
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::multimap::BTreeMultiMap;
+
+    #[test]
+    fn multiple_values_per_key() {
+        let mut map = BTreeMultiMap::new(4);
+        for key in 0..50 {
+            for value in 0..key % 4 {
+                map.insert(key, value);
+            }
+        }
+        assert_eq!(map.len(), (0..50).map(|key| key % 4).sum::<usize>());
+        assert_eq!(map.get_all(&7).collect::<Vec<_>>(), vec![&0, &1, &2]);
+        assert_eq!(map.get_all(&8).next(), None);
+        assert!(!map.contains_key(&8));
+    }
+
+    #[test]
+    fn remove_values() {
+        let mut map = BTreeMultiMap::new(3);
+        map.insert("a", 1);
+        map.insert("a", 2);
+        map.insert("a", 1);
+        map.insert("b", 3);
+        assert!(map.remove_entry(&"a", &1));
+        assert_eq!(map.get_all(&"a").collect::<Vec<_>>(), vec![&2, &1]);
+        assert!(!map.remove_entry(&"a", &3));
+        assert!(!map.remove_entry(&"c", &1));
+        assert!(map.remove_entry(&"b", &3));
+        assert!(!map.contains_key(&"b"));
+        assert_eq!(map.remove_all(&"a"), vec![2, 1]);
+        assert!(map.is_empty());
+    }
+}
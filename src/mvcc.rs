@@ -0,0 +1,380 @@
+use crate::bplus_tree::BPlusTree;
+use std::collections::VecDeque;
+use std::ops::Bound;
+use std::sync::{Mutex, RwLock};
+
+/// The number of keys a snapshot iterator reads each time it takes the latch of the tree.
+const BATCH_SIZE: usize = 64;
+
+/// A set of keys keeping old versions around, so that a reader can open a `Snapshot` and see
+/// the keys as they were, while writers keep changing the tree.
+///
+/// Every change commits a new version. A key remembers the versions it was alive in, and
+/// versions no open snapshot can see are dropped when writes commit or snapshots close.
+/// Writers are serialized by a latch, which snapshots take only for a batch of keys at a time.
+#[derive(Debug)]
+pub struct MvccBTree<T> {
+    versions: RwLock<Versions<T>>,
+    // The versions of open snapshots, with the number of snapshots at each.
+    // Taken after `versions` when both are needed.
+    snapshots: Mutex<BPlusTree<u64, usize>>,
+}
+
+#[derive(Debug)]
+struct Versions<T> {
+    // Every key with the lifetimes it had, oldest first. Only the last one can be open.
+    keys: BPlusTree<T, Vec<Lifetime>>,
+    // Keys removed by each version in ascending order, whose lifetimes wait to be dropped.
+    removed: VecDeque<(u64, T)>,
+    // The last committed version.
+    version: u64,
+    len: usize,
+}
+
+/// The versions in `created..deleted` which see a key.
+#[derive(Clone, Copy, Debug)]
+struct Lifetime {
+    created: u64,
+    deleted: Option<u64>,
+}
+
+/// A consistent view of an `MvccBTree` at the version it was opened at.
+/// Old versions are kept until it is dropped.
+#[derive(Debug)]
+pub struct Snapshot<'a, T>
+where
+    T: Ord + Clone,
+{
+    tree: &'a MvccBTree<T>,
+    version: u64,
+}
+
+impl<T> MvccBTree<T>
+where
+    T: Ord + Clone,
+{
+    pub fn new(order: usize) -> Self {
+        Self {
+            versions: RwLock::new(Versions {
+                keys: BPlusTree::new(order),
+                removed: VecDeque::new(),
+                version: 0,
+                len: 0,
+            }),
+            snapshots: Mutex::new(BPlusTree::new(order)),
+        }
+    }
+
+    /// Returns the number of keys in the latest version.
+    pub fn len(&self) -> usize {
+        self.versions.read().unwrap().len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the last committed version.
+    pub fn version(&self) -> u64 {
+        self.versions.read().unwrap().version
+    }
+
+    pub fn contains(&self, key: &T) -> bool {
+        let versions = self.versions.read().unwrap();
+        versions.is_visible(key, versions.version)
+    }
+
+    /// Insert a key, returning whether it was not present yet.
+    pub fn insert(&self, key: T) -> bool {
+        let mut versions = self.versions.write().unwrap();
+        let version = versions.version + 1;
+        match versions.keys.get_mut(&key) {
+            Some(lifetimes) if lifetimes.last().is_some_and(Lifetime::is_open) => return false,
+            Some(lifetimes) => lifetimes.push(Lifetime::new(version)),
+            None => {
+                versions.keys.insert(key, vec![Lifetime::new(version)]);
+            }
+        }
+        versions.version = version;
+        versions.len += 1;
+        true
+    }
+
+    /// Remove a key, returning whether it was present.
+    /// Snapshots opened before keep seeing it.
+    pub fn remove(&self, key: &T) -> bool {
+        let mut versions = self.versions.write().unwrap();
+        let version = versions.version + 1;
+        match versions
+            .keys
+            .get_mut(key)
+            .and_then(|lifetimes| lifetimes.last_mut())
+        {
+            Some(lifetime) if lifetime.is_open() => lifetime.deleted = Some(version),
+            _ => return false,
+        }
+        versions.version = version;
+        versions.len -= 1;
+        versions.removed.push_back((version, key.clone()));
+        let oldest = self.oldest_snapshot().unwrap_or(version);
+        versions.collect_garbage(oldest);
+        true
+    }
+
+    /// Open a view of the latest version, which later changes do not affect.
+    pub fn snapshot(&self) -> Snapshot<'_, T> {
+        // Registering the snapshot before releasing the latch keeps writers from dropping
+        // lifetimes it can see.
+        let versions = self.versions.read().unwrap();
+        let version = versions.version;
+        let mut snapshots = self.snapshots.lock().unwrap();
+        match snapshots.get_mut(&version) {
+            Some(count) => *count += 1,
+            None => {
+                snapshots.insert(version, 1);
+            }
+        }
+        Snapshot {
+            tree: self,
+            version,
+        }
+    }
+
+    /// Returns the version of the oldest open snapshot.
+    fn oldest_snapshot(&self) -> Option<u64> {
+        let snapshots = self.snapshots.lock().unwrap();
+        snapshots.iter().next().map(|(&version, _)| version)
+    }
+
+    fn close(&self, version: u64) {
+        {
+            let mut snapshots = self.snapshots.lock().unwrap();
+            let count = snapshots.get_mut(&version).unwrap();
+            *count -= 1;
+            if *count > 0 {
+                return;
+            }
+            snapshots.remove(&version);
+        }
+        let mut versions = self.versions.write().unwrap();
+        let oldest = self.oldest_snapshot().unwrap_or(versions.version);
+        versions.collect_garbage(oldest);
+    }
+}
+
+impl<T> Versions<T>
+where
+    T: Ord + Clone,
+{
+    fn is_visible(&self, key: &T, version: u64) -> bool {
+        self.keys.get(key).is_some_and(|lifetimes| {
+            lifetimes
+                .iter()
+                .any(|lifetime| lifetime.is_visible(version))
+        })
+    }
+
+    /// Drop the lifetimes of removed keys which no version from `oldest` on can see.
+    fn collect_garbage(&mut self, oldest: u64) {
+        while let Some((version, _)) = self.removed.front() {
+            if *version > oldest {
+                return;
+            }
+            let (_, key) = self.removed.pop_front().unwrap();
+            // A key removed more than once may be gone already.
+            let lifetimes = match self.keys.get_mut(&key) {
+                Some(lifetimes) => lifetimes,
+                None => continue,
+            };
+            lifetimes.retain(|lifetime| lifetime.deleted.is_none_or(|deleted| deleted > oldest));
+            if lifetimes.is_empty() {
+                self.keys.remove(&key);
+            }
+        }
+    }
+}
+
+impl Lifetime {
+    fn new(created: u64) -> Self {
+        Self {
+            created,
+            deleted: None,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.deleted.is_none()
+    }
+
+    fn is_visible(&self, version: u64) -> bool {
+        self.created <= version && self.deleted.is_none_or(|deleted| version < deleted)
+    }
+}
+
+impl<'a, T> Snapshot<'a, T>
+where
+    T: Ord + Clone,
+{
+    /// Returns the version the snapshot sees.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn contains(&self, key: &T) -> bool {
+        let versions = self.tree.versions.read().unwrap();
+        versions.is_visible(key, self.version)
+    }
+
+    /// Returns an iterator over the keys the snapshot sees in ascending order.
+    /// Writers may commit between batches of keys without the iterator noticing.
+    pub fn iter(&self) -> SnapshotIter<'_, 'a, T> {
+        SnapshotIter {
+            snapshot: self,
+            batch: VecDeque::new(),
+            after: Bound::Unbounded,
+            is_done: false,
+        }
+    }
+}
+
+impl<'a, T> Drop for Snapshot<'a, T>
+where
+    T: Ord + Clone,
+{
+    fn drop(&mut self) {
+        self.tree.close(self.version);
+    }
+}
+
+/// An iterator over the keys of a `Snapshot`.
+#[derive(Debug)]
+pub struct SnapshotIter<'s, 'a, T>
+where
+    T: Ord + Clone,
+{
+    snapshot: &'s Snapshot<'a, T>,
+    // Keys read but not yielded yet.
+    batch: VecDeque<T>,
+    // The last key read.
+    after: Bound<T>,
+    is_done: bool,
+}
+
+impl<'s, 'a, T> Iterator for SnapshotIter<'s, 'a, T>
+where
+    T: Ord + Clone,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        while self.batch.is_empty() && !self.is_done {
+            let versions = self.snapshot.tree.versions.read().unwrap();
+            let mut read = 0;
+            for (key, lifetimes) in versions.keys.range((self.after.clone(), Bound::Unbounded)) {
+                read += 1;
+                if read > BATCH_SIZE {
+                    break;
+                }
+                self.after = Bound::Excluded(key.clone());
+                if lifetimes
+                    .iter()
+                    .any(|lifetime| lifetime.is_visible(self.snapshot.version))
+                {
+                    self.batch.push_back(key.clone());
+                }
+            }
+            self.is_done = read <= BATCH_SIZE;
+        }
+        self.batch.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mvcc::MvccBTree;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn snapshot_sees_old_version() {
+        let tree = MvccBTree::new(4);
+        for key in 0..100 {
+            tree.insert(key);
+        }
+        let snapshot = tree.snapshot();
+        for key in 0..50 {
+            assert!(tree.remove(&key));
+        }
+        for key in 100..200 {
+            tree.insert(key);
+        }
+        // Removing and inserting again gives a key a second lifetime.
+        assert!(tree.insert(10));
+        assert!(tree.remove(&10));
+        assert!(!tree.remove(&10));
+
+        assert_eq!(tree.len(), 150);
+        assert!(!tree.contains(&10));
+        assert!(snapshot.contains(&10));
+        assert!(!snapshot.contains(&150));
+        assert_eq!(
+            snapshot.iter().collect::<Vec<_>>(),
+            (0..100).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            tree.snapshot().iter().collect::<Vec<_>>(),
+            (50..200).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn collect_garbage_when_snapshots_close() {
+        let tree = MvccBTree::new(4);
+        for key in 0..100 {
+            tree.insert(key);
+        }
+        let first = tree.snapshot();
+        let second = tree.snapshot();
+        for key in 0..100 {
+            tree.remove(&key);
+        }
+        drop(first);
+        // The second snapshot still sees the same version.
+        assert_eq!(tree.versions.read().unwrap().keys.len(), 100);
+        assert_eq!(second.iter().count(), 100);
+        drop(second);
+        let versions = tree.versions.read().unwrap();
+        assert!(versions.keys.is_empty());
+        assert!(versions.removed.is_empty());
+    }
+
+    #[test]
+    fn iterate_while_writing() {
+        let tree = Arc::new(MvccBTree::new(8));
+        for key in 0..1_000u32 {
+            tree.insert(key * 2);
+        }
+        let writer = {
+            let tree = Arc::clone(&tree);
+            thread::spawn(move || {
+                for key in 0..1_000u32 {
+                    tree.insert(key * 2 + 1);
+                    tree.remove(&(key * 2));
+                }
+            })
+        };
+        for _ in 0..20 {
+            let snapshot = tree.snapshot();
+            let keys: Vec<_> = snapshot.iter().collect();
+            // Every version holds exactly 1,000 keys, except right after an insertion.
+            assert!(keys.len() == 1_000 || keys.len() == 1_001);
+            assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(snapshot.iter().collect::<Vec<_>>(), keys);
+        }
+        writer.join().unwrap();
+        assert_eq!(
+            tree.snapshot().iter().collect::<Vec<_>>(),
+            (0..1_000).map(|key| key * 2 + 1).collect::<Vec<_>>()
+        );
+    }
+}
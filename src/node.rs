@@ -1,3 +1,14 @@
+use crate::btree::{merge_sorted, MemoryUsage, SplitPolicy};
+use crate::compare::Comparator;
+use crate::small_vec::SmallVec;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::convert::TryFrom;
+use core::mem;
+use core::ops::{Index, IndexMut, Range};
+
 /// NodeKind indicates a type of B-Tree node.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub(crate) enum NodeKind {
@@ -6,166 +17,1440 @@ pub(crate) enum NodeKind {
     Leaf,
 }
 
+/// Index of a node in the arena of a `BTree`.
+/// 32 bits address about four billion nodes, and take half the space of a pointer
+/// in the children of each node.
+pub(crate) type NodeId = u32;
+
+/// Nodes of trees whose order is up to this keep their keys and children inline,
+/// without allocating on the heap.
+const INLINE_ORDER: usize = 8;
+
+// A node holds `order` keys and `order + 1` children at most, while it overflows before a split.
+pub(crate) type Keys<T> = SmallVec<T, INLINE_ORDER>;
+pub(crate) type Children = SmallVec<NodeId, { INLINE_ORDER + 1 }>;
+
+// Fields are laid out in declaration order, from the start of a cache line. Keys come first,
+// so a search reads the key count and the first keys from a single line, and children,
+// which are only read once the search is done with the keys, come after them.
 #[derive(Clone, Debug, PartialEq)]
-pub(crate) struct Node<T: Ord> {
+#[repr(C, align(64))]
+pub(crate) struct Node<T> {
+    // Keys of a node.
+    pub(crate) keys: Keys<T>,
+    // Child nodes.
+    pub(crate) children: Children,
+    // The number of keys in the subtree rooted at this node.
+    pub(crate) size: usize,
     // The maximum number of children each node can accomodates.
     pub(crate) order: usize,
     // A kind of a node.
     pub(crate) kind: NodeKind,
-    // Keys of a node.
-    pub(crate) keys: Vec<T>,
-    // Child nodes.
-    pub(crate) children: Vec<Node<T>>,
 }
 
-impl<T> Node<T>
-where
-    T: Ord + Clone,
-{
+impl<T> Node<T> {
     pub fn new(order: usize) -> Self {
         Self {
             order,
             kind: NodeKind::Root,
-            keys: vec![],
-            children: vec![],
+            keys: Keys::new(),
+            children: Children::new(),
+            size: 0,
+        }
+    }
+
+    pub(crate) fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Search the keys of this node in the order of a comparator.
+    /// Small nodes are scanned linearly, which is faster than bisecting a few keys as
+    /// the scan does not depend on the outcome of each comparison.
+    pub(crate) fn search<C: Comparator<T>>(&self, key: &T, cmp: &C) -> Result<usize, usize> {
+        if self.order > C::LINEAR_SEARCH_ORDER {
+            return self.keys.binary_search_by(|probe| cmp.compare(probe, key));
+        }
+        let index = cmp.count_less(&self.keys, key);
+        match self.keys.get(index) {
+            Some(probe) if cmp.compare(probe, key) == Ordering::Equal => Ok(index),
+            _ => Err(index),
+        }
+    }
+
+    /// Returns the number of keys of this node less than `key`, or equal to it if `inclusive`.
+    /// Unlike `search`, this finds the first or the last of equal keys.
+    pub(crate) fn partition<C: Comparator<T>>(&self, key: &T, inclusive: bool, cmp: &C) -> usize {
+        self.keys
+            .partition_point(|probe| match cmp.compare(probe, key) {
+                Ordering::Less => true,
+                Ordering::Equal => inclusive,
+                Ordering::Greater => false,
+            })
+    }
+
+    pub(crate) fn is_overflow(&self) -> bool {
+        self.keys.len() == self.order
+    }
+
+    /// The minimum number of keys a node except the root must hold.
+    pub(crate) fn min_keys(&self) -> usize {
+        (self.order - 1) / 2
+    }
+
+    pub(crate) fn is_underflow(&self) -> bool {
+        self.keys.len() < self.min_keys()
+    }
+}
+
+/// Storage owning all nodes of a tree.
+/// Nodes lie in a slab and refer to their children by index into it. Slabs share their chunks
+/// of nodes, so `snapshot` freezes the nodes without copying them, and later changes to either
+/// arena copy the chunks they write to.
+#[derive(Clone, Debug)]
+pub(crate) struct Arena<T> {
+    nodes: Slab<T>,
+    // Slots of `nodes` released by merges, reused by later allocations.
+    free: Vec<NodeId>,
+    // Nodes from the root down to the leftmost leaf, cached for `pop_first` and `push_first`.
+    // It is emptied whenever a node may change, by mutable indexing or by allocating
+    // or releasing a node.
+    first_path: Vec<NodeId>,
+    #[cfg(feature = "metrics")]
+    pub(crate) counters: Counters,
+}
+
+/// The number of nodes in a chunk of a `Slab`.
+const SLAB_WIDTH: usize = 1 << SLAB_BITS;
+const SLAB_BITS: u32 = 6;
+
+/// Nodes stored in reference counted chunks of `SLAB_WIDTH`, indexed directly by the high bits
+/// of node ids. Cloning a slab copies one pointer per chunk and shares the chunks. Writing to
+/// a node copies its chunk first, unless no other slab shares it.
+#[derive(Clone, Debug)]
+struct Slab<T> {
+    // The first chunk starts with room for one node and doubles until it is full, so that
+    // small trees stay small. Later chunks are allocated whole. Only the last chunk may be
+    // shorter, and slots from `len` onwards hold empty nodes.
+    chunks: Vec<Arc<[Node<T>]>>,
+    len: usize,
+}
+
+impl<T> Slab<T> {
+    fn new() -> Self {
+        Self {
+            chunks: vec![],
+            len: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, id: usize) -> &Node<T> {
+        &self.chunks[id >> SLAB_BITS][id % SLAB_WIDTH]
+    }
+
+    /// Returns the number of nodes the chunks have room for.
+    fn capacity(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.len()).sum()
+    }
+}
+
+impl<T: Clone> Slab<T> {
+    fn get_mut(&mut self, id: usize) -> &mut Node<T> {
+        let chunk = &mut self.chunks[id >> SLAB_BITS];
+        if Arc::get_mut(chunk).is_none() {
+            *chunk = chunk.iter().cloned().collect();
+        }
+        &mut Arc::get_mut(chunk).expect("a copied chunk is not shared")[id % SLAB_WIDTH]
+    }
+
+    fn push(&mut self, node: Node<T>) {
+        let id = self.len;
+        if id == self.capacity() {
+            match self.chunks.last_mut() {
+                Some(chunk) if chunk.len() < SLAB_WIDTH => {
+                    let len = (chunk.len() * 2).min(SLAB_WIDTH);
+                    *chunk = Self::resize(chunk, len);
+                }
+                _ => {
+                    let len = if self.chunks.is_empty() {
+                        1
+                    } else {
+                        SLAB_WIDTH
+                    };
+                    self.chunks.push(Self::resize(&mut Arc::from(vec![]), len));
+                }
+            }
+        }
+        self.len += 1;
+        *self.get_mut(id) = node;
+    }
+
+    /// Returns a chunk of `len` slots, holding the nodes of another one, which are moved
+    /// unless the chunk is shared, and empty nodes after them.
+    fn resize(chunk: &mut Arc<[Node<T>]>, len: usize) -> Arc<[Node<T>]> {
+        let mut nodes = Vec::with_capacity(len);
+        match Arc::get_mut(chunk) {
+            Some(owned) => nodes.extend(
+                owned
+                    .iter_mut()
+                    .map(|node| mem::replace(node, Node::new(0))),
+            ),
+            None => nodes.extend(chunk.iter().cloned()),
+        }
+        nodes.truncate(len);
+        nodes.resize_with(len, || Node::new(0));
+        Arc::from(nodes)
+    }
+
+    /// Free the spare capacity of the nodes, of the last chunk and of the list of chunks.
+    fn shrink_to_fit(&mut self) {
+        for id in 0..self.len {
+            let node = self.get_mut(id);
+            node.keys.shrink_to_fit();
+            node.children.shrink_to_fit();
+        }
+        let spare = self.capacity() - self.len;
+        if let Some(chunk) = self.chunks.last_mut() {
+            let len = chunk.len() - spare;
+            *chunk = Self::resize(chunk, len);
+        }
+        self.chunks.retain(|chunk| !chunk.is_empty());
+        self.chunks.shrink_to_fit();
+    }
+
+    /// Returns how many chunks of this slab are shared with another one, and how many it has.
+    #[cfg(test)]
+    fn shared_chunks(&self, other: &Self) -> (usize, usize) {
+        let shared = self
+            .chunks
+            .iter()
+            .filter(|chunk| other.chunks.iter().any(|other| Arc::ptr_eq(chunk, other)))
+            .count();
+        (shared, self.chunks.len())
+    }
+}
+
+/// Counts of changes to the structure of a tree.
+#[cfg(feature = "metrics")]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Counters {
+    pub(crate) splits: usize,
+    pub(crate) merges: usize,
+    pub(crate) rotations: usize,
+    pub(crate) allocations: usize,
+}
+
+impl<T> Index<NodeId> for Arena<T> {
+    type Output = Node<T>;
+
+    fn index(&self, id: NodeId) -> &Node<T> {
+        self.nodes.get(id as usize)
+    }
+}
+
+impl<T: Clone> IndexMut<NodeId> for Arena<T> {
+    fn index_mut(&mut self, id: NodeId) -> &mut Node<T> {
+        self.first_path.clear();
+        self.nodes.get_mut(id as usize)
+    }
+}
+
+impl<T> Arena<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            nodes: Slab::new(),
+            free: vec![],
+            first_path: vec![],
+            #[cfg(feature = "metrics")]
+            counters: Counters::default(),
+        }
+    }
+}
+
+impl<T: Clone> Arena<T> {
+    /// Returns an arena sharing all nodes with this one, copying only the list of chunks.
+    /// Released slots are not reused by the copy, so that it does not copy the list of them.
+    pub(crate) fn snapshot(&self) -> Self {
+        Self {
+            nodes: self.nodes.clone(),
+            free: vec![],
+            first_path: vec![],
+            #[cfg(feature = "metrics")]
+            counters: Counters::default(),
         }
     }
 
-    pub(crate) fn traverse(&self) -> Vec<T> {
+    /// Returns how many chunks of nodes this arena shares with another one, and how many
+    /// it has.
+    #[cfg(test)]
+    pub(crate) fn shared_chunks(&self, other: &Self) -> (usize, usize) {
+        self.nodes.shared_chunks(&other.nodes)
+    }
+
+    /// Release all nodes at once, keeping the counters.
+    pub(crate) fn clear(&mut self) {
+        self.nodes = Slab::new();
+        self.free.clear();
+        self.first_path.clear();
+    }
+
+    pub(crate) fn allocate(&mut self, node: Node<T>) -> NodeId {
+        #[cfg(feature = "metrics")]
+        {
+            self.counters.allocations += 1;
+        }
+        self.first_path.clear();
+        match self.free.pop() {
+            Some(id) => {
+                *self.nodes.get_mut(id as usize) = node;
+                id
+            }
+            None => {
+                let id = NodeId::try_from(self.nodes.len()).expect("too many nodes in a tree");
+                self.nodes.push(node);
+                id
+            }
+        }
+    }
+
+    /// Count the bytes taken by the nodes, splitting them into keys, overhead and slack.
+    /// Slots of nodes not under `root`, released or left behind by `snapshot`, are slack.
+    pub(crate) fn memory_usage(&self, root: NodeId) -> MemoryUsage {
+        let node_size = mem::size_of::<Node<T>>();
+        let mut usage = MemoryUsage {
+            nodes: (self.free.capacity() + self.first_path.capacity()) * mem::size_of::<NodeId>(),
+            slack: (self.nodes.capacity() - self.nodes.len()) * node_size,
+            ..MemoryUsage::default()
+        };
+        let mut is_live = vec![false; self.nodes.len()];
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            is_live[id as usize] = true;
+            stack.extend(self[id].children.iter());
+        }
+        for (id, is_live) in is_live.into_iter().enumerate() {
+            let node = self.nodes.get(id);
+            let key_size = mem::size_of::<T>();
+            let child_size = mem::size_of::<NodeId>();
+            let mut total = node_size;
+            if node.keys.is_spilled() {
+                total += node.keys.capacity() * key_size;
+            }
+            if node.children.is_spilled() {
+                total += node.children.capacity() * child_size;
+            }
+            if !is_live {
+                usage.slack += total;
+                continue;
+            }
+            let keys = node.keys.len() * key_size;
+            let slack = (node.keys.capacity() - node.keys.len()) * key_size
+                + (node.children.capacity() - node.children.len()) * child_size;
+            usage.keys += keys;
+            usage.slack += slack;
+            usage.nodes += total - keys - slack;
+        }
+        usage
+    }
+
+    /// Returns the smallest key of a subtree.
+    pub(crate) fn first(&self, id: NodeId) -> Option<&T> {
+        let mut node = &self[id];
+        while !node.is_leaf() {
+            node = &self[node.children[0]];
+        }
+        node.keys.first()
+    }
+
+    /// Push the largest keys of a subtree in descending order until `keys` holds `k` of them.
+    /// Subtrees left of the last key pushed are never visited.
+    pub(crate) fn largest<'a>(&'a self, id: NodeId, k: usize, keys: &mut Vec<&'a T>) {
+        let node = &self[id];
+        for index in (0..=node.keys.len()).rev() {
+            if !node.is_leaf() {
+                self.largest(node.children[index], k, keys);
+            }
+            if keys.len() == k || index == 0 {
+                return;
+            }
+            keys.push(&node.keys[index - 1]);
+        }
+    }
+
+    /// Remove the smallest key of a subtree without comparing keys, if its leaf has more keys
+    /// than `min_keys`. Returns `None` otherwise, leaving the subtree as it is.
+    /// The path to the leftmost leaf is cached, so that repeated calls do not descend again.
+    pub(crate) fn pop_first(&mut self, root: NodeId, min_keys: usize) -> Option<T> {
+        let leaf = self.first_leaf(root);
+        let node = &self[leaf];
+        if node.keys.is_empty() || (leaf != root && node.keys.len() <= min_keys) {
+            return None;
+        }
+        // Nodes are changed without going through `IndexMut`, as the path stays the same.
+        for &id in &self.first_path {
+            self.nodes.get_mut(id as usize).size -= 1;
+        }
+        Some(self.nodes.get_mut(leaf as usize).keys.remove(0))
+    }
+
+    /// Insert a key smaller than all keys of a subtree at the front of its leftmost leaf,
+    /// comparing it with the first key only. Gives the key back if it is not smaller or
+    /// the leaf has no room left, leaving the subtree as it is.
+    pub(crate) fn push_first<C: Comparator<T>>(
+        &mut self,
+        root: NodeId,
+        key: T,
+        cmp: &C,
+    ) -> Result<(), T> {
+        let leaf = self.first_leaf(root);
+        let node = &self[leaf];
+        let is_first = node
+            .keys
+            .first()
+            .is_some_and(|first| cmp.compare(&key, first) == Ordering::Less);
+        if !is_first || node.keys.len() + 1 >= node.order {
+            return Err(key);
+        }
+        for &id in &self.first_path {
+            self.nodes.get_mut(id as usize).size += 1;
+        }
+        self.nodes.get_mut(leaf as usize).keys.insert(0, key);
+        Ok(())
+    }
+
+    /// Returns the leftmost leaf of a subtree, caching the path down to it.
+    fn first_leaf(&mut self, root: NodeId) -> NodeId {
+        if self.first_path.first() != Some(&root) {
+            self.first_path.clear();
+            let mut id = root;
+            self.first_path.push(id);
+            while !self[id].is_leaf() {
+                id = self[id].children[0];
+                self.first_path.push(id);
+            }
+        }
+        *self.first_path.last().unwrap()
+    }
+
+    /// Take a node out of the arena, making its slot available for reuse.
+    pub(crate) fn release(&mut self, id: NodeId) -> Node<T> {
+        let order = self[id].order;
+        self.free.push(id);
+        mem::replace(&mut self[id], Node::new(order))
+    }
+
+    /// Create a node from its keys and children, counting the keys of the subtree.
+    pub(crate) fn with_children(
+        &mut self,
+        order: usize,
+        kind: NodeKind,
+        keys: Keys<T>,
+        children: Children,
+    ) -> NodeId {
+        let size = keys.len()
+            + children
+                .iter()
+                .map(|&child| self[child].size)
+                .sum::<usize>();
+        self.allocate(Node {
+            order,
+            kind,
+            keys,
+            children,
+            size,
+        })
+    }
+
+    /// Build a subtree from keys sorted in ascending order without duplicates.
+    /// Nodes are filled evenly level by level, each of them holding as many keys as possible.
+    pub(crate) fn build_from_sorted_keys(&mut self, keys: Vec<T>, order: usize) -> NodeId {
+        let layout = LeafLayout::new(keys.len(), order);
+        let (leaves, separators) = layout.build(keys, 0..layout.count, order);
+        let leaves = leaves.into_iter().map(|leaf| self.allocate(leaf)).collect();
+        self.build_upper_levels(leaves, separators, order)
+    }
+
+    /// Build a subtree like `build_from_sorted_keys`, building runs of leaves on
+    /// up to `threads` threads. Upper levels hold a small fraction of the keys,
+    /// and are built on the calling thread.
+    #[cfg(feature = "std")]
+    pub(crate) fn par_build_from_sorted_keys(
+        &mut self,
+        mut keys: Vec<T>,
+        order: usize,
+        threads: usize,
+    ) -> NodeId
+    where
+        T: Send,
+    {
+        let layout = LeafLayout::new(keys.len(), order);
+        let threads = threads.clamp(1, layout.count);
+        // Cut the keys from the back into one run of whole leaves per thread.
+        let mut runs = Vec::with_capacity(threads);
+        for i in (0..threads).rev() {
+            let leaves =
+                even_offset(layout.count, threads, i)..even_offset(layout.count, threads, i + 1);
+            let run = keys.split_off(layout.first_key(leaves.start));
+            runs.push((leaves, run));
+        }
+        let built: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = runs
+                .into_iter()
+                .rev()
+                .map(|(leaves, run)| scope.spawn(move || layout.build(run, leaves, order)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        let mut leaves = Vec::with_capacity(layout.count);
+        let mut separators = Vec::with_capacity(layout.count - 1);
+        for (run_leaves, run_separators) in built {
+            leaves.extend(run_leaves.into_iter().map(|leaf| self.allocate(leaf)));
+            separators.extend(run_separators);
+        }
+        self.build_upper_levels(leaves, separators, order)
+    }
+
+    /// Group the nodes of each level under new parents until a single root remains.
+    /// `separators` holds the key between each pair of adjacent nodes.
+    fn build_upper_levels(
+        &mut self,
+        mut nodes: Vec<NodeId>,
+        mut separators: Vec<T>,
+        order: usize,
+    ) -> NodeId {
+        while nodes.len() > 1 {
+            let child_count = nodes.len();
+            let parent_count = child_count.div_ceil(order);
+            let mut children = nodes.into_iter();
+            let mut child_separators = separators.into_iter();
+            nodes = Vec::with_capacity(parent_count);
+            separators = Vec::with_capacity(parent_count - 1);
+            for i in 0..parent_count {
+                let share = even_share(child_count, parent_count, i);
+                nodes.push(self.with_children(
+                    order,
+                    NodeKind::Internal,
+                    child_separators.by_ref().take(share - 1).collect(),
+                    children.by_ref().take(share).collect(),
+                ));
+                if i + 1 < parent_count {
+                    separators.push(child_separators.next().unwrap());
+                }
+            }
+        }
+        let root = nodes.pop().unwrap();
+        self[root].kind = NodeKind::Root;
+        root
+    }
+
+    /// Move all keys of the subtree into `keys` in ascending order, releasing its nodes.
+    pub(crate) fn take_sorted_keys(&mut self, id: NodeId, keys: &mut Vec<T>) {
+        let node = self.release(id);
+        if node.is_leaf() {
+            keys.extend(node.keys);
+            return;
+        }
+        let mut children = node.children.into_iter();
+        for key in node.keys {
+            self.take_sorted_keys(children.next().unwrap(), keys);
+            keys.push(key);
+        }
+        self.take_sorted_keys(children.next().unwrap(), keys);
+    }
+
+    /// Free the spare capacity of the arena and its nodes, returning the new id of the root.
+    /// Slots released by merges, or of nodes left behind by `snapshot`, are dropped by moving
+    /// the tree to a new arena.
+    pub(crate) fn shrink_to_fit(&mut self, root: NodeId) -> NodeId {
+        let mut root = root;
+        if !self.free.is_empty() || self.count_nodes(root) < self.nodes.len() {
+            let mut nodes = Arena::new();
+            root = self.transfer(root, &mut nodes);
+            #[cfg(feature = "metrics")]
+            {
+                nodes.counters = mem::take(&mut self.counters);
+            }
+            *self = nodes;
+        }
+        self.nodes.shrink_to_fit();
+        self.free.shrink_to_fit();
+        root
+    }
+
+    /// Returns the number of nodes in a subtree.
+    fn count_nodes(&self, id: NodeId) -> usize {
+        1 + self[id]
+            .children
+            .iter()
+            .map(|&child| self.count_nodes(child))
+            .sum::<usize>()
+    }
+
+    /// Move the subtree into another arena, returning the id of its root there.
+    pub(crate) fn transfer(&mut self, id: NodeId, other: &mut Arena<T>) -> NodeId {
+        let mut node = self.release(id);
+        for child in node.children.iter_mut() {
+            *child = self.transfer(*child, other);
+        }
+        other.allocate(node)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn traverse(&self, id: NodeId) -> Vec<T>
+    where
+        T: Clone,
+    {
+        let node = &self[id];
         let mut extracted = vec![];
-        if self.kind == NodeKind::Leaf {
-            extracted = self.keys.clone();
+        if node.is_leaf() {
+            extracted = node.keys.to_vec();
         } else {
-            extracted.append(&mut self.children[0].traverse());
-            for i in 0..self.keys.len() {
-                extracted.push(self.keys[i].clone());
-                extracted.append(&mut self.children[i + 1].traverse());
+            extracted.append(&mut self.traverse(node.children[0]));
+            for i in 0..node.keys.len() {
+                extracted.push(node.keys[i].clone());
+                extracted.append(&mut self.traverse(node.children[i + 1]));
             }
         }
         extracted
     }
 
-    /// Search a node for a given key.
-    pub(crate) fn get<'a>(&self, key: &'a T) -> Option<&'a T> {
-        // Binary search the keys of the node for a given element.
-        // If it is found, return it. otherwise, `idx` will be an index
-        // of subtree the element should be.
-        let idx = match self.keys.binary_search(&key) {
-            Ok(_) => return Some(key),
-            Err(idx) => idx,
+    /// Search a subtree for a given key, returning the key stored in the tree.
+    pub(crate) fn get<C: Comparator<T>>(&self, id: NodeId, key: &T, cmp: &C) -> Option<&T> {
+        let mut node = &self[id];
+        loop {
+            // Binary search the keys of the node for a given element.
+            // If it is found, return the one stored in the node. otherwise, `idx` will be
+            // an index of subtree the element should be.
+            let idx = match node.search(key, cmp) {
+                Ok(idx) => return Some(&node.keys[idx]),
+                Err(idx) => idx,
+            };
+
+            // If the node is leaf, stop searching because there's nowhere to search.
+            // Or descend into the subtree.
+            if node.is_leaf() {
+                return None;
+            }
+            node = &self[node.children[idx]];
+        }
+    }
+
+    /// Search a subtree for a given key, returning the key stored in the tree.
+    pub(crate) fn get_mut<C: Comparator<T>>(
+        &mut self,
+        id: NodeId,
+        key: &T,
+        cmp: &C,
+    ) -> Option<&mut T> {
+        let mut id = id;
+        loop {
+            let node = &self[id];
+            match node.search(key, cmp) {
+                Ok(index) => return Some(&mut self[id].keys[index]),
+                Err(_) if node.is_leaf() => return None,
+                Err(index) => id = node.children[index],
+            }
+        }
+    }
+
+    /// Insert a key into the subtree, returning whether it was not present yet.
+    /// If `allow_duplicates`, a key equal to ones present is inserted after them instead.
+    /// The tree is descended in a loop and overflowing nodes are split bottom-up,
+    /// so the call stack does not grow with the height of the tree.
+    pub(crate) fn insert<C: Comparator<T>>(
+        &mut self,
+        id: NodeId,
+        key: T,
+        cmp: &C,
+        policy: SplitPolicy,
+        allow_duplicates: bool,
+    ) -> bool {
+        // Nodes visited on the way down to the leaf, paired with the index of the child followed.
+        let mut path = vec![];
+        let mut id = id;
+        loop {
+            let node = &mut self[id];
+            let index = match node.search(&key, cmp) {
+                Ok(_) if allow_duplicates => node.partition(&key, true, cmp),
+                Ok(_) => return false,
+                Err(index) => index,
+            };
+            if node.is_leaf() {
+                node.keys.insert(index, key);
+                node.size += 1;
+                break;
+            }
+            path.push((id, index));
+            id = node.children[index];
+        }
+        while let Some((parent, index)) = path.pop() {
+            self[parent].size += 1;
+            if self[self[parent].children[index]].is_overflow() {
+                self.make_room(parent, index, policy);
+            }
+        }
+        true
+    }
+
+    /// Insert keys sorted in ascending order without duplicates into a subtree in one pass,
+    /// returning how many of them were not present yet. Each leaf takes its run of keys at once,
+    /// and overflowing children are split into as many nodes as needed on the way back up,
+    /// but the node `id` itself may be left overflowing.
+    pub(crate) fn insert_sorted<C: Comparator<T>>(
+        &mut self,
+        id: NodeId,
+        mut keys: Vec<T>,
+        cmp: &C,
+    ) -> usize {
+        if keys.is_empty() {
+            return 0;
+        }
+        if self[id].is_leaf() {
+            let old_keys: Vec<T> = mem::take(&mut self[id].keys).into_iter().collect();
+            let old_len = old_keys.len();
+            // Keys already in the leaf are kept.
+            let merged = merge_sorted(keys, old_keys, cmp);
+            let added = merged.len() - old_len;
+            let node = &mut self[id];
+            node.keys = merged.into();
+            node.size += added;
+            return added;
+        }
+        // Cut the keys into a run for each child, dropping keys equal to separators.
+        let mut runs = Vec::with_capacity(self[id].children.len());
+        for separator in self[id].keys.iter().rev() {
+            let start = keys.partition_point(|key| cmp.compare(key, separator) == Ordering::Less);
+            let is_present = keys
+                .get(start)
+                .is_some_and(|key| cmp.compare(key, separator) == Ordering::Equal);
+            runs.push(keys.split_off(start + usize::from(is_present)));
+            keys.truncate(start);
+        }
+        runs.push(keys);
+        let mut added = 0;
+        // Visiting children from the last one, splitting a child does not move those left to visit.
+        for (index, run) in runs.into_iter().rev().enumerate().rev() {
+            let child = self[id].children[index];
+            added += self.insert_sorted(child, run, cmp);
+            self.split_overflowing(id, index);
+        }
+        self[id].size += added;
+        added
+    }
+
+    /// Split `children[index]` of a node into as few nodes as it takes to stop overflowing,
+    /// which can be more than two after inserting many keys at once.
+    pub(crate) fn split_overflowing(&mut self, parent: NodeId, index: usize) {
+        let child = &self[self[parent].children[index]];
+        if child.keys.len() < child.order {
+            return;
+        }
+        // Each node holds at most `order - 1` keys, and each node but the last one
+        // takes a separator out of the keys.
+        let parts = (child.keys.len() + 1).div_ceil(child.order);
+        #[cfg(feature = "metrics")]
+        {
+            self.counters.splits += parts - 1;
+        }
+        self.spread(parent, index, 1, parts);
+    }
+
+    /// Fix an overflowing `children[index]` of a node as a policy tells.
+    pub(crate) fn make_room(&mut self, parent: NodeId, index: usize, policy: SplitPolicy) {
+        if policy == SplitPolicy::Split {
+            self.split_children(parent, index);
+            return;
+        }
+        // The adjacent sibling with fewer keys, paired with the index of the left one of the two.
+        let children = &self[parent].children;
+        let left = index
+            .checked_sub(1)
+            .map(|sibling| (self[children[sibling]].keys.len(), sibling));
+        let right = children
+            .get(index + 1)
+            .map(|&sibling| (self[sibling].keys.len(), index));
+        let (sibling_len, pair) = match (left, right) {
+            (Some(left), Some(right)) => left.min(right),
+            (left, right) => left.or(right).unwrap(),
         };
+        if sibling_len + 1 < self[parent].order {
+            #[cfg(feature = "metrics")]
+            let left_len = self[self[parent].children[pair]].keys.len();
+            self.spread(parent, pair, 2, 2);
+            #[cfg(feature = "metrics")]
+            {
+                let moved = left_len.abs_diff(self[self[parent].children[pair]].keys.len());
+                self.counters.rotations += moved;
+            }
+        } else if policy == SplitPolicy::BStar {
+            #[cfg(feature = "metrics")]
+            {
+                self.counters.splits += 1;
+            }
+            self.spread(parent, pair, 2, 3);
+        } else {
+            self.split_children(parent, index);
+        }
+    }
 
-        // If the node is leaf, stop searching because there's nowhere to search.
-        // Or search subtree.
-        if self.kind == NodeKind::Leaf {
-            None
+    /// Spread the keys of `count` children of a node from `children[index]`, and the separators
+    /// between them, evenly over `parts` nodes, allocating the nodes missing.
+    fn spread(&mut self, parent: NodeId, index: usize, count: usize, parts: usize) {
+        let mut ids = self[parent].children[index..index + count].to_vec();
+        let mut keys = vec![];
+        let mut children = vec![];
+        for (i, &id) in ids.iter().enumerate() {
+            if i > 0 {
+                keys.push(self[parent].keys.remove(index));
+            }
+            keys.extend(mem::take(&mut self[id].keys));
+            children.extend(mem::take(&mut self[id].children));
+        }
+        let (order, kind) = (self[ids[0]].order, self[ids[0]].kind);
+        for _ in count..parts {
+            let id = self.with_children(order, kind, Keys::new(), Children::new());
+            self[parent].children.insert(index + 1, id);
+            ids.insert(1, id);
+        }
+        let key_count = keys.len() - (parts - 1);
+        // Fill the nodes from the last one, taking keys and children off the back.
+        for (i, &id) in ids.iter().enumerate().rev() {
+            let share = even_share(key_count, parts, i);
+            let node_keys = Keys::from(keys.split_off(keys.len() - share));
+            let node_children = if children.is_empty() {
+                Children::new()
+            } else {
+                Children::from(children.split_off(children.len() - share - 1))
+            };
+            let size = node_keys.len()
+                + node_children
+                    .iter()
+                    .map(|&child| self[child].size)
+                    .sum::<usize>();
+            let node = &mut self[id];
+            node.keys = node_keys;
+            node.children = node_children;
+            node.size = size;
+            if i > 0 {
+                let separator = keys.pop().unwrap();
+                self[parent].keys.insert(index, separator);
+            }
+        }
+    }
+
+    pub(crate) fn split_children(&mut self, parent: NodeId, index: usize) {
+        #[cfg(feature = "metrics")]
+        {
+            self.counters.splits += 1;
+        }
+        let child = self[parent].children[index];
+        let node = &mut self[child];
+        let order = node.order;
+        let kind = node.kind;
+        let split_at = order / 2;
+        let keys = node.keys.split_off(split_at + 1);
+        let children = if kind != NodeKind::Leaf {
+            node.children.split_off(split_at + 1)
         } else {
-            self.children[idx].get(key)
+            Children::new()
+        };
+        let right_child = self.with_children(order, kind, keys, children);
+        self[child].size -= self[right_child].size + 1;
+        let ascending_key = self[child].keys.pop().unwrap();
+        let parent = &mut self[parent];
+        parent.children.insert(index + 1, right_child);
+        parent.keys.insert(index, ascending_key);
+    }
+
+    /// Returns the smallest key greater than `bound`, or the smallest key if it is `None`.
+    pub(crate) fn first_after<C: Comparator<T>>(
+        &self,
+        id: NodeId,
+        bound: Option<&T>,
+        cmp: &C,
+    ) -> Option<&T> {
+        let node = &self[id];
+        let index = match bound.map(|bound| node.search(bound, cmp)) {
+            None => 0,
+            Some(Ok(index)) => index + 1,
+            Some(Err(index)) => index,
+        };
+        if !node.is_leaf() {
+            if let Some(key) = self.first_after(node.children[index], bound, cmp) {
+                return Some(key);
+            }
         }
+        node.keys.get(index)
     }
 
-    pub(crate) fn is_overflow(&self) -> bool {
-        self.keys.len() == self.order
+    /// Returns the smallest key greater than `key`, or equal to it if `inclusive`.
+    pub(crate) fn first_above<C: Comparator<T>>(
+        &self,
+        id: NodeId,
+        key: &T,
+        inclusive: bool,
+        cmp: &C,
+    ) -> Option<&T> {
+        let mut node = &self[id];
+        // The closest key above seen so far, which deeper nodes can only beat.
+        let mut candidate = None;
+        loop {
+            let index = match node.search(key, cmp) {
+                Ok(index) if inclusive => return Some(&node.keys[index]),
+                // Keys equal to this one may lie on either side of it.
+                Ok(_) => node.partition(key, true, cmp),
+                Err(index) => index,
+            };
+            candidate = node.keys.get(index).or(candidate);
+            if node.is_leaf() {
+                return candidate;
+            }
+            node = &self[node.children[index]];
+        }
+    }
+
+    /// Returns the largest key less than `key`, or equal to it if `inclusive`.
+    pub(crate) fn last_below<C: Comparator<T>>(
+        &self,
+        id: NodeId,
+        key: &T,
+        inclusive: bool,
+        cmp: &C,
+    ) -> Option<&T> {
+        let mut node = &self[id];
+        // The closest key below seen so far, which deeper nodes can only beat.
+        let mut candidate = None;
+        loop {
+            let index = match node.search(key, cmp) {
+                Ok(index) if inclusive => return Some(&node.keys[index]),
+                Ok(_) => node.partition(key, false, cmp),
+                Err(index) => index,
+            };
+            if index > 0 {
+                candidate = Some(&node.keys[index - 1]);
+            }
+            if node.is_leaf() {
+                return candidate;
+            }
+            node = &self[node.children[index]];
+        }
     }
 
-    pub(crate) fn insert(&mut self, key: T) {
-        let index = match self.keys.binary_search(&key) {
-            Ok(_) => return,
-            Err(index) => index,
+    /// Remove a key from the subtree, returning the element which was stored.
+    /// Children left with fewer than `min_keys` keys are refilled, but this node may be left
+    /// underfull.
+    pub(crate) fn remove<C: Comparator<T>>(
+        &mut self,
+        id: NodeId,
+        key: &T,
+        cmp: &C,
+        min_keys: usize,
+    ) -> Option<T> {
+        let node = &mut self[id];
+        let removed = match node.search(key, cmp) {
+            Ok(index) if node.is_leaf() => node.keys.remove(index),
+            Err(_) if node.is_leaf() => return None,
+            Ok(index) => {
+                // Replace the key with its predecessor, which is the largest key of the left subtree.
+                let child = node.children[index];
+                let predecessor = self.remove_last(child, min_keys);
+                let removed = mem::replace(&mut self[id].keys[index], predecessor);
+                self.rebalance_children(id, index, min_keys);
+                removed
+            }
+            Err(index) => {
+                let child = node.children[index];
+                let removed = self.remove(child, key, cmp, min_keys)?;
+                self.rebalance_children(id, index, min_keys);
+                removed
+            }
         };
-        if self.children.len() == 0 {
-            self.keys.insert(index, key);
+        self[id].size -= 1;
+        Some(removed)
+    }
+
+    /// Remove the largest key of the subtree, refilling children as `remove` does.
+    pub(crate) fn remove_last(&mut self, id: NodeId, min_keys: usize) -> T {
+        let node = &mut self[id];
+        node.size -= 1;
+        if node.is_leaf() {
+            return node.keys.pop().unwrap();
+        }
+        let last = node.children.len() - 1;
+        let last_child = node.children[last];
+        let key = self.remove_last(last_child, min_keys);
+        self.rebalance_children(id, last, min_keys);
+        key
+    }
+
+    /// Refill a child if it has fewer than `min_keys` keys, by borrowing a key from a sibling
+    /// or merging it into one. If they do not fit into one node, which can happen when
+    /// `min_keys` is more than half of a node, they share their keys evenly instead.
+    fn rebalance_children(&mut self, id: NodeId, index: usize, min_keys: usize) {
+        let node = &self[id];
+        if self[node.children[index]].keys.len() >= min_keys {
             return;
         }
-        self.children[index].insert(key);
-        if self.children[index].is_overflow() {
-            self.split_children(index);
+        if index > 0 && self[node.children[index - 1]].keys.len() > min_keys {
+            self.rotate_from_left(id, index);
+        } else if index + 1 < node.children.len()
+            && self[node.children[index + 1]].keys.len() > min_keys
+        {
+            self.rotate_from_right(id, index);
+        } else {
+            self.even_out(id, index.saturating_sub(1));
+        }
+    }
+
+    /// Returns the `index`-th smallest key of the subtree, counting from zero.
+    pub(crate) fn select(&self, id: NodeId, mut index: usize) -> Option<&T> {
+        let node = &self[id];
+        if index >= node.size {
+            return None;
+        }
+        if node.is_leaf() {
+            return node.keys.get(index);
+        }
+        for (i, &child) in node.children.iter().enumerate() {
+            let child_size = self[child].size;
+            if index < child_size {
+                return self.select(child, index);
+            }
+            index -= child_size;
+            if index == 0 {
+                return node.keys.get(i);
+            }
+            index -= 1;
         }
+        None
     }
 
-    fn split_children(&mut self, index: usize) {
-        let split_at = self.children[index].order / 2;
-        let right_child = Node {
-            order: self.children[index].order,
-            kind: self.children[index].kind,
-            keys: self.children[index].keys.split_off(split_at + 1),
-            children: if self.children[index].kind != NodeKind::Leaf {
-                self.children[index].children.split_off(split_at + 1)
+    /// Returns the number of keys in the subtree less than `key`, or equal to it if `inclusive`.
+    pub(crate) fn rank<C: Comparator<T>>(
+        &self,
+        id: NodeId,
+        key: &T,
+        inclusive: bool,
+        cmp: &C,
+    ) -> usize {
+        let node = &self[id];
+        let index = node.partition(key, inclusive, cmp);
+        if node.is_leaf() {
+            return index;
+        }
+        let smaller: usize = node.children[..index]
+            .iter()
+            .map(|&child| self[child].size)
+            .sum::<usize>()
+            + index;
+        // Keys before the separators counted are smaller, and the subtree right after them
+        // holds the boundary.
+        smaller + self.rank(node.children[index], key, inclusive, cmp)
+    }
+
+    /// Split the subtree into keys less than `key`, or equal to it if `inclusive`, which stay
+    /// in this node, and the rest, which are returned as a subtree of the same height.
+    /// Nodes along the cut may be left underfull, even without any key.
+    pub(crate) fn split_off<C: Comparator<T>>(
+        &mut self,
+        id: NodeId,
+        key: &T,
+        inclusive: bool,
+        cmp: &C,
+    ) -> NodeId {
+        let node = &mut self[id];
+        let index = match node.search(key, cmp) {
+            Ok(index) if inclusive => index + 1,
+            Ok(index) | Err(index) => index,
+        };
+        let order = node.order;
+        let kind = node.kind;
+        let keys = node.keys.split_off(index);
+        let children = if node.is_leaf() {
+            Children::new()
+        } else {
+            let mut children = node.children.split_off(index + 1);
+            let child = node.children[index];
+            children.insert(0, self.split_off(child, key, inclusive, cmp));
+            children
+        };
+        let right = self.with_children(order, kind, keys, children);
+        self[id].size -= self[right].size;
+        right
+    }
+
+    /// Refill underfull nodes on the rightmost path below a node, which `split_off` leaves
+    /// behind. The node itself may stay underfull.
+    pub(crate) fn fix_right_border(&mut self, id: NodeId) {
+        if self[id].is_leaf() {
+            return;
+        }
+        loop {
+            let node = &self[id];
+            let last = node.children.len() - 1;
+            let last_child = node.children[last];
+            self.fix_right_border(last_child);
+            if last == 0 || !self[last_child].is_underflow() {
+                return;
+            }
+            let node = &self[id];
+            let sibling_len = self[node.children[last - 1]].keys.len();
+            let len = self[last_child].keys.len();
+            if sibling_len + len < node.order - 1 {
+                self.merge_children(id, last - 1);
             } else {
-                vec![]
-            },
+                for _ in 0..(sibling_len - len) / 2 {
+                    self.rotate_from_left(id, last);
+                }
+            }
+        }
+    }
+
+    /// Refill underfull nodes on the leftmost path below a node, which `split_off` leaves
+    /// behind. The node itself may stay underfull.
+    pub(crate) fn fix_left_border(&mut self, id: NodeId) {
+        if self[id].is_leaf() {
+            return;
+        }
+        loop {
+            let first_child = self[id].children[0];
+            self.fix_left_border(first_child);
+            let node = &self[id];
+            if node.children.len() == 1 || !self[first_child].is_underflow() {
+                return;
+            }
+            let sibling_len = self[node.children[1]].keys.len();
+            let len = self[first_child].keys.len();
+            if sibling_len + len < node.order - 1 {
+                self.merge_children(id, 0);
+            } else {
+                for _ in 0..(sibling_len - len) / 2 {
+                    self.rotate_from_right(id, 0);
+                }
+            }
+        }
+    }
+
+    /// Join two subtrees with a separator greater than the keys of `left` and less than
+    /// those of `right`. The lower subtree hangs off the border of the higher one, so only
+    /// nodes along that border are restructured. Both roots may be underfull, and the
+    /// returned root may be left overflowing or without any key.
+    pub(crate) fn join(&mut self, left: NodeId, separator: T, right: NodeId) -> NodeId {
+        let (left_height, right_height) = (self.height(left), self.height(right));
+        if left_height == right_height {
+            let order = self[left].order;
+            self.make_child(left);
+            self.make_child(right);
+            let keys = Keys::from(vec![separator]);
+            let children = Children::from(vec![left, right]);
+            let root = self.with_children(order, NodeKind::Root, keys, children);
+            self.refill_pair(root, 0);
+            return root;
+        }
+        // Nodes on the border of the higher subtree, down to the one which takes the lower
+        // subtree as a child.
+        let (root, lower) = if left_height > right_height {
+            (left, right)
+        } else {
+            (right, left)
+        };
+        let mut path = vec![root];
+        for _ in 1..left_height.abs_diff(right_height) {
+            let node = &self[*path.last().unwrap()];
+            let border = if lower == right {
+                node.children.len() - 1
+            } else {
+                0
+            };
+            path.push(node.children[border]);
+        }
+        let added = 1 + self[lower].size;
+        for &id in &path {
+            self[id].size += added;
+        }
+        self.make_child(lower);
+        let parent = *path.last().unwrap();
+        let node = &mut self[parent];
+        if lower == right {
+            node.keys.push(separator);
+            node.children.push(right);
+            let index = node.keys.len() - 1;
+            self.refill_pair(parent, index);
+        } else {
+            node.keys.insert(0, separator);
+            node.children.insert(0, left);
+            self.refill_pair(parent, 0);
+        }
+        // Split nodes overflowing with the separator on the way back up.
+        for pair in path.windows(2).rev() {
+            if self[pair[1]].is_overflow() {
+                let index = if lower == right {
+                    self[pair[0]].children.len() - 1
+                } else {
+                    0
+                };
+                self.split_children(pair[0], index);
+            }
+        }
+        root
+    }
+
+    /// Returns the number of levels below a node.
+    fn height(&self, id: NodeId) -> usize {
+        let mut node = &self[id];
+        let mut height = 0;
+        while !node.is_leaf() {
+            node = &self[node.children[0]];
+            height += 1;
+        }
+        height
+    }
+
+    /// Mark the root of a subtree as a leaf or an internal node, before it becomes a child.
+    pub(crate) fn make_child(&mut self, id: NodeId) {
+        let node = &mut self[id];
+        node.kind = if node.is_leaf() {
+            NodeKind::Leaf
+        } else {
+            NodeKind::Internal
         };
-        self.children.insert(index + 1, right_child);
-        let ascending_key = self.children[index].keys.pop().unwrap();
-        self.keys.insert(index, ascending_key);
     }
+
+    /// Refill `children[index]` or `children[index + 1]` of a node if it underflows,
+    /// by merging the two or moving keys over from the fuller one until they are even.
+    fn refill_pair(&mut self, id: NodeId, index: usize) {
+        let node = &self[id];
+        let (left, right) = (node.children[index], node.children[index + 1]);
+        if !self[left].is_underflow() && !self[right].is_underflow() {
+            return;
+        }
+        self.even_out(id, index);
+    }
+
+    /// Merge `children[index]` and `children[index + 1]` of a node if they fit into one node,
+    /// and otherwise move keys over from the fuller one until they are even.
+    fn even_out(&mut self, id: NodeId, index: usize) {
+        let node = &self[id];
+        let (left, right) = (node.children[index], node.children[index + 1]);
+        let left_len = self[left].keys.len();
+        let right_len = self[right].keys.len();
+        if left_len + right_len < node.order - 1 {
+            self.merge_children(id, index);
+        } else if left_len < right_len {
+            for _ in 0..(right_len - left_len) / 2 {
+                self.rotate_from_right(id, index);
+            }
+        } else {
+            for _ in 0..(left_len - right_len) / 2 {
+                self.rotate_from_left(id, index + 1);
+            }
+        }
+    }
+
+    /// Move the last key of `children[index - 1]` up to a node, and the separator
+    /// it replaces down to the front of `children[index]`.
+    pub(crate) fn rotate_from_left(&mut self, id: NodeId, index: usize) {
+        #[cfg(feature = "metrics")]
+        {
+            self.counters.rotations += 1;
+        }
+        let left = self[id].children[index - 1];
+        let right = self[id].children[index];
+        let left_node = &mut self[left];
+        let key = left_node.keys.pop().unwrap();
+        let child = left_node.children.pop();
+        let moved = 1 + child.map_or(0, |child| self[child].size);
+        self[left].size -= moved;
+        let separator = mem::replace(&mut self[id].keys[index - 1], key);
+        let right = &mut self[right];
+        right.keys.insert(0, separator);
+        right.size += moved;
+        if let Some(child) = child {
+            right.children.insert(0, child);
+        }
+    }
+
+    /// Move the first key of `children[index + 1]` up to a node, and the separator
+    /// it replaces down to the back of `children[index]`.
+    pub(crate) fn rotate_from_right(&mut self, id: NodeId, index: usize) {
+        #[cfg(feature = "metrics")]
+        {
+            self.counters.rotations += 1;
+        }
+        let left = self[id].children[index];
+        let right = self[id].children[index + 1];
+        let right_node = &mut self[right];
+        let key = right_node.keys.remove(0);
+        let child = if right_node.is_leaf() {
+            None
+        } else {
+            Some(right_node.children.remove(0))
+        };
+        let moved = 1 + child.map_or(0, |child| self[child].size);
+        self[right].size -= moved;
+        let separator = mem::replace(&mut self[id].keys[index], key);
+        let left = &mut self[left];
+        left.keys.push(separator);
+        left.size += moved;
+        left.children.extend(child);
+    }
+
+    /// Merge `children[index + 1]` and the separator between them into `children[index]`,
+    /// releasing the right one.
+    pub(crate) fn merge_children(&mut self, id: NodeId, index: usize) {
+        #[cfg(feature = "metrics")]
+        {
+            self.counters.merges += 1;
+        }
+        let node = &mut self[id];
+        let right = node.children.remove(index + 1);
+        let left = node.children[index];
+        let separator = node.keys.remove(index);
+        let right = self.release(right);
+        let left = &mut self[left];
+        left.keys.push(separator);
+        left.size += 1 + right.size;
+        left.keys.extend(right.keys);
+        left.children.extend(right.children);
+    }
+}
+
+/// How a bulk build spreads sorted keys over leaves, keeping one key between each pair of
+/// leaves as a separator.
+#[derive(Clone, Copy, Debug)]
+struct LeafLayout {
+    // The number of leaves.
+    count: usize,
+    // The number of keys in all leaves, leaving out separators.
+    key_count: usize,
+}
+
+impl LeafLayout {
+    fn new(len: usize, order: usize) -> Self {
+        let count = (len + 1).div_ceil(order);
+        Self {
+            count,
+            key_count: len + 1 - count,
+        }
+    }
+
+    /// Returns the position of the first key of a leaf among all keys.
+    #[cfg(feature = "std")]
+    fn first_key(&self, leaf: usize) -> usize {
+        even_offset(self.key_count, self.count, leaf) + leaf
+    }
+
+    /// Build a range of leaves from their keys, which start with the first key of the first leaf.
+    /// Returns the leaves and the separators after each of them but the last leaf of all.
+    fn build<T>(&self, keys: Vec<T>, leaves: Range<usize>, order: usize) -> (Vec<Node<T>>, Vec<T>) {
+        let mut keys = keys.into_iter();
+        let mut nodes = Vec::with_capacity(leaves.len());
+        let mut separators = Vec::with_capacity(leaves.len());
+        for i in leaves {
+            let leaf_keys: Keys<T> = keys
+                .by_ref()
+                .take(even_share(self.key_count, self.count, i))
+                .collect();
+            nodes.push(Node {
+                order,
+                kind: NodeKind::Leaf,
+                size: leaf_keys.len(),
+                keys: leaf_keys,
+                children: Children::new(),
+            });
+            if i + 1 < self.count {
+                separators.push(keys.next().unwrap());
+            }
+        }
+        (nodes, separators)
+    }
+}
+
+/// Size of the `index`-th of `parts` near-equal parts of `total`.
+pub(crate) fn even_share(total: usize, parts: usize, index: usize) -> usize {
+    total / parts + usize::from(index < total % parts)
+}
+
+/// Total size of the parts before the `index`-th of `parts` near-equal parts of `total`.
+#[cfg(feature = "std")]
+fn even_offset(total: usize, parts: usize, index: usize) -> usize {
+    index * (total / parts) + index.min(total % parts)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::node::{Node, NodeKind};
+    use crate::compare::OrdComparator;
+    use crate::node::{Arena, Children, Node, NodeKind};
 
     #[test]
     fn test_split_children() {
-        let mut tree = Node {
-            order: 3,
-            kind: NodeKind::Internal,
-            keys: vec![2, 6],
-            children: vec![
-                Node {
-                    order: 3,
-                    kind: NodeKind::Leaf,
-                    keys: vec![1],
-                    children: vec![],
-                },
-                Node {
-                    order: 3,
-                    kind: NodeKind::Leaf,
-                    keys: vec![3, 4, 5],
-                    children: vec![],
-                },
-                Node {
-                    order: 3,
-                    kind: NodeKind::Leaf,
-                    keys: vec![7],
-                    children: vec![],
-                },
-            ],
-        };
-        tree.split_children(1);
+        let mut nodes = Arena::new();
+        let leaves = vec![
+            nodes.with_children(3, NodeKind::Leaf, vec![1].into(), Children::new()),
+            nodes.with_children(3, NodeKind::Leaf, vec![3, 4, 5].into(), Children::new()),
+            nodes.with_children(3, NodeKind::Leaf, vec![7].into(), Children::new()),
+        ];
+        let tree = nodes.with_children(3, NodeKind::Internal, vec![2, 6].into(), leaves.into());
+        nodes.split_children(tree, 1);
+        assert_eq!(nodes[tree].keys[..], [2, 4, 6]);
+        assert_eq!(nodes[tree].size, 7);
+        let children: Vec<_> = nodes[tree]
+            .children
+            .iter()
+            .map(|&child| {
+                (
+                    nodes[child].kind,
+                    nodes[child].keys.to_vec(),
+                    nodes[child].size,
+                )
+            })
+            .collect();
         assert_eq!(
-            Node {
-                order: 3,
-                kind: NodeKind::Internal,
-                keys: vec![2, 4, 6],
-                children: vec![
-                    Node {
-                        order: 3,
-                        kind: NodeKind::Leaf,
-                        keys: vec![1],
-                        children: vec![],
-                    },
-                    Node {
-                        order: 3,
-                        kind: NodeKind::Leaf,
-                        keys: vec![3],
-                        children: vec![],
-                    },
-                    Node {
-                        order: 3,
-                        kind: NodeKind::Leaf,
-                        keys: vec![5],
-                        children: vec![],
-                    },
-                    Node {
-                        order: 3,
-                        kind: NodeKind::Leaf,
-                        keys: vec![7],
-                        children: vec![],
-                    },
-                ],
-            },
-            tree,
+            children,
+            vec![
+                (NodeKind::Leaf, vec![1], 1),
+                (NodeKind::Leaf, vec![3], 1),
+                (NodeKind::Leaf, vec![5], 1),
+                (NodeKind::Leaf, vec![7], 1),
+            ]
         );
     }
+
+    #[test]
+    fn search_small_and_wide_nodes() {
+        for &order in &[3, 4, 16, 17, 64] {
+            let mut node = Node::new(order);
+            for len in 0..order {
+                node.keys = (0..len).map(|key| key * 2).collect::<Vec<_>>().into();
+                for probe in 0..len * 2 + 1 {
+                    assert_eq!(
+                        node.search(&probe, &OrdComparator),
+                        node.keys.binary_search(&probe)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reuse_released_nodes() {
+        let mut nodes = Arena::new();
+        let leaves = vec![
+            nodes.with_children(3, NodeKind::Leaf, vec![1].into(), Children::new()),
+            nodes.with_children(3, NodeKind::Leaf, vec![3].into(), Children::new()),
+        ];
+        let released = leaves[1];
+        let tree = nodes.with_children(3, NodeKind::Root, vec![2].into(), leaves.into());
+        nodes.merge_children(tree, 0);
+        assert_eq!(nodes[nodes[tree].children[0]].keys[..], [1, 2, 3]);
+        let leaf = nodes.with_children(3, NodeKind::Leaf, vec![4].into(), Children::new());
+        assert_eq!(leaf, released);
+        assert_eq!(nodes.nodes.len(), 3);
+    }
 }
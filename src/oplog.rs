@@ -0,0 +1,249 @@
+use crate::btree::BTree;
+use crate::error::ReplayError;
+use alloc::vec::Vec;
+use core::ops::Deref;
+
+/// A change to the keys of a tree, as recorded by a `LoggedBTree`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Op<T> {
+    Insert(T),
+    Remove(T),
+}
+
+/// An operation numbered by its position in the log, counting from zero.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Record<T> {
+    pub seq: u64,
+    pub op: Op<T>,
+}
+
+/// Where a `LoggedBTree` sends the operations it records, such as a vector in memory
+/// or a file the sink writes each record to.
+pub trait OpSink<T> {
+    fn record(&mut self, record: Record<T>);
+}
+
+impl<T> OpSink<T> for Vec<Record<T>> {
+    fn record(&mut self, record: Record<T>) {
+        self.push(record);
+    }
+}
+
+impl<T, S> OpSink<T> for &mut S
+where
+    S: OpSink<T>,
+{
+    fn record(&mut self, record: Record<T>) {
+        (**self).record(record);
+    }
+}
+
+/// A tree which records every insertion and removal into a sink before applying it, so the
+/// exact sequence of operations which led to a state can be captured. An operation which
+/// panics, such as inserting a present key under `DuplicatePolicy::Error`, is recorded as well.
+/// `BTree::replay` rebuilds the tree from the records.
+/// Every `&self` method of `BTree` is available through `Deref`.
+#[derive(Debug)]
+pub struct LoggedBTree<T, S> {
+    tree: BTree<T>,
+    sink: S,
+    // The sequence number of the next operation.
+    seq: u64,
+}
+
+impl<T, S> LoggedBTree<T, S>
+where
+    T: Ord + Clone,
+    S: OpSink<T>,
+{
+    /// Create an empty tree recording into `sink`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_ORDER`.
+    pub fn new(order: usize, sink: S) -> Self {
+        Self::with_tree(BTree::new(order), sink)
+    }
+
+    /// Record the operations on a given tree, keeping its keys and policies.
+    /// Only operations from now on are recorded, so the log alone reproduces the tree only if
+    /// it is empty.
+    pub fn with_tree(tree: BTree<T>, sink: S) -> Self {
+        Self { tree, sink, seq: 0 }
+    }
+
+    /// Insert a key, as `BTree::insert` does.
+    pub fn insert(&mut self, key: T) {
+        self.log(Op::Insert(key.clone()));
+        self.tree.insert(key);
+    }
+
+    /// Remove a key, returning whether it was present, as `BTree::remove` does.
+    pub fn remove(&mut self, key: &T) -> bool {
+        self.take(key).is_some()
+    }
+
+    /// Remove a key, returning the key which was stored in the tree, as `BTree::take` does.
+    pub fn take(&mut self, key: &T) -> Option<T> {
+        self.log(Op::Remove(key.clone()));
+        self.tree.take(key)
+    }
+
+    /// Returns the sequence number the next operation will be recorded with.
+    pub fn next_seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Stop recording, returning the tree and the sink.
+    pub fn into_parts(self) -> (BTree<T>, S) {
+        (self.tree, self.sink)
+    }
+
+    fn log(&mut self, op: Op<T>) {
+        self.sink.record(Record { seq: self.seq, op });
+        self.seq += 1;
+    }
+}
+
+impl<T, S> Deref for LoggedBTree<T, S> {
+    type Target = BTree<T>;
+
+    fn deref(&self) -> &BTree<T> {
+        &self.tree
+    }
+}
+
+impl<T> BTree<T>
+where
+    T: Ord + Clone,
+{
+    /// Rebuild a tree by applying recorded operations in order to an empty tree of `order`,
+    /// so the state a `LoggedBTree` reached can be reproduced deterministically, or restored
+    /// from a log kept as a poor man's durability. The tree inserts with the default duplicate
+    /// policy, so a log of a tree with another policy reproduces it only if no inserted key
+    /// was present.
+    ///
+    /// Records must be numbered from zero without gaps, or an error names the first record
+    /// out of sequence.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_ORDER`.
+    pub fn replay<I>(order: usize, records: I) -> Result<Self, ReplayError>
+    where
+        I: IntoIterator<Item = Record<T>>,
+    {
+        let mut tree = BTree::new(order);
+        for (expected, record) in (0..).zip(records) {
+            if record.seq != expected {
+                return Err(ReplayError {
+                    expected,
+                    found: record.seq,
+                });
+            }
+            match record.op {
+                Op::Insert(key) => tree.insert(key),
+                Op::Remove(key) => {
+                    tree.remove(&key);
+                }
+            }
+        }
+        Ok(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::btree::{BTree, DuplicatePolicy};
+    use crate::error::ReplayError;
+    use crate::oplog::{LoggedBTree, Op, OpSink, Record};
+    use rand::Rng;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn record_operations_in_order() {
+        let mut tree = LoggedBTree::new(4, vec![]);
+        tree.insert(2);
+        tree.insert(1);
+        assert!(tree.remove(&2));
+        assert!(!tree.remove(&5));
+        assert_eq!(tree.take(&1), Some(1));
+        assert!(tree.is_empty());
+        assert_eq!(tree.next_seq(), 5);
+        let ops: Vec<_> = tree.sink().iter().map(|record| record.op.clone()).collect();
+        assert_eq!(
+            ops,
+            [
+                Op::Insert(2),
+                Op::Insert(1),
+                Op::Remove(2),
+                Op::Remove(5),
+                Op::Remove(1)
+            ]
+        );
+        assert!(tree
+            .sink()
+            .iter()
+            .enumerate()
+            .all(|(i, record)| record.seq == i as u64));
+    }
+
+    #[test]
+    fn record_the_operation_which_panics() {
+        struct Last(Option<Record<u32>>);
+
+        impl OpSink<u32> for Last {
+            fn record(&mut self, record: Record<u32>) {
+                self.0 = Some(record);
+            }
+        }
+
+        let mut last = Last(None);
+        let mut inner = BTree::new(4);
+        inner.set_duplicate_policy(DuplicatePolicy::Error);
+        inner.insert(7);
+        let mut tree = LoggedBTree::with_tree(inner, &mut last);
+        tree.insert(8);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| tree.insert(7)));
+        assert!(result.is_err());
+        assert_eq!(
+            last.0,
+            Some(Record {
+                seq: 1,
+                op: Op::Insert(7)
+            })
+        );
+    }
+
+    #[test]
+    fn replay_reproduces_the_tree() {
+        let mut rng = rand::thread_rng();
+        let mut tree = LoggedBTree::new(5, vec![]);
+        for _ in 0..2_000 {
+            let key = rng.gen_range(0, 300u32);
+            if rng.gen_bool(0.6) {
+                tree.insert(key);
+            } else {
+                tree.remove(&key);
+            }
+        }
+        let (tree, log) = tree.into_parts();
+        let replayed = BTree::replay(5, log.clone()).unwrap();
+        assert_eq!(replayed, tree);
+        assert_eq!(replayed.order(), 5);
+
+        let mut gap = log;
+        gap.remove(10);
+        assert_eq!(
+            BTree::replay(5, gap),
+            Err(ReplayError {
+                expected: 10,
+                found: 11
+            })
+        );
+    }
+}
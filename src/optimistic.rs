@@ -0,0 +1,429 @@
+use crate::segments::Segments;
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::atomic::{
+    self, AtomicBool, AtomicI16, AtomicI32, AtomicI64, AtomicI8, AtomicIsize, AtomicU16, AtomicU32,
+    AtomicU64, AtomicU8, AtomicUsize,
+};
+use std::thread;
+
+/// The maximum number of children of a node of an `OptimisticBTree`.
+pub const OPTIMISTIC_ORDER: usize = 16;
+const MAX_KEYS: usize = OPTIMISTIC_ORDER - 1;
+
+/// A key which an `OptimisticBTree` can store in an atomic, so that readers may load it
+/// while a writer changes the node holding it.
+pub trait AtomicKey: Ord + Copy {
+    type Atomic: Default + fmt::Debug + Send + Sync;
+
+    fn load(atomic: &Self::Atomic) -> Self;
+    fn store(atomic: &Self::Atomic, key: Self);
+}
+
+macro_rules! impl_atomic_key {
+    ($($int:ty => $atomic:ty),*) => {
+        $(
+            impl AtomicKey for $int {
+                type Atomic = $atomic;
+
+                fn load(atomic: &$atomic) -> Self {
+                    atomic.load(atomic::Ordering::SeqCst)
+                }
+
+                fn store(atomic: &$atomic, key: Self) {
+                    atomic.store(key, atomic::Ordering::SeqCst)
+                }
+            }
+        )*
+    };
+}
+
+impl_atomic_key!(
+    u8 => AtomicU8, u16 => AtomicU16, u32 => AtomicU32, u64 => AtomicU64, usize => AtomicUsize,
+    i8 => AtomicI8, i16 => AtomicI16, i32 => AtomicI32, i64 => AtomicI64, isize => AtomicIsize
+);
+
+/// A concurrent B-Tree of integer keys whose readers never take a latch.
+///
+/// Every node carries a version, which is odd while a writer holds the node and is bumped when
+/// the writer changes it. A reader remembers the version of each node it visits, and checks
+/// it again before trusting anything read from the node (optimistic lock coupling).
+/// If a writer got in between, the lookup restarts from the root.
+///
+/// Keys and child pointers are stored in atomics, so that reading a node while it is being
+/// changed is never undefined behavior, only a reason to restart.
+/// Writers use the versions as latches, holding a parent and a child at a time.
+/// `ConcurrentBTree` holds keys of any type instead, so its readers take latches.
+#[derive(Debug)]
+pub struct OptimisticBTree<K: AtomicKey = u64> {
+    nodes: Segments<OptimisticNode<K>>,
+    // Guards `root`. Writers hold it only until they hold the root node.
+    root_version: AtomicU64,
+    root: AtomicUsize,
+    len: AtomicUsize,
+}
+
+// Signals that a node changed under a reader, which must start over.
+struct Restart;
+
+#[derive(Debug)]
+struct OptimisticNode<K: AtomicKey> {
+    version: AtomicU64,
+    // Fixed before the node is linked into the tree.
+    is_leaf: AtomicBool,
+    len: AtomicUsize,
+    keys: [K::Atomic; MAX_KEYS],
+    children: [AtomicUsize; OPTIMISTIC_ORDER],
+}
+
+impl<K: AtomicKey> OptimisticBTree<K> {
+    pub fn new() -> Self {
+        let nodes = Segments::new();
+        let root = allocate_node(&nodes, true);
+        Self {
+            nodes,
+            root_version: AtomicU64::new(0),
+            root: AtomicUsize::new(root),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of keys in the tree.
+    /// Insertions running concurrently may or may not be counted yet.
+    pub fn len(&self) -> usize {
+        self.len.load(atomic::Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, key: K) -> bool {
+        loop {
+            if let Ok(is_found) = self.try_contains(key) {
+                return is_found;
+            }
+            thread::yield_now();
+        }
+    }
+
+    fn try_contains(&self, key: K) -> Result<bool, Restart> {
+        let root_version = read_lock(&self.root_version)?;
+        let mut node = self.nodes.get(self.root.load(atomic::Ordering::SeqCst));
+        let mut version = read_lock(&node.version)?;
+        validate(&self.root_version, root_version)?;
+        loop {
+            let len = node.len.load(atomic::Ordering::SeqCst).min(MAX_KEYS);
+            let index = match node.search(len, key) {
+                Ok(_) => {
+                    validate(&node.version, version)?;
+                    return Ok(true);
+                }
+                Err(index) => index,
+            };
+            if node.is_leaf.load(atomic::Ordering::SeqCst) {
+                validate(&node.version, version)?;
+                return Ok(false);
+            }
+            let child_id = node.children[index].load(atomic::Ordering::SeqCst);
+            // The child pointer is only meaningful if the node did not change meanwhile.
+            validate(&node.version, version)?;
+            let child = self.nodes.get(child_id);
+            let child_version = read_lock(&child.version)?;
+            validate(&node.version, version)?;
+            node = child;
+            version = child_version;
+        }
+    }
+
+    /// Insert a key, returning whether it was not present yet.
+    pub fn insert(&self, key: K) -> bool {
+        write_lock(&self.root_version);
+        let mut node = self.nodes.get(self.root.load(atomic::Ordering::SeqCst));
+        write_lock(&node.version);
+        if node.is_full() {
+            let new_root_id = allocate_node(&self.nodes, false);
+            let new_root = self.nodes.get(new_root_id);
+            write_lock(&new_root.version);
+            new_root.children[0].store(
+                self.root.load(atomic::Ordering::SeqCst),
+                atomic::Ordering::SeqCst,
+            );
+            self.split_children(new_root, 0, node);
+            self.root.store(new_root_id, atomic::Ordering::SeqCst);
+            write_unlock(&node.version, true);
+            write_unlock(&self.root_version, true);
+            node = new_root;
+        } else {
+            write_unlock(&self.root_version, false);
+        }
+
+        let is_inserted = self.insert_into(node, key);
+        if is_inserted {
+            self.len.fetch_add(1, atomic::Ordering::SeqCst);
+        }
+        is_inserted
+    }
+
+    /// Insert a key below a latched node which is not full, splitting full children on the way down.
+    fn insert_into<'a>(&'a self, mut node: &'a OptimisticNode<K>, key: K) -> bool {
+        loop {
+            let len = node.len.load(atomic::Ordering::SeqCst);
+            let index = match node.search(len, key) {
+                Ok(_) => {
+                    write_unlock(&node.version, false);
+                    return false;
+                }
+                Err(index) => index,
+            };
+            if node.is_leaf.load(atomic::Ordering::SeqCst) {
+                node.insert_key(len, index, key);
+                write_unlock(&node.version, true);
+                return true;
+            }
+            let mut child = self
+                .nodes
+                .get(node.children[index].load(atomic::Ordering::SeqCst));
+            write_lock(&child.version);
+            let is_split = child.is_full();
+            if is_split {
+                self.split_children(node, index, child);
+                match key.cmp(&K::load(&node.keys[index])) {
+                    Ordering::Less => {}
+                    Ordering::Equal => {
+                        write_unlock(&child.version, true);
+                        write_unlock(&node.version, true);
+                        return false;
+                    }
+                    Ordering::Greater => {
+                        write_unlock(&child.version, true);
+                        child = self
+                            .nodes
+                            .get(node.children[index + 1].load(atomic::Ordering::SeqCst));
+                        write_lock(&child.version);
+                    }
+                }
+            }
+            write_unlock(&node.version, is_split);
+            node = child;
+        }
+    }
+
+    /// Split a full child, whose latch the caller holds with that of `parent`,
+    /// moving its median key up to the parent.
+    fn split_children(&self, parent: &OptimisticNode<K>, index: usize, child: &OptimisticNode<K>) {
+        let is_leaf = child.is_leaf.load(atomic::Ordering::SeqCst);
+        let right_id = allocate_node(&self.nodes, is_leaf);
+        let right = self.nodes.get(right_id);
+        let split_at = MAX_KEYS / 2;
+        for i in 0..MAX_KEYS - split_at - 1 {
+            let key = K::load(&child.keys[split_at + 1 + i]);
+            K::store(&right.keys[i], key);
+        }
+        if !is_leaf {
+            for i in 0..MAX_KEYS - split_at {
+                let grandchild = child.children[split_at + 1 + i].load(atomic::Ordering::SeqCst);
+                right.children[i].store(grandchild, atomic::Ordering::SeqCst);
+            }
+        }
+        right
+            .len
+            .store(MAX_KEYS - split_at - 1, atomic::Ordering::SeqCst);
+        let ascending_key = K::load(&child.keys[split_at]);
+        child.len.store(split_at, atomic::Ordering::SeqCst);
+
+        let len = parent.len.load(atomic::Ordering::SeqCst);
+        for i in (index + 1..=len).rev() {
+            let grandchild = parent.children[i].load(atomic::Ordering::SeqCst);
+            parent.children[i + 1].store(grandchild, atomic::Ordering::SeqCst);
+        }
+        parent.children[index + 1].store(right_id, atomic::Ordering::SeqCst);
+        parent.insert_key(len, index, ascending_key);
+    }
+}
+
+impl<K: AtomicKey> Default for OptimisticBTree<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: AtomicKey> Default for OptimisticNode<K> {
+    fn default() -> Self {
+        Self {
+            version: AtomicU64::new(0),
+            is_leaf: AtomicBool::new(false),
+            len: AtomicUsize::new(0),
+            keys: std::array::from_fn(|_| K::Atomic::default()),
+            children: std::array::from_fn(|_| AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl<K: AtomicKey> OptimisticNode<K> {
+    fn is_full(&self) -> bool {
+        self.len.load(atomic::Ordering::SeqCst) == MAX_KEYS
+    }
+
+    /// Binary search the first `len` keys.
+    fn search(&self, len: usize, key: K) -> Result<usize, usize> {
+        let (mut low, mut high) = (0, len);
+        while low < high {
+            let middle = (low + high) / 2;
+            match K::load(&self.keys[middle]).cmp(&key) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Equal => return Ok(middle),
+                Ordering::Greater => high = middle,
+            }
+        }
+        Err(low)
+    }
+
+    /// Insert a key at `index` of the first `len` keys, shifting the keys after it.
+    fn insert_key(&self, len: usize, index: usize, key: K) {
+        for i in (index..len).rev() {
+            let moved = K::load(&self.keys[i]);
+            K::store(&self.keys[i + 1], moved);
+        }
+        K::store(&self.keys[index], key);
+        self.len.store(len + 1, atomic::Ordering::SeqCst);
+    }
+}
+
+/// Take a node from the store and mark it as a leaf or not, before it is linked into the tree.
+fn allocate_node<K: AtomicKey>(nodes: &Segments<OptimisticNode<K>>, is_leaf: bool) -> usize {
+    let id = nodes.allocate();
+    nodes
+        .get(id)
+        .is_leaf
+        .store(is_leaf, atomic::Ordering::SeqCst);
+    id
+}
+
+/// Returns the version of a node which no writer holds.
+fn read_lock(version: &AtomicU64) -> Result<u64, Restart> {
+    let current = version.load(atomic::Ordering::SeqCst);
+    if !current.is_multiple_of(2) {
+        return Err(Restart);
+    }
+    Ok(current)
+}
+
+/// Check that a node is still at the version a reader started with.
+fn validate(version: &AtomicU64, expected: u64) -> Result<(), Restart> {
+    if version.load(atomic::Ordering::SeqCst) != expected {
+        return Err(Restart);
+    }
+    Ok(())
+}
+
+fn write_lock(version: &AtomicU64) {
+    loop {
+        let current = version.load(atomic::Ordering::SeqCst);
+        if current.is_multiple_of(2)
+            && version
+                .compare_exchange_weak(
+                    current,
+                    current + 1,
+                    atomic::Ordering::SeqCst,
+                    atomic::Ordering::SeqCst,
+                )
+                .is_ok()
+        {
+            return;
+        }
+        thread::yield_now();
+    }
+}
+
+/// Release a node, moving it to a new version if it was changed so that readers restart.
+fn write_unlock(version: &AtomicU64, is_changed: bool) {
+    if is_changed {
+        version.fetch_add(1, atomic::Ordering::SeqCst);
+    } else {
+        version.fetch_sub(1, atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::optimistic::{OptimisticBTree, OptimisticNode};
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
+
+    // Asserts given subtree is valid, collecting its keys in order, and returns its height.
+    fn is_valid_subtree(
+        tree: &OptimisticBTree,
+        node: &OptimisticNode<u64>,
+        keys: &mut Vec<u64>,
+    ) -> usize {
+        assert_eq!(node.version.load(Ordering::SeqCst) % 2, 0);
+        let len = node.len.load(Ordering::SeqCst);
+        let node_keys: Vec<u64> = node.keys[..len]
+            .iter()
+            .map(|key| key.load(Ordering::SeqCst))
+            .collect();
+        if node.is_leaf.load(Ordering::SeqCst) {
+            keys.extend(node_keys);
+            return 1;
+        }
+        let heights: Vec<usize> = (0..=len)
+            .map(|i| {
+                let child = tree.nodes.get(node.children[i].load(Ordering::SeqCst));
+                assert!(child.len.load(Ordering::SeqCst) > 0);
+                let height = is_valid_subtree(tree, child, keys);
+                keys.extend(node_keys.get(i));
+                height
+            })
+            .collect();
+        // All leaves must be at the same depth.
+        assert!(heights.iter().all(|&height| height == heights[0]));
+        heights[0] + 1
+    }
+
+    #[test]
+    fn read_while_writing() {
+        let tree = Arc::new(OptimisticBTree::new());
+        let writers: Vec<_> = (0..2u64)
+            .map(|thread_index| {
+                let tree = Arc::clone(&tree);
+                // Writers insert even keys, overlapping with each other.
+                thread::spawn(move || {
+                    (0..20_000u64)
+                        .map(|i| (i * 7 + thread_index * 5_000) % 30_000 * 2)
+                        .filter(|&key| tree.insert(key))
+                        .count()
+                })
+            })
+            .collect();
+        let reader = {
+            let tree = Arc::clone(&tree);
+            thread::spawn(move || (0..30_000u64).all(|i| !tree.contains(i * 2 + 1)))
+        };
+        let inserted: usize = writers
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum();
+        assert!(reader.join().unwrap());
+        assert_eq!(tree.len(), inserted);
+
+        let mut keys = vec![];
+        let root = tree.nodes.get(tree.root.load(Ordering::SeqCst));
+        is_valid_subtree(&tree, root, &mut keys);
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(keys.len(), inserted);
+        assert!(keys.iter().all(|&key| tree.contains(key)));
+    }
+
+    #[test]
+    fn store_signed_keys() {
+        let tree = OptimisticBTree::<i32>::new();
+        for key in -500..500 {
+            assert!(tree.insert(key * 3));
+        }
+        assert!(!tree.insert(-300));
+        assert_eq!(tree.len(), 1_000);
+        assert!((-500..500).all(|key| tree.contains(key * 3) && !tree.contains(key * 3 + 1)));
+    }
+}
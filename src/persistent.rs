@@ -0,0 +1,335 @@
+use crate::btree::check_order;
+use crate::error::InvalidOrder;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem;
+
+/// An immutable B-Tree. `insert` leaves the tree alone and returns a new one, which shares
+/// every node off the path to the new key with the old tree, so keeping old versions around
+/// (for undo, say) costs only the nodes which changed.
+///
+/// Cloning a tree only bumps the reference count of its root. `insert_mut` changes a tree in
+/// place instead, copying a node only while another clone still shares it (copy-on-write).
+#[derive(Clone, Debug)]
+pub struct PersistentBTree<T> {
+    // The maximum number of children each node can accomodates.
+    order: usize,
+    root: Arc<PersistentNode<T>>,
+    len: usize,
+}
+
+#[derive(Clone, Debug)]
+struct PersistentNode<T> {
+    keys: Vec<T>,
+    children: Vec<Arc<PersistentNode<T>>>,
+}
+
+/// The result of inserting a key into a subtree.
+enum Insertion<T> {
+    // The key is in the subtree already.
+    Present,
+    Replaced(PersistentNode<T>),
+    // The new node overflowed, and was split around the key between the halves.
+    Split(PersistentNode<T>, T, PersistentNode<T>),
+}
+
+impl<T> PersistentBTree<T>
+where
+    T: Ord + Clone,
+{
+    /// Create an empty tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_ORDER`. Use `try_new` to handle it as an error.
+    pub fn new(order: usize) -> Self {
+        match Self::try_new(order) {
+            Ok(tree) => tree,
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /// Create an empty tree, failing if `order` is less than `MIN_ORDER`.
+    pub fn try_new(order: usize) -> Result<Self, InvalidOrder> {
+        check_order(order)?;
+        Ok(Self {
+            order,
+            root: Arc::new(PersistentNode::new()),
+            len: 0,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, key: &T) -> bool {
+        let mut node = &self.root;
+        loop {
+            match node.keys.binary_search(key) {
+                Ok(_) => return true,
+                Err(_) if node.is_leaf() => return false,
+                Err(index) => node = &node.children[index],
+            }
+        }
+    }
+
+    /// Returns a tree which also holds `key`, copying only the nodes on the path to it.
+    /// If the key is present already, the new tree shares the root with this one.
+    pub fn insert(&self, key: T) -> Self {
+        let root = match self.root.insert(key, self.order) {
+            Insertion::Present => return self.clone(),
+            Insertion::Replaced(root) => root,
+            Insertion::Split(left, median, right) => PersistentNode {
+                keys: vec![median],
+                children: vec![Arc::new(left), Arc::new(right)],
+            },
+        };
+        Self {
+            order: self.order,
+            root: Arc::new(root),
+            len: self.len + 1,
+        }
+    }
+
+    /// Insert a key in place, returning whether it was not present yet.
+    /// Nodes this tree shares with clones are copied first, so the clones are not affected.
+    pub fn insert_mut(&mut self, key: T) -> bool {
+        if self.contains(&key) {
+            return false;
+        }
+        if let Some((median, right)) = Arc::make_mut(&mut self.root).insert_mut(key, self.order) {
+            let left = mem::replace(&mut self.root, Arc::new(PersistentNode::new()));
+            self.root = Arc::new(PersistentNode {
+                keys: vec![median],
+                children: vec![left, Arc::new(right)],
+            });
+        }
+        self.len += 1;
+        true
+    }
+
+    /// Returns an iterator over keys in ascending order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        let mut iter = Iter { stack: vec![] };
+        iter.push_leftmost_path(&self.root);
+        iter
+    }
+}
+
+impl<T> PersistentNode<T>
+where
+    T: Ord + Clone,
+{
+    fn new() -> Self {
+        Self {
+            keys: vec![],
+            children: vec![],
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Returns a copy of this node with `key` inserted below it.
+    fn insert(&self, key: T, order: usize) -> Insertion<T> {
+        let index = match self.keys.binary_search(&key) {
+            Ok(_) => return Insertion::Present,
+            Err(index) => index,
+        };
+        let mut node = self.clone();
+        if self.is_leaf() {
+            node.keys.insert(index, key);
+        } else {
+            match self.children[index].insert(key, order) {
+                Insertion::Present => return Insertion::Present,
+                Insertion::Replaced(child) => node.children[index] = Arc::new(child),
+                Insertion::Split(left, median, right) => {
+                    node.keys.insert(index, median);
+                    node.children[index] = Arc::new(left);
+                    node.children.insert(index + 1, Arc::new(right));
+                }
+            }
+        }
+        match node.split_overflow(order) {
+            Some((median, right)) => Insertion::Split(node, median, right),
+            None => Insertion::Replaced(node),
+        }
+    }
+
+    /// Insert a key missing from the subtree in place, copying shared children on the way.
+    /// Returns the key and the right half to add to the parent if this node was split.
+    fn insert_mut(&mut self, key: T, order: usize) -> Option<(T, PersistentNode<T>)> {
+        let index = self.keys.binary_search(&key).unwrap_err();
+        if self.is_leaf() {
+            self.keys.insert(index, key);
+        } else if let Some((median, right)) =
+            Arc::make_mut(&mut self.children[index]).insert_mut(key, order)
+        {
+            self.keys.insert(index, median);
+            self.children.insert(index + 1, Arc::new(right));
+        }
+        self.split_overflow(order)
+    }
+
+    /// Split a node which has as many keys as children are allowed, returning
+    /// the key between the halves and the right half.
+    fn split_overflow(&mut self, order: usize) -> Option<(T, PersistentNode<T>)> {
+        if self.keys.len() < order {
+            return None;
+        }
+        let split_at = self.keys.len() / 2;
+        let right = PersistentNode {
+            keys: self.keys.split_off(split_at + 1),
+            children: if self.is_leaf() {
+                vec![]
+            } else {
+                self.children.split_off(split_at + 1)
+            },
+        };
+        let median = self.keys.pop().unwrap();
+        Some((median, right))
+    }
+}
+
+/// An iterator over keys of a `PersistentBTree` in ascending order.
+pub struct Iter<'a, T> {
+    // Nodes on the path to the next key, paired with the index of the next key to yield in each.
+    stack: Vec<(&'a PersistentNode<T>, usize)>,
+}
+
+impl<'a, T> Iter<'a, T> {
+    fn push_leftmost_path(&mut self, mut node: &'a PersistentNode<T>) {
+        loop {
+            self.stack.push((node, 0));
+            match node.children.first() {
+                Some(child) => node = child,
+                None => return,
+            }
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((node, index)) = self.stack.pop() {
+            if index < node.keys.len() {
+                self.stack.push((node, index + 1));
+                // Keys in the subtree right after the key come before the next key of this node.
+                if let Some(child) = node.children.get(index + 1) {
+                    self.push_leftmost_path(child);
+                }
+                return Some(&node.keys[index]);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::persistent::{PersistentBTree, PersistentNode};
+    use rand::Rng;
+    use std::sync::Arc;
+
+    // Asserts given subtree is valid, and returns its height.
+    fn is_valid_subtree(node: &PersistentNode<u32>, order: usize, is_root: bool) -> usize {
+        assert!(node.keys.len() < order);
+        assert!(node.keys.windows(2).all(|pair| pair[0] < pair[1]));
+        if !is_root {
+            assert!(node.keys.len() >= (order - 1) / 2);
+        }
+        if node.is_leaf() {
+            return 1;
+        }
+        assert_eq!(node.keys.len() + 1, node.children.len());
+        let heights: Vec<usize> = node
+            .children
+            .iter()
+            .map(|child| is_valid_subtree(child, order, false))
+            .collect();
+        // All leaves must be at the same depth.
+        assert!(heights.iter().all(|&height| height == heights[0]));
+        heights[0] + 1
+    }
+
+    #[test]
+    fn keep_old_versions() {
+        let mut rng = rand::thread_rng();
+        let keys: Vec<u32> = (0..300).map(|_| rng.gen_range(0, 500)).collect();
+        let mut versions = vec![PersistentBTree::new(4)];
+        for &key in &keys {
+            let next = versions.last().unwrap().insert(key);
+            versions.push(next);
+        }
+        for (i, tree) in versions.iter().enumerate() {
+            let mut expected = keys[..i].to_vec();
+            expected.sort_unstable();
+            expected.dedup();
+            is_valid_subtree(&tree.root, 4, true);
+            assert_eq!(tree.iter().copied().collect::<Vec<_>>(), expected);
+            assert_eq!(tree.len(), expected.len());
+        }
+    }
+
+    #[test]
+    fn share_untouched_nodes() {
+        let tree = (0..100).fold(PersistentBTree::new(4), |tree, key| tree.insert(key * 2));
+        let next = tree.insert(1);
+        assert!(next.contains(&1));
+        assert!(!tree.contains(&1));
+        // Only the leftmost path was copied.
+        assert!(!Arc::ptr_eq(&tree.root, &next.root));
+        assert!(!Arc::ptr_eq(&tree.root.children[0], &next.root.children[0]));
+        assert!(tree.root.children[1..]
+            .iter()
+            .zip(&next.root.children[1..])
+            .all(|(old, new)| Arc::ptr_eq(old, new)));
+        assert!(Arc::ptr_eq(&tree.root, &tree.insert(2).root));
+    }
+
+    #[test]
+    fn copy_on_write() {
+        let mut tree = PersistentBTree::new(4);
+        for key in 0..100 {
+            assert!(tree.insert_mut(key * 2));
+        }
+        assert!(!tree.insert_mut(10));
+        is_valid_subtree(&tree.root, 4, true);
+
+        let mut copy = tree.clone();
+        assert!(Arc::ptr_eq(&tree.root, &copy.root));
+        assert!(copy.insert_mut(1));
+        assert!(!tree.contains(&1));
+        assert_eq!(tree.len(), 100);
+        assert!(tree.root.children[1..]
+            .iter()
+            .zip(&copy.root.children[1..])
+            .all(|(old, new)| Arc::ptr_eq(old, new)));
+        // Nodes the copy owns alone are changed in place.
+        let root = Arc::as_ptr(&copy.root);
+        assert!(copy.insert_mut(3));
+        assert_eq!(Arc::as_ptr(&copy.root), root);
+
+        for key in 0..100 {
+            copy.insert_mut(key * 2 + 1);
+        }
+        is_valid_subtree(&copy.root, 4, true);
+        assert_eq!(
+            copy.iter().copied().collect::<Vec<_>>(),
+            (0..200).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            tree.iter().copied().collect::<Vec<_>>(),
+            (0..100).map(|key| key * 2).collect::<Vec<_>>()
+        );
+    }
+}
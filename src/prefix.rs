@@ -0,0 +1,559 @@
+use crate::btree::check_order;
+use crate::error::InvalidOrder;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem;
+
+/// Index of a node in the arena of a `PrefixBTree`.
+type NodeId = usize;
+
+/// This structure represents a set of byte strings, such as URLs or paths, laid out as a B+ tree.
+/// Keys in a node often share a long prefix, so each node stores the prefix once and only
+/// the rest of each key. A search compares a key with the prefix once per node, then only
+/// with the short suffixes. Separators in internal nodes are cut down to the bytes needed
+/// to tell the leaves apart, so internal nodes stay small however long keys are.
+#[derive(Clone, Debug)]
+pub struct PrefixBTree {
+    // The maximum number of children each node can accomodates.
+    order: usize,
+    // All nodes of the tree. Nodes refer to each other by index into this vector.
+    nodes: Vec<PrefixNode>,
+    // Slots of `nodes` released by merges, reused by later splits.
+    free: Vec<NodeId>,
+    root: NodeId,
+    len: usize,
+}
+
+/// A node of a `PrefixBTree`.
+#[derive(Clone, Debug)]
+struct PrefixNode {
+    // Bytes every key of the node starts with.
+    prefix: Vec<u8>,
+    // Keys of a leaf, or separators of an internal node, without the prefix.
+    suffixes: Vec<Vec<u8>>,
+    // Child nodes. Empty for leaves.
+    children: Vec<NodeId>,
+    // The next leaf in key order. Always `None` for internal nodes.
+    next: Option<NodeId>,
+}
+
+impl PrefixBTree {
+    /// Create an empty tree.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_ORDER`. Use `try_new` to handle it as an error.
+    pub fn new(order: usize) -> Self {
+        Self::try_new(order).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Create an empty tree, failing if `order` is less than `MIN_ORDER`.
+    pub fn try_new(order: usize) -> Result<Self, InvalidOrder> {
+        check_order(order)?;
+        Ok(Self {
+            order,
+            nodes: vec![PrefixNode::new()],
+            free: vec![],
+            root: 0,
+            len: 0,
+        })
+    }
+
+    /// Returns the number of keys in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of bytes of keys and separators stored in nodes, after compression.
+    pub fn key_bytes(&self) -> usize {
+        self.nodes
+            .iter()
+            .map(|node| node.prefix.len() + node.suffixes.iter().map(Vec::len).sum::<usize>())
+            .sum()
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.nodes[self.find_leaf(key)].search(key).is_ok()
+    }
+
+    /// Insert a key, returning whether it was not present yet.
+    pub fn insert(&mut self, key: &[u8]) -> bool {
+        let inserted = self.insert_at(self.root, key);
+        if inserted {
+            self.len += 1;
+        }
+        if self.is_overflow(self.root) {
+            let mut root = PrefixNode::new();
+            root.children.push(self.root);
+            self.root = self.allocate(root);
+            self.split_children(self.root, 0);
+        }
+        inserted
+    }
+
+    /// Remove a key, returning whether it was present.
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        let removed = self.remove_at(self.root, key);
+        if removed {
+            self.len -= 1;
+        }
+        // The root has lost its last separator, so its only child becomes the new root.
+        let root = &self.nodes[self.root];
+        if !root.is_leaf() && root.len() == 0 {
+            let child = root.children[0];
+            self.release(self.root);
+            self.root = child;
+        }
+        removed
+    }
+
+    /// Returns an iterator over keys in ascending order.
+    /// Keys are stored split into prefixes and suffixes, so each of them is put back together.
+    pub fn iter(&self) -> Iter<'_> {
+        let mut leaf = self.root;
+        while !self.nodes[leaf].is_leaf() {
+            leaf = self.nodes[leaf].children[0];
+        }
+        Iter {
+            nodes: &self.nodes,
+            leaf: Some(leaf),
+            index: 0,
+        }
+    }
+
+    fn allocate(&mut self, node: PrefixNode) -> NodeId {
+        match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = node;
+                id
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+
+    fn release(&mut self, id: NodeId) {
+        self.nodes[id] = PrefixNode::new();
+        self.free.push(id);
+    }
+
+    /// Borrow two distinct nodes mutably at once.
+    fn pair_mut(&mut self, left: NodeId, right: NodeId) -> (&mut PrefixNode, &mut PrefixNode) {
+        if left < right {
+            let (head, tail) = self.nodes.split_at_mut(right);
+            (&mut head[left], &mut tail[0])
+        } else {
+            let (head, tail) = self.nodes.split_at_mut(left);
+            (&mut tail[0], &mut head[right])
+        }
+    }
+
+    fn is_overflow(&self, id: NodeId) -> bool {
+        self.nodes[id].len() == self.order
+    }
+
+    fn is_underflow(&self, id: NodeId) -> bool {
+        self.nodes[id].len() < self.min_keys()
+    }
+
+    /// The minimum number of keys a node except the root must hold.
+    fn min_keys(&self) -> usize {
+        (self.order - 1) / 2
+    }
+
+    /// Descend from the root to the leaf whose range covers a given key.
+    fn find_leaf(&self, key: &[u8]) -> NodeId {
+        let mut id = self.root;
+        while !self.nodes[id].is_leaf() {
+            let node = &self.nodes[id];
+            id = node.children[node.child_index(key)];
+        }
+        id
+    }
+
+    /// Insert a key into a subtree, returning whether it was not present yet.
+    /// Children which overflow are split, but the node `id` may be left overflowing.
+    fn insert_at(&mut self, id: NodeId, key: &[u8]) -> bool {
+        let node = &mut self.nodes[id];
+        if node.is_leaf() {
+            return match node.search(key) {
+                Ok(_) => false,
+                Err(index) => {
+                    node.insert_key(index, key);
+                    true
+                }
+            };
+        }
+        let index = node.child_index(key);
+        let child = node.children[index];
+        let inserted = self.insert_at(child, key);
+        if self.is_overflow(child) {
+            self.split_children(id, index);
+        }
+        inserted
+    }
+
+    /// Split an overflowing child into two and insert a separator between them.
+    /// A leaf is separated by the shortest key which divides the halves, while an internal node
+    /// moves its middle separator up. Both halves are compressed again, as each of them may
+    /// share a longer prefix.
+    fn split_children(&mut self, id: NodeId, index: usize) {
+        let child_id = self.nodes[id].children[index];
+        let split_at = self.order / 2;
+        let child = &mut self.nodes[child_id];
+        let is_leaf = child.is_leaf();
+        let mut keys = child.take_keys();
+        let mut right_child = PrefixNode::new();
+        let separator = if is_leaf {
+            right_child.set_keys(keys.split_off(split_at));
+            right_child.next = child.next;
+            shortest_separator(keys.last().unwrap(), &right_child.key(0))
+        } else {
+            right_child.set_keys(keys.split_off(split_at + 1));
+            right_child.children = child.children.split_off(split_at + 1);
+            keys.pop().unwrap()
+        };
+        child.set_keys(keys);
+        let right_id = self.allocate(right_child);
+        if is_leaf {
+            self.nodes[child_id].next = Some(right_id);
+        }
+        let node = &mut self.nodes[id];
+        node.insert_key(index, &separator);
+        node.children.insert(index + 1, right_id);
+    }
+
+    /// Remove a key from a subtree, returning whether it was present.
+    /// Children which underflow are refilled, but the node `id` may be left underflowing.
+    fn remove_at(&mut self, id: NodeId, key: &[u8]) -> bool {
+        let node = &mut self.nodes[id];
+        if node.is_leaf() {
+            return match node.search(key) {
+                Ok(index) => {
+                    node.remove_key(index);
+                    true
+                }
+                Err(_) => false,
+            };
+        }
+        let index = node.child_index(key);
+        let child = node.children[index];
+        let removed = self.remove_at(child, key);
+        if self.is_underflow(child) {
+            self.rebalance_children(id, index);
+        }
+        removed
+    }
+
+    /// Refill an underflowing child by borrowing a key from a sibling, or merge it into
+    /// a sibling if neither of them has a key to spare.
+    fn rebalance_children(&mut self, id: NodeId, index: usize) {
+        let min_keys = self.min_keys();
+        let children = &self.nodes[id].children;
+        let has_spare = |sibling: NodeId| self.nodes[sibling].len() > min_keys;
+        if index > 0 && has_spare(children[index - 1]) {
+            self.borrow_from_left(id, index);
+        } else if index + 1 < children.len() && has_spare(children[index + 1]) {
+            self.borrow_from_right(id, index);
+        } else if index > 0 {
+            self.merge_children(id, index - 1);
+        } else {
+            self.merge_children(id, index);
+        }
+    }
+
+    fn borrow_from_left(&mut self, id: NodeId, index: usize) {
+        let left_id = self.nodes[id].children[index - 1];
+        let child_id = self.nodes[id].children[index];
+        let (left, child) = self.pair_mut(left_id, child_id);
+        let key = left.remove_key(left.len() - 1);
+        if child.is_leaf() {
+            child.insert_key(0, &key);
+            let separator = shortest_separator(&left.key(left.len() - 1), &key);
+            self.nodes[id].replace_key(index - 1, &separator);
+        } else {
+            child.children.insert(0, left.children.pop().unwrap());
+            let separator = self.nodes[id].replace_key(index - 1, &key);
+            self.nodes[child_id].insert_key(0, &separator);
+        }
+    }
+
+    fn borrow_from_right(&mut self, id: NodeId, index: usize) {
+        let child_id = self.nodes[id].children[index];
+        let right_id = self.nodes[id].children[index + 1];
+        let (child, right) = self.pair_mut(child_id, right_id);
+        let key = right.remove_key(0);
+        if child.is_leaf() {
+            child.insert_key(child.len(), &key);
+            let separator = shortest_separator(&key, &right.key(0));
+            self.nodes[id].replace_key(index, &separator);
+        } else {
+            child.children.push(right.children.remove(0));
+            let separator = self.nodes[id].replace_key(index, &key);
+            let child = &mut self.nodes[child_id];
+            child.insert_key(child.len(), &separator);
+        }
+    }
+
+    /// Merge `children[index + 1]` of a node into `children[index]`.
+    fn merge_children(&mut self, id: NodeId, index: usize) {
+        let node = &mut self.nodes[id];
+        let right_id = node.children.remove(index + 1);
+        let separator = node.remove_key(index);
+        let left_id = node.children[index];
+        let mut right = mem::replace(&mut self.nodes[right_id], PrefixNode::new());
+        let left = &mut self.nodes[left_id];
+        let mut keys = left.take_keys();
+        if left.is_leaf() {
+            left.next = right.next;
+        } else {
+            keys.push(separator);
+        }
+        keys.extend(right.take_keys());
+        left.set_keys(keys);
+        left.children.extend(right.children);
+        self.release(right_id);
+    }
+}
+
+impl PrefixNode {
+    fn new() -> Self {
+        Self {
+            prefix: vec![],
+            suffixes: vec![],
+            children: vec![],
+            next: None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.suffixes.len()
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Put the key at `index` back together from the prefix and its suffix.
+    fn key(&self, index: usize) -> Vec<u8> {
+        let mut key = self.prefix.clone();
+        key.extend_from_slice(&self.suffixes[index]);
+        key
+    }
+
+    /// Search the node for a given key, in the same way as `slice::binary_search`.
+    fn search(&self, key: &[u8]) -> Result<usize, usize> {
+        let shared = common_prefix_len(&self.prefix, key);
+        if shared < self.prefix.len() {
+            // The key does not start with the prefix, so it orders before or after every key.
+            return match key.get(shared) {
+                Some(&byte) if byte > self.prefix[shared] => Err(self.len()),
+                _ => Err(0),
+            };
+        }
+        let rest = &key[shared..];
+        self.suffixes
+            .binary_search_by(|suffix| suffix.as_slice().cmp(rest))
+    }
+
+    /// Index of the child whose subtree may contain `key`.
+    fn child_index(&self, key: &[u8]) -> usize {
+        match self.search(key) {
+            Ok(index) => index + 1,
+            Err(index) => index,
+        }
+    }
+
+    /// Insert a key at `index`, shortening the prefix if the key does not start with it.
+    fn insert_key(&mut self, index: usize, key: &[u8]) {
+        let shared = common_prefix_len(&self.prefix, key);
+        if shared < self.prefix.len() {
+            let cut = self.prefix.split_off(shared);
+            for suffix in &mut self.suffixes {
+                suffix.splice(0..0, cut.iter().copied());
+            }
+        }
+        self.suffixes.insert(index, key[shared..].to_vec());
+    }
+
+    /// Remove the key at `index`, lengthening the prefix if the remaining keys share more bytes.
+    fn remove_key(&mut self, index: usize) -> Vec<u8> {
+        let key = self.key(index);
+        self.suffixes.remove(index);
+        self.extend_prefix();
+        key
+    }
+
+    /// Replace the key at `index` with another one which orders at the same position.
+    fn replace_key(&mut self, index: usize, key: &[u8]) -> Vec<u8> {
+        let old_key = self.remove_key(index);
+        self.insert_key(index, key);
+        old_key
+    }
+
+    /// Take all keys out of the node, leaving it without keys.
+    fn take_keys(&mut self) -> Vec<Vec<u8>> {
+        let prefix = mem::take(&mut self.prefix);
+        mem::take(&mut self.suffixes)
+            .into_iter()
+            .map(|suffix| [prefix.as_slice(), &suffix].concat())
+            .collect()
+    }
+
+    /// Replace the keys of the node with sorted ones, storing the prefix they all share once.
+    fn set_keys(&mut self, keys: Vec<Vec<u8>>) {
+        self.prefix.clear();
+        self.suffixes = keys;
+        self.extend_prefix();
+    }
+
+    /// Move the bytes all suffixes share into the prefix.
+    /// Suffixes are sorted, so they share what the first and the last one share.
+    fn extend_prefix(&mut self) {
+        let shared = match (self.suffixes.first(), self.suffixes.last()) {
+            (Some(first), Some(last)) => common_prefix_len(first, last),
+            _ => return,
+        };
+        if shared == 0 {
+            return;
+        }
+        self.prefix.extend_from_slice(&self.suffixes[0][..shared]);
+        for suffix in &mut self.suffixes {
+            suffix.drain(..shared);
+        }
+    }
+}
+
+/// Returns the shortest key which is greater than `left` and not greater than `right`,
+/// so that internal nodes only hold the bytes needed to tell two leaves apart.
+/// `left` must be less than `right`.
+fn shortest_separator(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let shared = common_prefix_len(left, right);
+    right[..shared + 1].to_vec()
+}
+
+/// Returns the number of leading bytes two slices share.
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+/// An iterator over keys of a `PrefixBTree` in ascending order.
+pub struct Iter<'a> {
+    nodes: &'a [PrefixNode],
+    // The leaf and the index in it of the next key to yield.
+    leaf: Option<NodeId>,
+    index: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let node = &self.nodes[self.leaf?];
+            if self.index < node.len() {
+                self.index += 1;
+                return Some(node.key(self.index - 1));
+            }
+            self.leaf = node.next;
+            self.index = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prefix::PrefixBTree;
+    use rand::Rng;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn reject_small_orders() {
+        for order in 0..3 {
+            assert!(PrefixBTree::try_new(order).is_err());
+        }
+        assert!(PrefixBTree::try_new(3).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "order of a B-Tree must be at least 3")]
+    fn create_with_order_one() {
+        PrefixBTree::new(1);
+    }
+
+    fn url(i: u32) -> Vec<u8> {
+        format!("https://example.com/items/{}/detail", i).into_bytes()
+    }
+
+    #[test]
+    fn compress_shared_prefixes() {
+        let mut tree = PrefixBTree::new(16);
+        let mut total = 0;
+        for i in 0..1_000 {
+            assert!(tree.insert(&url(i)));
+            total += url(i).len();
+        }
+        assert!(!tree.insert(&url(10)));
+        assert_eq!(tree.len(), 1_000);
+        assert!(tree.contains(&url(999)));
+        assert!(!tree.contains(&url(1_000)));
+        assert!(!tree.contains(b"https://example.com/"));
+        assert!(!tree.contains(b"zzz"));
+        // Keys are about 36 bytes long, and only the number and what follows it are stored per key.
+        assert!(tree.key_bytes() < total / 2);
+    }
+
+    #[test]
+    fn truncate_separators() {
+        let mut tree = PrefixBTree::new(8);
+        let tail = "/".repeat(100);
+        for i in 0..2_000 {
+            assert!(tree.insert(format!("{:04}{}", i, tail).as_bytes()));
+        }
+        for i in (0..2_000).step_by(3) {
+            assert!(tree.remove(format!("{:04}{}", i, tail).as_bytes()));
+        }
+        // Four digits tell any two keys apart, however long the rest of the key is.
+        for node in tree.nodes.iter().filter(|node| !node.is_leaf()) {
+            for index in 0..node.len() {
+                assert!(node.key(index).len() <= 4);
+            }
+        }
+        assert_eq!(tree.iter().count(), tree.len());
+    }
+
+    #[test]
+    fn differential_against_std() {
+        let mut rng = rand::thread_rng();
+        for order in 3..8 {
+            let mut tree = PrefixBTree::new(order);
+            let mut expected = BTreeSet::new();
+            for _ in 0..3_000 {
+                // Keys of varying length, many of which are prefixes of each other.
+                let key = url(rng.gen_range(0, 500));
+                let key = key[..rng.gen_range(20, key.len() + 1)].to_vec();
+                if rng.gen_bool(0.6) {
+                    assert_eq!(tree.insert(&key), expected.insert(key));
+                } else {
+                    assert_eq!(tree.remove(&key), expected.remove(&key));
+                }
+                assert_eq!(tree.len(), expected.len());
+            }
+            assert!(tree.iter().eq(expected.iter().cloned()));
+            for key in &expected {
+                assert!(tree.contains(key));
+            }
+            for key in expected {
+                assert!(tree.remove(&key));
+            }
+            assert!(tree.is_empty());
+            assert_eq!(tree.iter().next(), None);
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::OnceLock;
+
+const FIRST_SEGMENT: usize = 64;
+const SEGMENT_COUNT: usize = 40;
+
+/// Storage of slots whose addresses never change, so that threads can follow an id to a slot
+/// without a latch. Segment `i` holds `FIRST_SEGMENT << i` slots, allocated when first used.
+#[derive(Debug)]
+pub(crate) struct Segments<E> {
+    segments: [OnceLock<Box<[E]>>; SEGMENT_COUNT],
+    next: AtomicUsize,
+}
+
+impl<E: Default> Segments<E> {
+    pub(crate) fn new() -> Self {
+        Self {
+            segments: std::array::from_fn(|_| OnceLock::new()),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the segment holding a slot, and the position of the slot in it.
+    fn locate(id: usize) -> (usize, usize) {
+        let segment = (id / FIRST_SEGMENT + 1).ilog2() as usize;
+        (segment, id - FIRST_SEGMENT * ((1 << segment) - 1))
+    }
+
+    /// Returns the id of a slot no other caller got.
+    pub(crate) fn allocate(&self) -> usize {
+        self.next.fetch_add(1, atomic::Ordering::SeqCst)
+    }
+
+    /// Returns the number of slots allocated so far.
+    #[cfg(feature = "bw-tree")]
+    pub(crate) fn len(&self) -> usize {
+        self.next.load(atomic::Ordering::SeqCst)
+    }
+
+    pub(crate) fn get(&self, id: usize) -> &E {
+        let (segment, offset) = Self::locate(id);
+        let slots = self.segments[segment].get_or_init(|| {
+            (0..FIRST_SEGMENT << segment)
+                .map(|_| E::default())
+                .collect()
+        });
+        &slots[offset]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::segments::{Segments, FIRST_SEGMENT};
+
+    #[test]
+    fn locate_slots() {
+        type Slots = Segments<u8>;
+        assert_eq!(Slots::locate(0), (0, 0));
+        assert_eq!(Slots::locate(FIRST_SEGMENT - 1), (0, FIRST_SEGMENT - 1));
+        assert_eq!(Slots::locate(FIRST_SEGMENT), (1, 0));
+        assert_eq!(Slots::locate(FIRST_SEGMENT * 3), (2, 0));
+        assert_eq!(
+            Slots::locate(FIRST_SEGMENT * 7 - 1),
+            (2, FIRST_SEGMENT * 4 - 1)
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use crate::btree::BTree;
+use crate::compare::OrdComparator;
+use alloc::sync::Arc;
+use core::ops::Deref;
+
+/// A read-only handle to a frozen `BTree`, which can be cloned cheaply and sent to other threads.
+/// All handles share one tree, so lookups from many threads run concurrently without locking.
+/// Every `&self` method of `BTree` is available through `Deref`.
+#[derive(Debug)]
+pub struct SharedBTree<T, C = OrdComparator> {
+    tree: Arc<BTree<T, C>>,
+}
+
+impl<T, C> SharedBTree<T, C> {
+    pub fn new(tree: BTree<T, C>) -> Self {
+        Self {
+            tree: Arc::new(tree),
+        }
+    }
+
+    /// Returns the tree if this is its only handle, or this handle otherwise.
+    pub fn try_unwrap(self) -> Result<BTree<T, C>, Self> {
+        Arc::try_unwrap(self.tree).map_err(|tree| Self { tree })
+    }
+}
+
+impl<T, C> Clone for SharedBTree<T, C> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: Arc::clone(&self.tree),
+        }
+    }
+}
+
+impl<T, C> Deref for SharedBTree<T, C> {
+    type Target = BTree<T, C>;
+
+    fn deref(&self) -> &BTree<T, C> {
+        &self.tree
+    }
+}
+
+impl<T, C> From<BTree<T, C>> for SharedBTree<T, C> {
+    fn from(tree: BTree<T, C>) -> Self {
+        Self::new(tree)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::array_btree::ArrayBTree;
+    use crate::bplus_tree::BPlusTree;
+    use crate::btree::BTree;
+    use crate::multimap::BTreeMultiMap;
+    use crate::shared::SharedBTree;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn trees_are_send_and_sync() {
+        assert_send_sync::<BTree<String>>();
+        assert_send_sync::<BTree<u32, fn(&u32, &u32) -> std::cmp::Ordering>>();
+        assert_send_sync::<BPlusTree<String, Vec<u8>>>();
+        assert_send_sync::<BTreeMultiMap<u32, String>>();
+        assert_send_sync::<ArrayBTree<String, 4>>();
+        assert_send_sync::<SharedBTree<String>>();
+    }
+
+    #[test]
+    fn lookup_from_many_threads() {
+        let tree = SharedBTree::new(BTree::from_sorted_iter(0..10_000u32, 8));
+        let handles: Vec<_> = (0..4)
+            .map(|thread_index| {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    (0..10_000u32)
+                        .filter(|key| key % 4 == thread_index)
+                        .all(|key| tree.get(&key) == Some(&key))
+                })
+            })
+            .collect();
+        assert!(handles.into_iter().all(|handle| handle.join().unwrap()));
+        assert_eq!(tree.len(), 10_000);
+        let tree = tree.try_unwrap().unwrap();
+        assert_eq!(tree.select(42), Some(&42));
+    }
+}
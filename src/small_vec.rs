@@ -0,0 +1,357 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::iter::FromIterator;
+use core::mem::{self, ManuallyDrop, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::{fmt, ptr, slice};
+
+/// A vector which keeps up to `N` items inline, and moves them to the heap once it grows beyond.
+/// Items move back inline only on `shrink_to_fit`, so a vector which has spilled keeps its allocation.
+pub(crate) enum SmallVec<T, const N: usize> {
+    // The first `len` items are initialized.
+    Inline {
+        len: usize,
+        items: [MaybeUninit<T>; N],
+    },
+    Heap(Vec<T>),
+}
+
+impl<T, const N: usize> SmallVec<T, N> {
+    pub(crate) fn new() -> Self {
+        Self::Inline {
+            len: 0,
+            items: [const { MaybeUninit::uninit() }; N],
+        }
+    }
+
+    pub(crate) fn push(&mut self, item: T) {
+        let len = self.len();
+        self.insert(len, item);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<T> {
+        match self {
+            Self::Inline { len, items } => {
+                if *len == 0 {
+                    return None;
+                }
+                *len -= 1;
+                // SAFETY: The last item is initialized, and it is no longer counted by `len`.
+                Some(unsafe { items[*len].assume_init_read() })
+            }
+            Self::Heap(vec) => vec.pop(),
+        }
+    }
+
+    /// Insert an item at `index`, shifting all items after it to the right.
+    pub(crate) fn insert(&mut self, index: usize, item: T) {
+        assert!(index <= self.len(), "insertion index out of bounds");
+        if self.len() == N {
+            self.spill();
+        }
+        match self {
+            Self::Inline { len, items } => {
+                // SAFETY: `index <= len < N`, so both the shifted items and the new slot lie in `items`.
+                unsafe {
+                    let slot = items.as_mut_ptr().cast::<T>().add(index);
+                    ptr::copy(slot, slot.add(1), *len - index);
+                    slot.write(item);
+                }
+                *len += 1;
+            }
+            Self::Heap(vec) => vec.insert(index, item),
+        }
+    }
+
+    /// Remove the item at `index`, shifting all items after it to the left.
+    pub(crate) fn remove(&mut self, index: usize) -> T {
+        match self {
+            Self::Inline { len, items } => {
+                assert!(index < *len, "removal index out of bounds");
+                // SAFETY: `index < len`, so the item is initialized, and the items after it
+                // are moved over its slot before `len` stops counting the last one.
+                unsafe {
+                    let slot = items.as_mut_ptr().cast::<T>().add(index);
+                    let item = slot.read();
+                    ptr::copy(slot.add(1), slot, *len - index - 1);
+                    *len -= 1;
+                    item
+                }
+            }
+            Self::Heap(vec) => vec.remove(index),
+        }
+    }
+
+    /// Split the vector at `at`, returning the items from it onwards.
+    pub(crate) fn split_off(&mut self, at: usize) -> Self {
+        match self {
+            Self::Inline { len, items } => {
+                assert!(at <= *len, "split index out of bounds");
+                let end = mem::replace(len, at);
+                let mut tail = Self::new();
+                for item in &items[at..end] {
+                    // SAFETY: Items in `at..end` are initialized, and no longer counted by `len`.
+                    tail.push(unsafe { item.assume_init_read() });
+                }
+                tail
+            }
+            Self::Heap(vec) => vec.split_off(at).into(),
+        }
+    }
+
+    /// Returns the number of items the vector can hold without allocating.
+    pub(crate) fn capacity(&self) -> usize {
+        match self {
+            Self::Inline { .. } => N,
+            Self::Heap(vec) => vec.capacity(),
+        }
+    }
+
+    /// Move the items back inline if they fit, or free the spare capacity of the heap otherwise.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        if let Self::Heap(vec) = self {
+            if vec.len() <= N {
+                *self = mem::take(vec).into_iter().collect();
+            } else {
+                vec.shrink_to_fit();
+            }
+        }
+    }
+
+    pub(crate) fn is_spilled(&self) -> bool {
+        matches!(self, Self::Heap(_))
+    }
+
+    /// Move the items to the heap, leaving room for as many again.
+    fn spill(&mut self) {
+        if let Self::Inline { len, items } = self {
+            let len = mem::replace(len, 0);
+            let mut vec = Vec::with_capacity(len * 2);
+            for item in &items[..len] {
+                // SAFETY: The first `len` items are initialized, and `len` was reset so that
+                // they are not dropped again.
+                vec.push(unsafe { item.assume_init_read() });
+            }
+            *self = Self::Heap(vec);
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for SmallVec<T, N> {
+    fn drop(&mut self) {
+        if let Self::Inline { len, items } = self {
+            // SAFETY: The first `len` items are initialized and dropped only here.
+            unsafe {
+                ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+                    items.as_mut_ptr().cast::<T>(),
+                    *len,
+                ));
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> Deref for SmallVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            // SAFETY: The first `len` items are initialized.
+            Self::Inline { len, items } => unsafe {
+                slice::from_raw_parts(items.as_ptr().cast::<T>(), *len)
+            },
+            Self::Heap(vec) => vec,
+        }
+    }
+}
+
+impl<T, const N: usize> DerefMut for SmallVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match self {
+            // SAFETY: The first `len` items are initialized.
+            Self::Inline { len, items } => unsafe {
+                slice::from_raw_parts_mut(items.as_mut_ptr().cast::<T>(), *len)
+            },
+            Self::Heap(vec) => vec,
+        }
+    }
+}
+
+impl<T, const N: usize> Default for SmallVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone, const N: usize> Clone for SmallVec<T, N> {
+    fn clone(&self) -> Self {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for SmallVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq, const N: usize> PartialEq for SmallVec<T, N> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Eq, const N: usize> Eq for SmallVec<T, N> {}
+
+impl<T, const N: usize> From<Vec<T>> for SmallVec<T, N> {
+    fn from(vec: Vec<T>) -> Self {
+        if vec.len() <= N {
+            vec.into_iter().collect()
+        } else {
+            Self::Heap(vec)
+        }
+    }
+}
+
+impl<T, const N: usize> Extend<T> for SmallVec<T, N> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.push(item);
+        }
+    }
+}
+
+impl<T, const N: usize> FromIterator<T> for SmallVec<T, N> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut small_vec = Self::new();
+        small_vec.extend(iter);
+        small_vec
+    }
+}
+
+impl<T, const N: usize> IntoIterator for SmallVec<T, N> {
+    type Item = T;
+    type IntoIter = IntoIter<T, N>;
+
+    fn into_iter(self) -> IntoIter<T, N> {
+        let small_vec = ManuallyDrop::new(self);
+        // SAFETY: `small_vec` is never dropped, so its items are moved into the iterator exactly once.
+        unsafe {
+            match &*small_vec {
+                Self::Inline { len, items } => IntoIter::Inline {
+                    next: 0,
+                    end: *len,
+                    items: ptr::read(items),
+                },
+                Self::Heap(vec) => IntoIter::Heap(ptr::read(vec).into_iter()),
+            }
+        }
+    }
+}
+
+/// An iterator moving items out of a `SmallVec`.
+pub(crate) enum IntoIter<T, const N: usize> {
+    // Items in `next..end` are initialized and not yielded yet.
+    Inline {
+        next: usize,
+        end: usize,
+        items: [MaybeUninit<T>; N],
+    },
+    Heap(vec::IntoIter<T>),
+}
+
+impl<T, const N: usize> Iterator for IntoIter<T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self {
+            Self::Inline { next, end, items } => {
+                if next == end {
+                    return None;
+                }
+                *next += 1;
+                // SAFETY: The item is initialized, and `next` has moved past it.
+                Some(unsafe { items[*next - 1].assume_init_read() })
+            }
+            Self::Heap(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::Inline { next, end, .. } => (end - next, Some(end - next)),
+            Self::Heap(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for IntoIter<T, N> {
+    fn drop(&mut self) {
+        if let Self::Inline { next, end, items } = self {
+            // SAFETY: Items in `next..end` are initialized and dropped only here.
+            unsafe {
+                ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+                    items.as_mut_ptr().cast::<T>().add(*next),
+                    *end - *next,
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::small_vec::SmallVec;
+    use std::rc::Rc;
+
+    #[test]
+    fn spill_to_heap() {
+        let mut small_vec: SmallVec<u32, 4> = SmallVec::new();
+        for i in 0..4 {
+            small_vec.push(i);
+        }
+        assert!(matches!(small_vec, SmallVec::Inline { .. }));
+        small_vec.insert(1, 10);
+        assert!(matches!(small_vec, SmallVec::Heap(_)));
+        assert_eq!(small_vec[..], [0, 10, 1, 2, 3]);
+        assert_eq!(small_vec.remove(1), 10);
+        assert_eq!(small_vec.pop(), Some(3));
+        assert_eq!(small_vec[..], [0, 1, 2]);
+        small_vec.shrink_to_fit();
+        assert!(matches!(small_vec, SmallVec::Inline { .. }));
+        assert_eq!(small_vec[..], [0, 1, 2]);
+    }
+
+    #[test]
+    fn insert_remove_and_split_inline() {
+        let mut small_vec: SmallVec<u32, 8> = vec![1, 3, 5].into();
+        small_vec.insert(1, 2);
+        small_vec.insert(3, 4);
+        small_vec.insert(0, 0);
+        assert_eq!(small_vec[..], [0, 1, 2, 3, 4, 5]);
+        assert_eq!(small_vec.remove(0), 0);
+        let tail = small_vec.split_off(2);
+        assert_eq!(small_vec[..], [1, 2]);
+        assert_eq!(tail[..], [3, 4, 5]);
+        assert!(matches!(tail, SmallVec::Inline { .. }));
+        assert_eq!(tail.into_iter().collect::<Vec<_>>(), vec![3, 4, 5]);
+    }
+
+    #[test]
+    fn drop_every_item_once() {
+        let item = Rc::new(());
+        for &count in &[3, 6] {
+            let mut small_vec: SmallVec<Rc<()>, 4> = SmallVec::new();
+            small_vec.extend((0..count).map(|_| item.clone()));
+            let tail = small_vec.split_off(1);
+            let mut iter = tail.into_iter();
+            iter.next();
+            assert_eq!(Rc::strong_count(&item), count);
+            drop(iter);
+            drop(small_vec.clone());
+            assert_eq!(Rc::strong_count(&item), 2);
+            drop(small_vec);
+            assert_eq!(Rc::strong_count(&item), 1);
+        }
+    }
+}
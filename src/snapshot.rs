@@ -0,0 +1,526 @@
+use crate::bplus_tree::BPlusTree;
+use crate::btree::{check_order, BTree};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{self, AtomicUsize};
+
+/// Bytes a snapshot file starts with, followed by the version of its format.
+const MAGIC: &[u8; 4] = b"RBTS";
+/// Bytes a delta file starts with, followed by the version of its format.
+const DELTA_MAGIC: &[u8; 4] = b"RBTD";
+const VERSION: u8 = 1;
+
+/// A key which can be written to a snapshot and read back.
+pub trait Codec: Sized {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()>;
+    fn decode(reader: &mut impl Read) -> io::Result<Self>;
+}
+
+macro_rules! impl_codec_for_int {
+    ($($int:ty),*) => {
+        $(
+            impl Codec for $int {
+                fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+                    writer.write_all(&self.to_le_bytes())
+                }
+
+                fn decode(reader: &mut impl Read) -> io::Result<Self> {
+                    let mut bytes = [0; core::mem::size_of::<$int>()];
+                    reader.read_exact(&mut bytes)?;
+                    Ok(<$int>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+impl_codec_for_int!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+// Pointer-sized integers are written as 64 bits, so snapshots move between platforms.
+impl Codec for usize {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+        (*self as u64).encode(writer)
+    }
+
+    fn decode(reader: &mut impl Read) -> io::Result<Self> {
+        usize::try_from(u64::decode(reader)?).map_err(invalid_data)
+    }
+}
+
+impl Codec for isize {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+        (*self as i64).encode(writer)
+    }
+
+    fn decode(reader: &mut impl Read) -> io::Result<Self> {
+        isize::try_from(i64::decode(reader)?).map_err(invalid_data)
+    }
+}
+
+impl Codec for bool {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+        u8::from(*self).encode(writer)
+    }
+
+    fn decode(reader: &mut impl Read) -> io::Result<Self> {
+        match u8::decode(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            byte => Err(invalid_data(format!("{} is not a boolean", byte))),
+        }
+    }
+}
+
+impl Codec for char {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+        u32::from(*self).encode(writer)
+    }
+
+    fn decode(reader: &mut impl Read) -> io::Result<Self> {
+        let code = u32::decode(reader)?;
+        char::from_u32(code).ok_or_else(|| invalid_data(format!("{:#x} is not a char", code)))
+    }
+}
+
+impl Codec for Vec<u8> {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.len().encode(writer)?;
+        writer.write_all(self)
+    }
+
+    fn decode(reader: &mut impl Read) -> io::Result<Self> {
+        let len = u64::decode(reader)?;
+        // Read through `take`, so a corrupted length does not allocate a huge buffer up front.
+        let mut bytes = vec![];
+        reader.take(len).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(bytes)
+    }
+}
+
+impl Codec for String {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.len().encode(writer)?;
+        writer.write_all(self.as_bytes())
+    }
+
+    fn decode(reader: &mut impl Read) -> io::Result<Self> {
+        String::from_utf8(Vec::decode(reader)?).map_err(invalid_data)
+    }
+}
+
+impl<A: Codec, B: Codec> Codec for (A, B) {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.0.encode(writer)?;
+        self.1.encode(writer)
+    }
+
+    fn decode(reader: &mut impl Read) -> io::Result<Self> {
+        Ok((A::decode(reader)?, B::decode(reader)?))
+    }
+}
+
+impl<T> BTree<T>
+where
+    T: Ord + Clone + Codec,
+{
+    /// Write the order and the keys of the tree to a file.
+    /// The file is replaced atomically, so a crash leaves either the old or the new snapshot.
+    /// `load` reads unique keys only, so a tree holding equal keys is rejected as invalid input.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        write_atomically(path.as_ref(), |writer| {
+            writer.write_all(MAGIC)?;
+            VERSION.encode(writer)?;
+            self.order().encode(writer)?;
+            self.len().encode(writer)?;
+            let mut last = None;
+            for key in self.iter() {
+                if last == Some(key) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "a tree with equal keys cannot be saved",
+                    ));
+                }
+                key.encode(writer)?;
+                last = Some(key);
+            }
+            Ok(())
+        })
+    }
+
+    /// Read a tree written by `save`, with the order it was saved with.
+    /// A file which is not a snapshot, or whose keys are out of order, is rejected as invalid data.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        read_header(&mut reader, MAGIC)?;
+        let order = usize::decode(&mut reader)?;
+        check_order(order).map_err(invalid_data)?;
+        let len = usize::decode(&mut reader)?;
+        let keys = read_sorted_keys(&mut reader, len)?;
+        if reader.read(&mut [0])? != 0 {
+            return Err(invalid_data("trailing bytes after the last key"));
+        }
+        Ok(Self::from_sorted_iter(keys, order))
+    }
+
+    /// Apply changes written by `TrackedBTree::save_delta` to the tree.
+    /// Deltas must be applied in the order they were written, on top of the snapshot they follow.
+    /// Inserting a key the tree has already is invalid data under `DuplicatePolicy::Error`,
+    /// and the changes before it stay applied.
+    pub fn apply_delta(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut reader = BufReader::new(File::open(path)?);
+        read_header(&mut reader, DELTA_MAGIC)?;
+        let len = usize::decode(&mut reader)?;
+        let changes: Vec<(T, bool)> = read_sorted_keys(&mut reader, len)?;
+        if reader.read(&mut [0])? != 0 {
+            return Err(invalid_data("trailing bytes after the last change"));
+        }
+        for (key, is_present) in changes {
+            if is_present {
+                self.try_insert(key)
+                    .map_err(|_| invalid_data("an inserted key is present already"))?;
+            } else {
+                self.remove(&key);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A tree which records the keys inserted and removed since its last snapshot,
+/// so that `save_delta` writes only those instead of every key.
+#[derive(Clone, Debug)]
+pub struct TrackedBTree<T> {
+    tree: BTree<T>,
+    // Keys changed since the last snapshot, mapped to whether they are in the tree now.
+    // A key changed back to how the snapshot had it is dropped.
+    changes: BPlusTree<T, bool>,
+}
+
+impl<T> TrackedBTree<T>
+where
+    T: Ord + Clone + Codec,
+{
+    /// Start tracking changes to a tree, which is taken to be saved already.
+    pub fn new(tree: BTree<T>) -> Self {
+        let order = tree.order();
+        Self {
+            tree,
+            changes: BPlusTree::new(order),
+        }
+    }
+
+    pub fn tree(&self) -> &BTree<T> {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> BTree<T> {
+        self.tree
+    }
+
+    /// Returns the number of keys changed since the last snapshot.
+    pub fn changes(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Insert a key, returning whether it was not present yet.
+    pub fn insert(&mut self, key: T) -> bool {
+        if self.tree.get(&key).is_some() {
+            return false;
+        }
+        self.record(&key, true);
+        self.tree.insert(key);
+        true
+    }
+
+    /// Remove a key, returning whether it was present.
+    pub fn remove(&mut self, key: &T) -> bool {
+        if !self.tree.remove(key) {
+            return false;
+        }
+        self.record(key, false);
+        true
+    }
+
+    /// Record a change to a key, or cancel the opposite change made since the last snapshot.
+    fn record(&mut self, key: &T, is_present: bool) {
+        if self.changes.remove(key).is_none() {
+            self.changes.insert(key.clone(), is_present);
+        }
+    }
+
+    /// Write every key of the tree like `BTree::save`, and start recording changes afresh.
+    pub fn save(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.tree.save(path)?;
+        self.clear_changes();
+        Ok(())
+    }
+
+    /// Write only the keys changed since the last snapshot or delta, and start recording
+    /// changes afresh. `BTree::apply_delta` brings a tree loaded from the last snapshot up to date.
+    pub fn save_delta(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        write_atomically(path.as_ref(), |writer| {
+            writer.write_all(DELTA_MAGIC)?;
+            VERSION.encode(writer)?;
+            self.changes.len().encode(writer)?;
+            self.changes.iter().try_for_each(|(key, &is_present)| {
+                key.encode(writer)?;
+                is_present.encode(writer)
+            })
+        })?;
+        self.clear_changes();
+        Ok(())
+    }
+
+    fn clear_changes(&mut self) {
+        self.changes = BPlusTree::new(self.tree.order());
+    }
+}
+
+/// Check a file starts with `magic` and the current version of the format.
+fn read_header(reader: &mut impl Read, magic: &[u8; 4]) -> io::Result<()> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    if &bytes != magic {
+        return Err(invalid_data("not a file written by a B-Tree"));
+    }
+    let version = u8::decode(reader)?;
+    if version != VERSION {
+        return Err(invalid_data(format!("unknown format version {}", version)));
+    }
+    Ok(())
+}
+
+/// Read `len` keys, checking they are in strictly ascending order.
+fn read_sorted_keys<T: Ord + Codec>(reader: &mut impl Read, len: usize) -> io::Result<Vec<T>> {
+    let mut keys: Vec<T> = vec![];
+    for _ in 0..len {
+        let key = T::decode(reader)?;
+        if keys.last().is_some_and(|last| *last >= key) {
+            return Err(invalid_data("keys are not in ascending order"));
+        }
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+/// Write a file through a temporary file next to it, which is then renamed over `path`.
+/// Temporary files are named after the process and a counter, so concurrent writes to the same
+/// path do not write to the same temporary file, and the last rename wins. The temporary file
+/// is removed if writing fails, and the directory is flushed after the rename.
+pub fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(
+        ".{}.{}.tmp",
+        process::id(),
+        WRITES.fetch_add(1, atomic::Ordering::Relaxed)
+    ));
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    let written = write(&mut writer).and_then(|()| {
+        let file = writer.into_inner().map_err(|error| error.into_error())?;
+        file.sync_all()
+    });
+    if let Err(error) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(error);
+    }
+    fs::rename(&temp_path, path)?;
+    sync_parent(path)
+}
+
+/// Flush the directory holding `path`, so that a rename into it survives a crash.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+// Other platforms cannot open a directory to flush it.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::btree::{BTree, DuplicatePolicy};
+    use crate::snapshot::{Codec, TrackedBTree};
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rusty_btree_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = temp_path("save_and_load");
+        let tree = BTree::from_sorted_iter((0..1_000u64).map(|key| key * 3), 5);
+        tree.save(&path).unwrap();
+        let loaded = BTree::<u64>::load(&path).unwrap();
+        assert_eq!(loaded, tree);
+        assert_eq!(loaded.order(), 5);
+
+        let mut tree = BTree::new(4);
+        for key in ["b", "", "ünïcödé", "a"] {
+            tree.insert((key.to_string(), key.len() as i32));
+        }
+        tree.save(&path).unwrap();
+        assert_eq!(BTree::<(String, i32)>::load(&path).unwrap(), tree);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn save_concurrently() {
+        let path = temp_path("save_concurrently");
+        let trees: Vec<_> = (0..4u32)
+            .map(|round| BTree::from_sorted_iter(round * 1_000..(round + 1) * 1_000, 4))
+            .collect();
+        let path = &path;
+        std::thread::scope(|scope| {
+            for tree in &trees {
+                scope.spawn(move || tree.save(path).unwrap());
+            }
+        });
+        assert!(trees.contains(&BTree::<u32>::load(path).unwrap()));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn save_equal_keys() {
+        let path = temp_path("save_equal_keys");
+        let mut tree = BTree::new(4);
+        tree.set_duplicate_policy(DuplicatePolicy::Allow);
+        tree.insert_many([1u32, 2, 2, 3]);
+        assert_eq!(
+            tree.save(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(fs::metadata(&path).is_err());
+    }
+
+    #[test]
+    fn save_and_apply_deltas() {
+        let base = temp_path("deltas_base");
+        let deltas = [temp_path("deltas_0"), temp_path("deltas_1")];
+        let mut tracked = TrackedBTree::new(BTree::from_sorted_iter(0..10_000u32, 16));
+        tracked.save(&base).unwrap();
+        for (round, delta) in deltas.iter().enumerate() {
+            let round = round as u32;
+            for key in (round..10_000).step_by(100) {
+                tracked.remove(&key);
+            }
+            // Keys removed and inserted again in the same delta are not changed.
+            assert!(tracked.insert(round));
+            assert!(!tracked.insert(round));
+            assert!(tracked.insert(20_000 + round));
+            assert_eq!(tracked.changes(), 100);
+            tracked.save_delta(delta).unwrap();
+            assert_eq!(tracked.changes(), 0);
+        }
+        assert!(fs::metadata(&deltas[0]).unwrap().len() < fs::metadata(&base).unwrap().len() / 10);
+
+        let mut tree = BTree::<u32>::load(&base).unwrap();
+        for delta in &deltas {
+            tree.apply_delta(delta).unwrap();
+        }
+        assert_eq!(&tree, tracked.tree());
+        // A snapshot is not a delta.
+        assert_eq!(
+            tree.apply_delta(&base).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        for path in deltas.iter().chain(Some(&base)) {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn apply_delta_inserting_present_key() {
+        let path = temp_path("delta_present_key");
+        let mut tracked = TrackedBTree::new(BTree::new(4));
+        tracked.insert(5u32);
+        tracked.save_delta(&path).unwrap();
+        let mut tree = BTree::from_sorted_iter(0..10u32, 4);
+        tree.set_duplicate_policy(DuplicatePolicy::Error);
+        assert_eq!(
+            tree.apply_delta(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(tree.len(), 10);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn apply_delta_of_keys_changed_back() {
+        let path = temp_path("delta_changed_back");
+        let mut tracked = TrackedBTree::new(BTree::from_sorted_iter(0..10u32, 4));
+        assert!(tracked.remove(&5));
+        assert!(tracked.insert(5));
+        assert!(tracked.insert(12));
+        assert!(tracked.remove(&12));
+        assert!(tracked.remove(&7));
+        assert_eq!(tracked.changes(), 1);
+        tracked.save_delta(&path).unwrap();
+        for &policy in &[DuplicatePolicy::Error, DuplicatePolicy::Allow] {
+            let mut tree = BTree::from_sorted_iter(0..10u32, 4);
+            tree.set_duplicate_policy(policy);
+            tree.apply_delta(&path).unwrap();
+            assert!(tree.iter().eq(tracked.tree().iter()));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reject_invalid_snapshots() {
+        let path = temp_path("reject_invalid_snapshots");
+        BTree::from_sorted_iter(0..100u32, 4).save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let load = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            BTree::<u32>::load(&path).unwrap_err().kind()
+        };
+        assert_eq!(
+            load(&bytes[..bytes.len() - 1]),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert_eq!(
+            load(&[&bytes[..], &[0]].concat()),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(load(b"not a snapshot"), io::ErrorKind::InvalidData);
+        // Swap the first two keys.
+        let mut swapped = bytes.clone();
+        let header = bytes.len() - 100 * 4;
+        swapped[header..header + 8].rotate_left(4);
+        assert_eq!(load(&swapped), io::ErrorKind::InvalidData);
+        // An order below `MIN_ORDER`.
+        let mut small_order = bytes;
+        small_order[5] = 2;
+        assert_eq!(load(&small_order), io::ErrorKind::InvalidData);
+
+        let mut encoded = vec![];
+        'x'.encode(&mut encoded).unwrap();
+        assert_eq!(char::decode(&mut &encoded[..]).unwrap(), 'x');
+        assert!(bool::decode(&mut &[2][..]).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
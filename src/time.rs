@@ -0,0 +1,52 @@
+/// Keys which are points in time, so that a tree can look up entries within a time window.
+pub trait Timestamp: Ord + Sized {
+    /// The length of time between two timestamps.
+    type Duration;
+
+    /// Returns the timestamp `duration` before this one, or `None` if it cannot be represented.
+    fn checked_sub(&self, duration: &Self::Duration) -> Option<Self>;
+}
+
+// Integers count ticks of some clock, such as seconds since the epoch.
+macro_rules! impl_timestamp_for_int {
+    ($($int:ty),*) => {
+        $(
+            impl Timestamp for $int {
+                type Duration = $int;
+
+                fn checked_sub(&self, duration: &$int) -> Option<Self> {
+                    <$int>::checked_sub(*self, *duration)
+                }
+            }
+        )*
+    };
+}
+
+impl_timestamp_for_int!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+// Durations are timestamps relative to some start, such as the boot of the process.
+impl Timestamp for core::time::Duration {
+    type Duration = core::time::Duration;
+
+    fn checked_sub(&self, duration: &Self::Duration) -> Option<Self> {
+        core::time::Duration::checked_sub(*self, *duration)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Timestamp for std::time::Instant {
+    type Duration = core::time::Duration;
+
+    fn checked_sub(&self, duration: &Self::Duration) -> Option<Self> {
+        std::time::Instant::checked_sub(self, *duration)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Timestamp for std::time::SystemTime {
+    type Duration = core::time::Duration;
+
+    fn checked_sub(&self, duration: &Self::Duration) -> Option<Self> {
+        std::time::SystemTime::checked_sub(self, *duration)
+    }
+}
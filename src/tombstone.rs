@@ -0,0 +1,229 @@
+use crate::btree::{self, BTree};
+use core::cmp::Ordering;
+use core::mem;
+
+/// A set of keys which removes keys lazily. `remove` only marks a key as deleted, leaving the
+/// nodes as they are, so bursts of removals do not pay for merging nodes. Marked keys take
+/// memory until `compact` drops all of them at once, which can also run automatically once
+/// a threshold of marked keys is reached.
+#[derive(Clone, Debug)]
+pub struct TombstoneBTree<T> {
+    // Live keys and keys marked as deleted.
+    tree: BTree<Slot<T>>,
+    order: usize,
+    // The number of keys marked as deleted.
+    tombstones: usize,
+    // The number of marked keys which triggers a compaction, if any.
+    threshold: Option<usize>,
+}
+
+/// A key in the tree, ordered by the key alone.
+#[derive(Clone, Debug)]
+struct Slot<T> {
+    key: T,
+    is_deleted: bool,
+}
+
+impl<T> TombstoneBTree<T>
+where
+    T: Ord + Clone,
+{
+    /// Create an empty tree, which compacts only when `compact` is called.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is less than `MIN_ORDER`.
+    pub fn new(order: usize) -> Self {
+        Self {
+            tree: BTree::new(order),
+            order,
+            tombstones: 0,
+            threshold: None,
+        }
+    }
+
+    /// Compact automatically once `threshold` keys are marked as deleted,
+    /// or only when `compact` is called if it is `None`.
+    pub fn set_compaction_threshold(&mut self, threshold: Option<usize>) {
+        self.threshold = threshold;
+        self.compact_over_threshold();
+    }
+
+    /// Returns the number of keys not marked as deleted.
+    pub fn len(&self) -> usize {
+        self.tree.len() - self.tombstones
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of keys marked as deleted, which `compact` would drop.
+    pub fn tombstones(&self) -> usize {
+        self.tombstones
+    }
+
+    pub fn contains(&self, key: &T) -> bool {
+        self.tree
+            .get(&Slot::new(key.clone()))
+            .is_some_and(|slot| !slot.is_deleted)
+    }
+
+    /// Insert a key, returning whether it was not present yet.
+    /// A key marked as deleted is brought back without touching the nodes.
+    pub fn insert(&mut self, key: T) -> bool {
+        let probe = Slot::new(key);
+        match self.tree.get_mut(&probe) {
+            Some(slot) if slot.is_deleted => {
+                slot.is_deleted = false;
+                self.tombstones -= 1;
+                true
+            }
+            Some(_) => false,
+            None => {
+                self.tree.insert(probe);
+                true
+            }
+        }
+    }
+
+    /// Mark a key as deleted, returning whether it was present.
+    pub fn remove(&mut self, key: &T) -> bool {
+        match self.tree.get_mut(&Slot::new(key.clone())) {
+            Some(slot) if !slot.is_deleted => slot.is_deleted = true,
+            _ => return false,
+        }
+        self.tombstones += 1;
+        self.compact_over_threshold();
+        true
+    }
+
+    /// Drop all keys marked as deleted, rebuilding the tree from the live keys in linear time.
+    pub fn compact(&mut self) {
+        if self.tombstones == 0 {
+            return;
+        }
+        let tree = mem::replace(&mut self.tree, BTree::new(self.order));
+        let live = tree
+            .into_sorted_vec()
+            .into_iter()
+            .filter(|slot| !slot.is_deleted);
+        self.tree = BTree::from_sorted_iter(live, self.order);
+        self.tombstones = 0;
+    }
+
+    /// Returns an iterator over keys not marked as deleted, in ascending order.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            inner: self.tree.iter(),
+        }
+    }
+
+    fn compact_over_threshold(&mut self) {
+        if self
+            .threshold
+            .is_some_and(|threshold| self.tombstones >= threshold)
+        {
+            self.compact();
+        }
+    }
+}
+
+impl<T> Slot<T> {
+    fn new(key: T) -> Self {
+        Self {
+            key,
+            is_deleted: false,
+        }
+    }
+}
+
+impl<T: Ord> PartialEq for Slot<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<T: Ord> Eq for Slot<T> {}
+
+impl<T: Ord> PartialOrd for Slot<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Ord> Ord for Slot<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+/// An iterator over keys of a `TombstoneBTree` in ascending order.
+pub struct Iter<'a, T> {
+    inner: btree::Iter<'a, Slot<T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .by_ref()
+            .find(|slot| !slot.is_deleted)
+            .map(|slot| &slot.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tombstone::TombstoneBTree;
+    use rand::Rng;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn remove_lazily() {
+        let mut tree = TombstoneBTree::new(4);
+        for key in 0..100 {
+            assert!(tree.insert(key));
+        }
+        for key in 0..50 {
+            assert!(tree.remove(&key));
+        }
+        assert!(!tree.remove(&10));
+        assert_eq!(tree.len(), 50);
+        assert_eq!(tree.tombstones(), 50);
+        assert!(!tree.contains(&10));
+        assert!(tree.contains(&60));
+        // Marked keys come back without a new slot.
+        assert!(tree.insert(10));
+        assert!(!tree.insert(10));
+        assert_eq!(tree.tombstones(), 49);
+        assert!(tree.iter().copied().eq(Some(10).into_iter().chain(50..100)));
+
+        tree.compact();
+        assert_eq!(tree.tombstones(), 0);
+        assert_eq!(tree.tree.len(), 51);
+        assert!(tree.iter().copied().eq(Some(10).into_iter().chain(50..100)));
+    }
+
+    #[test]
+    fn compact_over_threshold() {
+        let mut rng = rand::thread_rng();
+        let mut tree = TombstoneBTree::new(5);
+        tree.set_compaction_threshold(Some(20));
+        let mut expected = BTreeSet::new();
+        for _ in 0..3_000 {
+            let key: u32 = rng.gen_range(0, 200);
+            if rng.gen_bool(0.5) {
+                assert_eq!(tree.insert(key), expected.insert(key));
+            } else {
+                assert_eq!(tree.remove(&key), expected.remove(&key));
+            }
+            assert!(tree.tombstones() < 20);
+            assert_eq!(tree.len(), expected.len());
+        }
+        assert!(tree.iter().eq(expected.iter()));
+        tree.set_compaction_threshold(Some(0));
+        assert_eq!(tree.tree.len(), expected.len());
+    }
+}
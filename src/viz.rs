@@ -0,0 +1,229 @@
+use crate::btree::BTree;
+use crate::node::{Arena, NodeId};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+/// Space around the drawing.
+const MARGIN: usize = 10;
+/// Height of a node.
+const NODE_HEIGHT: usize = 30;
+/// Vertical space between levels, taken by the edges.
+const LEVEL_GAP: usize = 50;
+/// Horizontal space between neighbouring leaves, and around the widest key in its cell.
+const NODE_GAP: usize = 20;
+/// Width of a character of a key, in the monospace font the keys are drawn in.
+const CHAR_WIDTH: usize = 9;
+
+/// A node as it is drawn.
+struct Placement {
+    keys: Vec<String>,
+    // Indices of the placements of the children.
+    children: Vec<usize>,
+    level: usize,
+    x: usize,
+    width: usize,
+    // The left and right ends of the nodes of the subtree.
+    span: (usize, usize),
+}
+
+impl<T, C> BTree<T, C>
+where
+    T: fmt::Display,
+{
+    /// Draw the tree as a self-contained SVG image, one level of nodes below another.
+    /// Each key takes a cell of its node, and an edge leaves a node between the two keys
+    /// its child lies between. Leaves are placed left to right and each parent is centered
+    /// above its children, so no two nodes overlap.
+    pub fn to_svg(&self) -> String {
+        let (nodes, root) = self.root_node();
+        let mut placements = Vec::new();
+        collect(nodes, root, 0, &mut placements);
+        let cell = placements
+            .iter()
+            .flat_map(|p| p.keys.iter())
+            .map(|key| key.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(2)
+            * CHAR_WIDTH
+            + NODE_GAP;
+
+        // Children are collected before their parent, so they are placed first.
+        let mut next_x = MARGIN;
+        for i in 0..placements.len() {
+            let width = placements[i].keys.len().max(1) * cell;
+            let span = if placements[i].children.is_empty() {
+                let left = next_x;
+                next_x += width + NODE_GAP;
+                (left, left + width)
+            } else {
+                let children = &placements[i].children;
+                let first = &placements[children[0]];
+                let last = &placements[children[children.len() - 1]];
+                (first.span.0, last.span.1)
+            };
+            // A parent holds fewer keys than it has children, so it fits above them.
+            let placement = &mut placements[i];
+            placement.x = (span.0 + span.1 - width) / 2;
+            placement.width = width;
+            placement.span = span;
+        }
+
+        let levels = placements.iter().map(|p| p.level).max().unwrap_or(0) + 1;
+        let width = next_x - NODE_GAP + MARGIN;
+        let height = 2 * MARGIN + levels * NODE_HEIGHT + (levels - 1) * LEVEL_GAP;
+        let mut svg = String::new();
+        // Writing to a string never fails.
+        writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             font-family=\"monospace\" font-size=\"15\">",
+            width, height
+        )
+        .unwrap();
+        for placement in &placements {
+            draw_node(&mut svg, placement, &placements, cell).unwrap();
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// Collect the nodes of the subtree rooted at `id`, children before their parent,
+/// and return the index of the placement of `id`.
+fn collect<T: fmt::Display>(
+    nodes: &Arena<T>,
+    id: NodeId,
+    level: usize,
+    placements: &mut Vec<Placement>,
+) -> usize {
+    let node = &nodes[id];
+    let children = node
+        .children
+        .iter()
+        .map(|&child| collect(nodes, child, level + 1, placements))
+        .collect();
+    placements.push(Placement {
+        keys: node.keys.iter().map(|key| key.to_string()).collect(),
+        children,
+        level,
+        x: 0,
+        width: 0,
+        span: (0, 0),
+    });
+    placements.len() - 1
+}
+
+fn draw_node(
+    svg: &mut String,
+    placement: &Placement,
+    placements: &[Placement],
+    cell: usize,
+) -> fmt::Result {
+    let y = MARGIN + placement.level * (NODE_HEIGHT + LEVEL_GAP);
+    writeln!(
+        svg,
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"white\" stroke=\"black\"/>",
+        placement.x, y, placement.width, NODE_HEIGHT
+    )?;
+    for (i, key) in placement.keys.iter().enumerate() {
+        let x = placement.x + i * cell;
+        if i > 0 {
+            draw_line(svg, (x, y), (x, y + NODE_HEIGHT))?;
+        }
+        write!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" dominant-baseline=\"central\">",
+            x + cell / 2,
+            y + NODE_HEIGHT / 2
+        )?;
+        write_escaped(svg, key);
+        svg.push_str("</text>\n");
+    }
+    for (i, &child) in placement.children.iter().enumerate() {
+        let child = &placements[child];
+        draw_line(
+            svg,
+            (placement.x + i * cell, y + NODE_HEIGHT),
+            (child.x + child.width / 2, y + NODE_HEIGHT + LEVEL_GAP),
+        )?;
+    }
+    Ok(())
+}
+
+fn draw_line(svg: &mut String, from: (usize, usize), to: (usize, usize)) -> fmt::Result {
+    writeln!(
+        svg,
+        "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"black\"/>",
+        from.0, from.1, to.0, to.1
+    )
+}
+
+/// Write text escaping the characters XML gives a meaning.
+fn write_escaped(svg: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => svg.push_str("&amp;"),
+            '<' => svg.push_str("&lt;"),
+            '>' => svg.push_str("&gt;"),
+            '"' => svg.push_str("&quot;"),
+            c => svg.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::btree::BTree;
+
+    #[test]
+    fn draw_nodes_and_edges() {
+        let tree = BTree::from_sorted_iter(1..=3, 3);
+        let svg = tree.to_svg();
+        assert!(svg
+            .starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"116\" height=\"130\""));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<rect").count(), 3);
+        // Two edges to the leaves.
+        assert_eq!(svg.matches("<line").count(), 2);
+        assert!(svg.contains("<rect x=\"10\" y=\"90\" width=\"38\""));
+        assert!(svg.contains("<rect x=\"68\" y=\"90\" width=\"38\""));
+        // The root is centered above its leaves, and its edges leave it on both sides of its key.
+        assert!(svg.contains("<rect x=\"39\" y=\"10\" width=\"38\""));
+        assert!(svg.contains("<line x1=\"39\" y1=\"40\" x2=\"29\" y2=\"90\""));
+        assert!(svg.contains("<line x1=\"77\" y1=\"40\" x2=\"87\" y2=\"90\""));
+        assert!(svg.contains(">2</text>"));
+
+        let empty = BTree::<u32>::new(3).to_svg();
+        assert_eq!(empty.matches("<rect").count(), 1);
+        assert!(!empty.contains("<text"));
+
+        let escaped = BTree::from_sorted_iter(vec!["<a&b>"], 3).to_svg();
+        assert!(escaped.contains(">&lt;a&amp;b&gt;</text>"));
+    }
+
+    #[test]
+    fn nodes_do_not_overlap() {
+        let tree = BTree::from_sorted_iter(0..500, 5);
+        let svg = tree.to_svg();
+        let mut levels: Vec<Vec<(usize, usize)>> = vec![];
+        for rect in svg.lines().filter(|line| line.starts_with("<rect")) {
+            let attribute = |name: &str| -> usize {
+                let start = rect.find(&format!(" {}=\"", name)).unwrap() + name.len() + 3;
+                let end = start + rect[start..].find('"').unwrap();
+                rect[start..end].parse().unwrap()
+            };
+            let level = attribute("y") / 80;
+            if levels.len() <= level {
+                levels.resize(level + 1, vec![]);
+            }
+            levels[level].push((attribute("x"), attribute("x") + attribute("width")));
+        }
+        assert!(levels.len() > 2);
+        for level in &mut levels {
+            level.sort();
+            assert!(level.windows(2).all(|pair| pair[0].1 < pair[1].0));
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use crate::btree::{check_order, BTree};
+use crate::error::WireError;
+use crate::snapshot::Codec;
+use std::io::{self, Read};
+
+/// Bytes the wire format starts with.
+const WIRE_MAGIC: &[u8; 4] = b"RBTW";
+
+/// The version of the wire format `BTree::to_wire` writes. `BTree::from_wire` reads this
+/// version and older ones, and rejects newer ones with `WireError::UnsupportedVersion`.
+pub const WIRE_VERSION: u16 = 1;
+
+impl<T> BTree<T>
+where
+    T: Ord + Clone + Codec,
+{
+    /// Encode the order and the keys of the tree in a versioned format, which is the same on
+    /// every platform: integers are little endian, and pointer-sized ones take 64 bits.
+    /// The bytes can be stored as a byte string by any serializer, such as postcard or bincode.
+    /// The format holds unique keys only, so a tree holding equal keys is rejected.
+    pub fn to_wire(&self) -> Result<Vec<u8>, WireError> {
+        let mut bytes = WIRE_MAGIC.to_vec();
+        bytes.extend_from_slice(&WIRE_VERSION.to_le_bytes());
+        // Writing to a vector never fails.
+        self.order().encode(&mut bytes).unwrap();
+        self.len().encode(&mut bytes).unwrap();
+        let mut last = None;
+        for key in self.iter() {
+            if last == Some(key) {
+                return Err(WireError::EqualKeys);
+            }
+            key.encode(&mut bytes).unwrap();
+            last = Some(key);
+        }
+        Ok(bytes)
+    }
+
+    /// Decode a tree written by `to_wire`, on this or any other platform, by this or an older
+    /// version of the format.
+    pub fn from_wire(mut bytes: &[u8]) -> Result<Self, WireError> {
+        let reader = &mut bytes;
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).map_err(invalid_data)?;
+        if &magic != WIRE_MAGIC {
+            return Err(WireError::NotATree);
+        }
+        let version = u16::decode(reader).map_err(invalid_data)?;
+        if version > WIRE_VERSION {
+            return Err(WireError::UnsupportedVersion {
+                found: version,
+                supported: WIRE_VERSION,
+            });
+        }
+        let order = usize::decode(reader).map_err(invalid_data)?;
+        check_order(order).map_err(|error| WireError::InvalidData(error.to_string()))?;
+        let len = u64::decode(reader).map_err(invalid_data)?;
+        let mut keys: Vec<T> = vec![];
+        for _ in 0..len {
+            let key = T::decode(reader).map_err(invalid_data)?;
+            if keys.last().is_some_and(|last| *last >= key) {
+                return Err(WireError::InvalidData(
+                    "keys are not in ascending order".to_string(),
+                ));
+            }
+            keys.push(key);
+        }
+        if !reader.is_empty() {
+            return Err(WireError::InvalidData(
+                "trailing bytes after the last key".to_string(),
+            ));
+        }
+        Ok(Self::from_sorted_iter(keys, order))
+    }
+}
+
+fn invalid_data(error: io::Error) -> WireError {
+    WireError::InvalidData(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::btree::{BTree, DuplicatePolicy};
+    use crate::error::WireError;
+    use crate::wire::WIRE_VERSION;
+
+    #[test]
+    fn encode_the_same_bytes_on_every_platform() {
+        let tree = BTree::from_sorted_iter([1u32, 0x0102_0304], 4);
+        let bytes = tree.to_wire().unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            b'R', b'B', b'T', b'W',
+            1, 0,
+            4, 0, 0, 0, 0, 0, 0, 0,
+            2, 0, 0, 0, 0, 0, 0, 0,
+            1, 0, 0, 0,
+            4, 3, 2, 1,
+        ];
+        assert_eq!(bytes, expected);
+        assert_eq!(BTree::<u32>::from_wire(&bytes).unwrap(), tree);
+
+        let tree = BTree::from_sorted_iter((0..1_000i64).map(|key| key * 7 - 3_000), 5);
+        let decoded = BTree::<i64>::from_wire(&tree.to_wire().unwrap()).unwrap();
+        assert_eq!(decoded, tree);
+        assert_eq!(decoded.order(), 5);
+    }
+
+    #[test]
+    fn reject_incompatible_bytes() {
+        let bytes = BTree::from_sorted_iter(0..10u32, 4).to_wire().unwrap();
+        let decode = |bytes: &[u8]| BTree::<u32>::from_wire(bytes).unwrap_err();
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert_eq!(
+            decode(&newer),
+            WireError::UnsupportedVersion {
+                found: 2,
+                supported: WIRE_VERSION,
+            }
+        );
+        assert_eq!(decode(b"RBTS\x01"), WireError::NotATree);
+        assert!(matches!(
+            decode(&bytes[..bytes.len() - 1]),
+            WireError::InvalidData(_)
+        ));
+        assert!(matches!(
+            decode(&[&bytes[..], &[0]].concat()),
+            WireError::InvalidData(_)
+        ));
+        // Swap the first two keys.
+        let mut swapped = bytes.clone();
+        swapped[22..30].rotate_left(4);
+        assert!(matches!(decode(&swapped), WireError::InvalidData(_)));
+
+        let mut tree = BTree::new(4);
+        tree.set_duplicate_policy(DuplicatePolicy::Allow);
+        tree.insert_many([1u32, 1]);
+        assert_eq!(tree.to_wire(), Err(WireError::EqualKeys));
+    }
+}